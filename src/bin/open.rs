@@ -1,33 +1,34 @@
 #![cfg_attr(test, allow(dead_code))]
 
 use std::{
-    cmp::min,
     env,
     fs::File,
-    io::{self, Read, Write},
+    io::{self, BufReader, Read, Write},
 };
 
 use sxd_document::parser;
 
-fn pretty_error(xml: &str, position: usize) -> String {
-    let s = &xml[position..];
-    let l = s.chars().count();
-    s.chars().take(min(l, 15)).collect()
+fn pretty_error(e: &parser::ReadError) -> String {
+    match *e {
+        parser::ReadError::Parse(ref e) => {
+            let marker: String = e
+                .line_text()
+                .chars()
+                .take(e.column() - e.line_text_column())
+                .map(|c| if c == '\t' { c } else { ' ' })
+                .collect();
+            format!("{}\n{}\n{}^", e, e.line_text(), marker)
+        }
+        parser::ReadError::Io(_) => e.to_string(),
+    }
 }
 
 fn process_input<R>(input: R)
 where
     R: Read,
 {
-    let mut input = input;
-    let mut data = String::new();
-
-    if let Err(x) = input.read_to_string(&mut data) {
-        panic!("Can't read: {}", x);
-    }
-
-    let package = parser::parse(&data).unwrap_or_else(|e| {
-        panic!("Unable to parse: {}", pretty_error(&data, e.location()));
+    let package = parser::parse_reader(BufReader::new(input)).unwrap_or_else(|e| {
+        panic!("Unable to parse: {}", pretty_error(&e));
     });
 
     // let mut out = io::stdout();
@@ -51,3 +52,20 @@ fn main() {
         process_input(file);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn the_marker_is_under_the_error_on_a_long_line() {
+        let xml = format!("<a>{}&bogus;</a>", "x".repeat(300));
+        let e = parser::parse_reader(xml.as_bytes()).unwrap_err();
+
+        let pretty = pretty_error(&e);
+        let lines: Vec<_> = pretty.lines().collect();
+        let marker = lines[2].len() - 1;
+
+        assert_eq!(&lines[1][marker..marker + "bogus;".len()], "bogus;");
+    }
+}
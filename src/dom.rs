@@ -1,9 +1,9 @@
 //! A traditional DOM tree interface for navigating and manipulating
 //! XML documents.
 
-use std::{fmt, hash};
+use std::{error, fmt, hash};
 
-use super::{raw, QName};
+use super::{raw, str::XmlChar, QName};
 
 type SiblingFn<T> = unsafe fn(&raw::Connections, T) -> raw::SiblingIter<'_>;
 
@@ -27,6 +27,7 @@ macro_rules! wrapper(
 
 impl<'d> Document<'d> {
     wrapper!(wrap_root, Root, raw::Root);
+    wrapper!(wrap_document_type, DocumentType, raw::DocumentType);
     wrapper!(wrap_element, Element, raw::Element);
     wrapper!(wrap_attribute, Attribute, raw::Attribute);
     wrapper!(wrap_text, Text, raw::Text);
@@ -41,6 +42,11 @@ impl<'d> Document<'d> {
         }
     }
 
+    /// Copies the string into storage that lives as long as the document
+    pub(crate) fn intern(self, s: &str) -> &'d str {
+        self.storage.intern_str(s)
+    }
+
     fn wrap_parent_of_child(self, node: raw::ParentOfChild) -> ParentOfChild<'d> {
         match node {
             raw::ParentOfChild::Root(n) => ParentOfChild::Root(self.wrap_root(n)),
@@ -51,6 +57,9 @@ impl<'d> Document<'d> {
     fn wrap_child_of_root(self, node: raw::ChildOfRoot) -> ChildOfRoot<'d> {
         match node {
             raw::ChildOfRoot::Element(n) => ChildOfRoot::Element(self.wrap_element(n)),
+            raw::ChildOfRoot::DocumentType(n) => {
+                ChildOfRoot::DocumentType(self.wrap_document_type(n))
+            }
             raw::ChildOfRoot::Comment(n) => ChildOfRoot::Comment(self.wrap_comment(n)),
             raw::ChildOfRoot::ProcessingInstruction(n) => {
                 ChildOfRoot::ProcessingInstruction(self.wrap_pi(n))
@@ -73,6 +82,48 @@ impl<'d> Document<'d> {
         self.wrap_root(self.connections.root())
     }
 
+    /// The version from the XML declaration. Defaults to `1.0`.
+    pub fn xml_version(self) -> &'d str {
+        self.root().node().xml_version()
+    }
+
+    pub fn set_xml_version(self, version: &str) {
+        self.storage
+            .root_set_xml_version(self.connections.root(), version);
+    }
+
+    /// The encoding from the XML declaration, if one was given.
+    pub fn xml_encoding(self) -> Option<&'d str> {
+        self.root().node().xml_encoding()
+    }
+
+    pub fn set_xml_encoding(self, encoding: Option<&str>) {
+        self.storage
+            .root_set_xml_encoding(self.connections.root(), encoding);
+    }
+
+    /// The standalone flag from the XML declaration, if one was given.
+    pub fn xml_standalone(self) -> Option<bool> {
+        self.root().node().xml_standalone()
+    }
+
+    pub fn set_xml_standalone(self, standalone: Option<bool>) {
+        self.storage
+            .root_set_xml_standalone(self.connections.root(), standalone);
+    }
+
+    pub fn create_document_type(
+        self,
+        name: &str,
+        public_id: Option<&str>,
+        system_id: Option<&str>,
+    ) -> DocumentType<'d> {
+        self.wrap_document_type(
+            self.storage
+                .create_document_type(name, public_id, system_id),
+        )
+    }
+
     pub fn create_element<'n, N>(self, name: N) -> Element<'d>
     where
         N: Into<QName<'n>>,
@@ -84,6 +135,13 @@ impl<'d> Document<'d> {
         self.wrap_text(self.storage.create_text(text))
     }
 
+    /// Creates text as `create_text` does, but refuses characters
+    /// that XML does not allow.
+    pub fn try_create_text(self, text: &str) -> Result<Text<'d>, InvalidCharacter> {
+        self.check_chars(text)?;
+        Ok(self.create_text(text))
+    }
+
     pub fn create_comment(self, text: &str) -> Comment<'d> {
         self.wrap_comment(self.storage.create_comment(text))
     }
@@ -96,6 +154,19 @@ impl<'d> Document<'d> {
         self.wrap_pi(self.storage.create_processing_instruction(target, value))
     }
 
+    /// Finds the first character outside of the `Char` production.
+    /// XML 1.1 documents may also hold the restricted characters,
+    /// which are written out as references.
+    fn check_chars(self, text: &str) -> Result<(), InvalidCharacter> {
+        let xml_1_1 = self.xml_version() == "1.1";
+        let allowed = |c: char| c.is_char() || (xml_1_1 && c.is_restricted_char());
+
+        match text.char_indices().find(|&(_, c)| !allowed(c)) {
+            Some((index, character)) => Err(InvalidCharacter { character, index }),
+            None => Ok(()),
+        }
+    }
+
     fn siblings<T>(self, f: SiblingFn<T>, node: T) -> Vec<ChildOfElement<'d>> {
         // This is safe because we don't allow the connection
         // information to leak outside of this method.
@@ -125,6 +196,64 @@ impl<'d> fmt::Debug for Document<'d> {
     }
 }
 
+/// The byte offsets in the source document where a node starts and
+/// ends. Elements, attributes, text, comments and processing
+/// instructions have one when the parser was asked to record them;
+/// nodes produced by an entity reference span the reference.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SourceSpan {
+    start: usize,
+    end: usize,
+}
+
+impl SourceSpan {
+    pub fn new(start: usize, end: usize) -> SourceSpan {
+        SourceSpan { start, end }
+    }
+
+    /// The offset of the first byte of the node
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// The offset just past the last byte of the node
+    pub fn end(&self) -> usize {
+        self.end
+    }
+}
+
+/// A string given to one of the `try_` methods holds a character that
+/// XML does not allow, such as NUL or another control character.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidCharacter {
+    character: char,
+    index: usize,
+}
+
+impl InvalidCharacter {
+    /// The character that is not allowed
+    pub fn character(&self) -> char {
+        self.character
+    }
+
+    /// The byte offset of the character in the string
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl fmt::Display for InvalidCharacter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "character {:?} at byte {} is not allowed in XML",
+            self.character, self.index
+        )
+    }
+}
+
+impl error::Error for InvalidCharacter {}
+
 macro_rules! node(
     ($name:ident, $raw:ty, $doc:expr) => (
         #[doc = $doc]
@@ -225,6 +354,78 @@ impl<'d> fmt::Debug for Root<'d> {
     }
 }
 
+node!(
+    DocumentType,
+    raw::DocumentType,
+    "The `<!DOCTYPE>` declaration of a document. A root has at most one
+    document type, and it is always kept before the top element"
+);
+
+impl<'d> DocumentType<'d> {
+    pub fn name(&self) -> &'d str {
+        self.node().name()
+    }
+    pub fn public_id(&self) -> Option<&'d str> {
+        self.node().public_id()
+    }
+    pub fn system_id(&self) -> Option<&'d str> {
+        self.node().system_id()
+    }
+    /// The unparsed declarations between the `[` and `]`
+    pub fn internal_subset(&self) -> Option<&'d str> {
+        self.node().internal_subset()
+    }
+
+    pub fn set_name(&self, name: &str) {
+        self.document
+            .storage
+            .document_type_set_name(self.node, name);
+    }
+
+    pub fn set_public_id(&self, public_id: Option<&str>) {
+        self.document
+            .storage
+            .document_type_set_public_id(self.node, public_id);
+    }
+
+    pub fn set_system_id(&self, system_id: Option<&str>) {
+        self.document
+            .storage
+            .document_type_set_system_id(self.node, system_id);
+    }
+
+    pub fn set_internal_subset(&self, internal_subset: Option<&str>) {
+        self.document
+            .storage
+            .document_type_set_internal_subset(self.node, internal_subset);
+    }
+
+    pub fn parent(&self) -> Option<Root<'d>> {
+        self.document
+            .connections
+            .document_type_parent(self.node)
+            .map(|n| self.document.wrap_root(n))
+    }
+
+    pub fn remove_from_parent(&self) {
+        self.document
+            .connections
+            .remove_document_type_from_parent(self.node);
+    }
+}
+
+impl<'d> fmt::Debug for DocumentType<'d> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DocumentType {{ name: {:?}, public_id: {:?}, system_id: {:?} }}",
+            self.name(),
+            self.public_id(),
+            self.system_id()
+        )
+    }
+}
+
 /// A mapping from a prefix to a URI
 pub struct Namespace<'d> {
     prefix: &'d str,
@@ -276,7 +477,9 @@ impl<'d> Element<'d> {
     }
 
     /// Map a prefix to a namespace URI. Any existing prefix on this
-    /// element will be replaced.
+    /// element will be replaced. Mapping a prefix to an empty URI
+    /// undeclares it for this element and its descendants, as XML 1.1
+    /// allows.
     pub fn register_prefix(&self, prefix: &str, namespace_uri: &str) {
         self.document
             .storage
@@ -325,6 +528,19 @@ impl<'d> Element<'d> {
             .element_set_preferred_prefix(self.node, prefix);
     }
 
+    /// Where the element was read from in the source document, from
+    /// the start of its start tag to the end of its end tag, if the
+    /// parser was asked to record it
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        self.node().source_span()
+    }
+
+    pub fn set_source_span(&self, span: Option<SourceSpan>) {
+        self.document
+            .storage
+            .element_set_source_span(self.node, span)
+    }
+
     pub fn parent(&self) -> Option<ParentOfChild<'d>> {
         self.document
             .connections
@@ -436,6 +652,20 @@ impl<'d> Element<'d> {
         self.document.wrap_attribute(attr)
     }
 
+    /// Sets an attribute as `set_attribute_value` does, but refuses
+    /// characters that XML does not allow.
+    pub fn try_set_attribute_value<'n, N>(
+        &self,
+        name: N,
+        value: &str,
+    ) -> Result<Attribute<'d>, InvalidCharacter>
+    where
+        N: Into<QName<'n>>,
+    {
+        self.document.check_chars(value)?;
+        Ok(self.set_attribute_value(name, value))
+    }
+
     pub fn attribute_value<'n, N>(&self, name: N) -> Option<&'d str>
     where
         N: Into<QName<'n>>,
@@ -462,6 +692,101 @@ impl<'d> Element<'d> {
         self.append_child(text);
         text
     }
+
+    /// Replaces the children as `set_text` does, but refuses
+    /// characters that XML does not allow.
+    pub fn try_set_text(&self, text: &str) -> Result<Text<'_>, InvalidCharacter> {
+        self.document.check_chars(text)?;
+        Ok(self.set_text(text))
+    }
+
+    /// Removes the text children that are only whitespace, from this
+    /// element and its descendants. Text is kept where
+    /// `xml:space="preserve"` is set, on this element or an ancestor,
+    /// and CDATA sections are always kept.
+    pub fn remove_whitespace_text(&self) {
+        self.strip_whitespace_text(false);
+    }
+
+    /// Removes whitespace-only text as `remove_whitespace_text` does.
+    /// With `trim`, the whitespace at the start and end of the
+    /// remaining runs of text is removed as well.
+    pub(crate) fn strip_whitespace_text(&self, trim: bool) {
+        self.strip_whitespace_text_in(self.parent_preserves_space(), trim);
+    }
+
+    /// Whether the nearest `xml:space` attribute, on this element or
+    /// an ancestor, asks for whitespace to be preserved
+    fn preserves_space(&self) -> bool {
+        match self.attribute_value((crate::XML_NS_URI, "space")) {
+            Some("preserve") => true,
+            Some("default") => false,
+            _ => self.parent_preserves_space(),
+        }
+    }
+
+    fn parent_preserves_space(&self) -> bool {
+        self.parent()
+            .and_then(|p| p.element())
+            .filter(|p| p.preserves_space())
+            .is_some()
+    }
+
+    fn strip_whitespace_text_in(&self, preserve: bool, trim: bool) {
+        let preserve = match self.attribute_value((crate::XML_NS_URI, "space")) {
+            Some("preserve") => true,
+            Some("default") => false,
+            _ => preserve,
+        };
+
+        let children = self.children();
+        if !preserve {
+            for run in children.split(|c| c.text().is_none()) {
+                let run: Vec<_> = run.iter().filter_map(|c| c.text()).collect();
+                strip_whitespace_run(&run, trim);
+            }
+        }
+
+        for child in children.iter().filter_map(|c| c.element()) {
+            child.strip_whitespace_text_in(preserve, trim);
+        }
+    }
+}
+
+/// Removes a run of adjacent text nodes if it is only whitespace, or
+/// else trims the whitespace from its ends when asked to
+fn strip_whitespace_run(run: &[Text<'_>], trim: bool) {
+    let is_space = |c: char| c.is_space_char();
+    let is_blank = |t: &Text<'_>| !t.is_cdata() && t.text().chars().all(is_space);
+
+    if run.iter().all(is_blank) {
+        for text in run {
+            text.remove_from_parent();
+        }
+        return;
+    }
+
+    if !trim {
+        return;
+    }
+
+    for text in run.iter().take_while(|t| !t.is_cdata()) {
+        let trimmed = text.text().trim_start_matches(is_space);
+        if !trimmed.is_empty() {
+            text.set_text(trimmed);
+            break;
+        }
+        text.remove_from_parent();
+    }
+
+    for text in run.iter().rev().take_while(|t| !t.is_cdata()) {
+        let trimmed = text.text().trim_end_matches(is_space);
+        if !trimmed.is_empty() {
+            text.set_text(trimmed);
+            break;
+        }
+        text.remove_from_parent();
+    }
 }
 
 impl<'d> fmt::Debug for Element<'d> {
@@ -494,6 +819,30 @@ impl<'d> Attribute<'d> {
             .attribute_set_preferred_prefix(self.node, prefix);
     }
 
+    /// Whether the attribute was given in the document, as opposed to
+    /// being added from a default in the document type definition.
+    /// Attributes are specified unless the parser was asked to apply
+    /// defaults.
+    pub fn is_specified(&self) -> bool {
+        self.node().is_specified()
+    }
+
+    pub fn set_specified(&self, specified: bool) {
+        self.document
+            .storage
+            .attribute_set_specified(self.node, specified);
+    }
+
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        self.node().source_span()
+    }
+
+    pub fn set_source_span(&self, span: Option<SourceSpan>) {
+        self.document
+            .storage
+            .attribute_set_source_span(self.node, span)
+    }
+
     pub fn parent(&self) -> Option<Element<'d>> {
         self.document
             .connections
@@ -530,6 +879,33 @@ impl<'d> Text<'d> {
         self.document.storage.text_set_text(self.node, text)
     }
 
+    /// Changes the text as `set_text` does, but refuses characters
+    /// that XML does not allow.
+    pub fn try_set_text(&self, text: &str) -> Result<(), InvalidCharacter> {
+        self.document.check_chars(text)?;
+        self.set_text(text);
+        Ok(())
+    }
+
+    /// Whether the text is written out as a CDATA section. Text is
+    /// only marked as CDATA by the parser when it was asked to
+    /// preserve CDATA sections.
+    pub fn is_cdata(&self) -> bool {
+        self.node().is_cdata()
+    }
+
+    pub fn set_cdata(&self, cdata: bool) {
+        self.document.storage.text_set_cdata(self.node, cdata)
+    }
+
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        self.node().source_span()
+    }
+
+    pub fn set_source_span(&self, span: Option<SourceSpan>) {
+        self.document.storage.text_set_source_span(self.node, span)
+    }
+
     pub fn parent(&self) -> Option<Element<'d>> {
         self.document
             .connections
@@ -569,6 +945,24 @@ impl<'d> Comment<'d> {
         self.document.storage.comment_set_text(self.node, new_text)
     }
 
+    /// Changes the text as `set_text` does, but refuses characters
+    /// that XML does not allow.
+    pub fn try_set_text(&self, new_text: &str) -> Result<(), InvalidCharacter> {
+        self.document.check_chars(new_text)?;
+        self.set_text(new_text);
+        Ok(())
+    }
+
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        self.node().source_span()
+    }
+
+    pub fn set_source_span(&self, span: Option<SourceSpan>) {
+        self.document
+            .storage
+            .comment_set_source_span(self.node, span)
+    }
+
     pub fn parent(&self) -> Option<ParentOfChild<'d>> {
         self.document
             .connections
@@ -625,6 +1019,24 @@ impl<'d> ProcessingInstruction<'d> {
             .processing_instruction_set_value(self.node, new_value);
     }
 
+    /// Changes the value as `set_value` does, but refuses characters
+    /// that XML does not allow.
+    pub fn try_set_value(&self, new_value: Option<&str>) -> Result<(), InvalidCharacter> {
+        self.document.check_chars(new_value.unwrap_or(""))?;
+        self.set_value(new_value);
+        Ok(())
+    }
+
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        self.node().source_span()
+    }
+
+    pub fn set_source_span(&self, span: Option<SourceSpan>) {
+        self.document
+            .storage
+            .processing_instruction_set_source_span(self.node, span)
+    }
+
     pub fn parent(&self) -> Option<ParentOfChild<'d>> {
         self.document
             .connections
@@ -679,12 +1091,14 @@ macro_rules! unpack(
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ChildOfRoot<'d> {
     Element(Element<'d>),
+    DocumentType(DocumentType<'d>),
     Comment(Comment<'d>),
     ProcessingInstruction(ProcessingInstruction<'d>),
 }
 
 impl<'d> ChildOfRoot<'d> {
     unpack!(ChildOfRoot, element, Element, Element);
+    unpack!(ChildOfRoot, document_type, DocumentType, DocumentType);
     unpack!(ChildOfRoot, comment, Comment, Comment);
     unpack!(
         ChildOfRoot,
@@ -693,9 +1107,21 @@ impl<'d> ChildOfRoot<'d> {
         ProcessingInstruction
     );
 
+    /// The same node as a child of an element, unless it is a
+    /// document type, which can only be a child of the root.
+    pub fn into_child_of_element(self) -> Option<ChildOfElement<'d>> {
+        match self {
+            ChildOfRoot::Element(n) => Some(ChildOfElement::Element(n)),
+            ChildOfRoot::DocumentType(_) => None,
+            ChildOfRoot::Comment(n) => Some(ChildOfElement::Comment(n)),
+            ChildOfRoot::ProcessingInstruction(n) => Some(ChildOfElement::ProcessingInstruction(n)),
+        }
+    }
+
     fn as_raw(&self) -> raw::ChildOfRoot {
         match *self {
             ChildOfRoot::Element(n) => raw::ChildOfRoot::Element(n.node),
+            ChildOfRoot::DocumentType(n) => raw::ChildOfRoot::DocumentType(n.node),
             ChildOfRoot::Comment(n) => raw::ChildOfRoot::Comment(n.node),
             ChildOfRoot::ProcessingInstruction(n) => {
                 raw::ChildOfRoot::ProcessingInstruction(n.node)
@@ -769,6 +1195,7 @@ macro_rules! conversion_trait(
 conversion_trait!(
     ChildOfRoot, {
         Element               => ChildOfRoot::Element,
+        DocumentType          => ChildOfRoot::DocumentType,
         Comment               => ChildOfRoot::Comment,
         ProcessingInstruction => ChildOfRoot::ProcessingInstruction
     }
@@ -783,21 +1210,11 @@ conversion_trait!(
     }
 );
 
-impl<'d> From<ChildOfRoot<'d>> for ChildOfElement<'d> {
-    fn from(v: ChildOfRoot<'d>) -> ChildOfElement<'d> {
-        match v {
-            ChildOfRoot::Element(n) => ChildOfElement::Element(n),
-            ChildOfRoot::Comment(n) => ChildOfElement::Comment(n),
-            ChildOfRoot::ProcessingInstruction(n) => ChildOfElement::ProcessingInstruction(n),
-        }
-    }
-}
-
 #[cfg(test)]
 mod test {
     use super::{
         super::{Package, QName},
-        ChildOfElement, ChildOfRoot, ParentOfChild,
+        ChildOfElement, ChildOfRoot, ParentOfChild, SourceSpan,
     };
 
     macro_rules! assert_qname_eq(
@@ -1200,6 +1617,54 @@ mod test {
         assert_eq!("uri2", ns.uri());
     }
 
+    #[test]
+    fn checked_setters_refuse_characters_outside_of_xml() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let element = doc.create_element("element");
+
+        let err = element.try_set_text("ok\u{0}").unwrap_err();
+        assert_eq!(err.character(), '\u{0}');
+        assert_eq!(err.index(), 2);
+        assert!(element.children().is_empty());
+
+        assert!(element.try_set_attribute_value("a", "\u{1}").is_err());
+        assert_eq!(element.attribute_value("a"), None);
+
+        assert!(doc.try_create_text("\u{FFFE}").is_err());
+        assert_eq!(element.try_set_text("fine").unwrap().text(), "fine");
+    }
+
+    #[test]
+    fn checked_setters_allow_restricted_characters_in_xml_1_1() {
+        let package = Package::new();
+        let doc = package.as_document();
+        doc.set_xml_version("1.1");
+
+        let comment = doc.create_comment("");
+        assert!(comment.try_set_text("\u{1}").is_ok());
+        assert!(comment.try_set_text("\u{0}").is_err());
+        assert_eq!(comment.text(), "\u{1}");
+    }
+
+    #[test]
+    fn elements_can_undeclare_a_prefix() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let parent = doc.create_element("parent");
+        parent.register_prefix("prefix", "uri");
+
+        let child = doc.create_element("child");
+        child.register_prefix("prefix", "");
+
+        parent.append_child(child);
+
+        assert_eq!(child.namespace_uri_for_prefix("prefix"), None);
+        assert_eq!(child.namespaces_in_scope().len(), 1);
+    }
+
     #[test]
     fn attributes_belong_to_a_document() {
         let package = Package::new();
@@ -1247,6 +1712,22 @@ mod test {
         assert_eq!(Some("galaxy"), element.attribute_value("hello"));
     }
 
+    #[test]
+    fn attributes_are_specified_until_marked_as_defaulted() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let element = doc.create_element("element");
+        let attr = element.set_attribute_value("hello", "world");
+        assert!(attr.is_specified());
+
+        attr.set_specified(false);
+        assert!(!attr.is_specified());
+
+        let attr = element.set_attribute_value("hello", "galaxy");
+        assert!(attr.is_specified());
+    }
+
     #[test]
     fn attributes_can_be_removed() {
         let package = Package::new();
@@ -1335,6 +1816,71 @@ mod test {
         assert_eq!(children[0].text().unwrap().text(), quote);
     }
 
+    #[test]
+    fn elements_can_remove_whitespace_text() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let list = doc.create_element("list");
+        let item = doc.create_element("item");
+        list.append_child(doc.create_text("\n  "));
+        list.append_child(item);
+        list.append_child(doc.create_text("\n"));
+        item.append_child(doc.create_text(" "));
+        item.append_child(doc.create_text("one "));
+
+        list.remove_whitespace_text();
+
+        assert_eq!(list.children(), vec![ChildOfElement::Element(item)]);
+        let texts: Vec<_> = item
+            .children()
+            .iter()
+            .filter_map(|c| c.text())
+            .map(|t| t.text())
+            .collect();
+        assert_eq!(texts, [" ", "one "]);
+    }
+
+    #[test]
+    fn removing_whitespace_text_respects_xml_space() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let space = (crate::XML_NS_URI, "space");
+
+        let outer = doc.create_element("outer");
+        let pre = doc.create_element("pre");
+        let plain = doc.create_element("plain");
+        outer.set_attribute_value(space, "preserve");
+        outer.append_child(doc.create_text(" "));
+        outer.append_child(pre);
+        pre.append_child(doc.create_text(" "));
+        pre.append_child(plain);
+        plain.set_attribute_value(space, "default");
+        plain.append_child(doc.create_text(" "));
+
+        pre.remove_whitespace_text();
+        assert_eq!(pre.children().len(), 2);
+        assert!(plain.children().is_empty());
+
+        outer.remove_whitespace_text();
+        assert_eq!(outer.children().len(), 2);
+    }
+
+    #[test]
+    fn removing_whitespace_text_keeps_cdata() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let element = doc.create_element("element");
+        let text = doc.create_text(" ");
+        text.set_cdata(true);
+        element.append_child(text);
+
+        element.remove_whitespace_text();
+
+        assert_eq!(element.children(), vec![ChildOfElement::Text(text)]);
+    }
+
     #[test]
     fn text_knows_its_parent() {
         let package = Package::new();
@@ -1405,6 +1951,30 @@ mod test {
         assert_eq!(text.text(), "Made glorious summer by this sun of York");
     }
 
+    #[test]
+    fn text_can_be_marked_as_cdata() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let text = doc.create_text("<script>");
+        assert!(!text.is_cdata());
+
+        text.set_cdata(true);
+        assert!(text.is_cdata());
+    }
+
+    #[test]
+    fn elements_have_a_source_span() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let element = doc.create_element("alpha");
+        assert_eq!(element.source_span(), None);
+
+        element.set_source_span(Some(SourceSpan::new(3, 10)));
+        assert_eq!(element.source_span(), Some(SourceSpan::new(3, 10)));
+    }
+
     #[test]
     fn comment_belongs_to_a_document() {
         let package = Package::new();
@@ -1597,6 +2167,136 @@ mod test {
         assert_eq!(pi.value(), Some("full-screen"));
     }
 
+    #[test]
+    fn document_type_is_kept_before_the_top_element() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let element = doc.create_element("html");
+        let doctype = doc.create_document_type("html", None, Some("html.dtd"));
+        doc.root().append_child(element);
+        doc.root().append_child(doctype);
+
+        let children = doc.root().children();
+        assert_eq!(children[0], ChildOfRoot::DocumentType(doctype));
+        assert_eq!(children[1], ChildOfRoot::Element(element));
+        assert_eq!(doctype.parent(), Some(doc.root()));
+    }
+
+    #[test]
+    fn root_has_maximum_of_one_document_type_child() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let alpha = doc.create_document_type("alpha", None, None);
+        let beta = doc.create_document_type("beta", None, None);
+        doc.root().append_child(alpha);
+        doc.root().append_child(beta);
+
+        assert_eq!(doc.root().children(), vec![ChildOfRoot::DocumentType(beta)]);
+        assert_eq!(alpha.parent(), None);
+    }
+
+    #[test]
+    fn document_type_can_be_removed_from_parent() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let doctype = doc.create_document_type("html", None, None);
+        doc.root().append_child(doctype);
+        doctype.remove_from_parent();
+
+        assert!(doc.root().children().is_empty());
+        assert_eq!(doctype.parent(), None);
+    }
+
+    #[test]
+    fn only_children_other_than_a_document_type_can_move_to_an_element() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let doctype = doc.create_document_type("html", None, None);
+        let comment = doc.create_comment("c");
+        doc.root().append_child(doctype);
+        doc.root().append_child(comment);
+        let element = doc.create_element("element");
+
+        let children: Vec<_> = doc
+            .root()
+            .children()
+            .into_iter()
+            .filter_map(ChildOfRoot::into_child_of_element)
+            .collect();
+        element.append_children(children);
+
+        assert_eq!(element.children(), vec![ChildOfElement::Comment(comment)]);
+        assert_eq!(doctype.parent(), Some(doc.root()));
+    }
+
+    #[test]
+    fn document_type_is_not_a_sibling_of_elements() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let doctype = doc.create_document_type("html", None, None);
+        let comment = doc.create_comment("hi");
+        let element = doc.create_element("html");
+        doc.root().append_children(vec![
+            ChildOfRoot::DocumentType(doctype),
+            ChildOfRoot::Comment(comment),
+            ChildOfRoot::Element(element),
+        ]);
+
+        assert_eq!(
+            element.preceding_siblings(),
+            vec![ChildOfElement::Comment(comment)]
+        );
+    }
+
+    #[test]
+    fn document_type_can_be_changed() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        let doctype = doc.create_document_type("html", None, None);
+        doctype.set_name("book");
+        doctype.set_public_id(Some("-//OASIS//DTD DocBook XML V4.5//EN"));
+        doctype.set_system_id(Some("docbookx.dtd"));
+        doctype.set_internal_subset(Some("<!ENTITY a 'b'>"));
+
+        assert_eq!(doctype.name(), "book");
+        assert_eq!(
+            doctype.public_id(),
+            Some("-//OASIS//DTD DocBook XML V4.5//EN")
+        );
+        assert_eq!(doctype.system_id(), Some("docbookx.dtd"));
+        assert_eq!(doctype.internal_subset(), Some("<!ENTITY a 'b'>"));
+    }
+
+    #[test]
+    fn xml_declaration_has_defaults() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        assert_eq!(doc.xml_version(), "1.0");
+        assert_eq!(doc.xml_encoding(), None);
+        assert_eq!(doc.xml_standalone(), None);
+    }
+
+    #[test]
+    fn xml_declaration_can_be_changed() {
+        let package = Package::new();
+        let doc = package.as_document();
+
+        doc.set_xml_version("1.1");
+        doc.set_xml_encoding(Some("UTF-16"));
+        doc.set_xml_standalone(Some(true));
+
+        assert_eq!(doc.xml_version(), "1.1");
+        assert_eq!(doc.xml_encoding(), Some("UTF-16"));
+        assert_eq!(doc.xml_standalone(), Some(true));
+    }
+
     #[test]
     fn can_return_a_populated_package() {
         fn populate() -> Package {
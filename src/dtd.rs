@@ -0,0 +1,990 @@
+//! Declarations read from the internal subset of a document type
+//! declaration, and validation of documents against them.
+//!
+//! ### Example
+//!
+//! ```
+//! use sxd_document::dtd::{Dtd, Entity};
+//!
+//! let dtd = Dtd::parse(r#"<!ENTITY copy "&#169;">"#).expect("Failed to parse");
+//! assert_eq!(dtd.entity("copy"), Some(&Entity::Internal("\u{A9}".into())));
+//! ```
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    error, fmt, iter,
+};
+
+use super::{
+    dom, parser,
+    str::{XmlChar, XmlStr},
+};
+
+/// An entity declared with `<!ENTITY>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entity {
+    /// The replacement text is given in the declaration. Character
+    /// references have already been replaced, while references to
+    /// other entities are kept to be expanded where the entity is used.
+    Internal(String),
+    /// The replacement text is stored elsewhere. An entity with a
+    /// notation is unparsed and cannot be referenced from content.
+    External {
+        public_id: Option<String>,
+        system_id: String,
+        notation: Option<String>,
+    },
+}
+
+/// The kind of value an attribute declared with `<!ATTLIST>` holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeType {
+    CData,
+    Id,
+    IdRef,
+    IdRefs,
+    Entity,
+    Entities,
+    NmToken,
+    NmTokens,
+    /// One of the listed notation names
+    Notation(Vec<String>),
+    /// One of the listed name tokens
+    Enumeration(Vec<String>),
+}
+
+/// What happens when an attribute is left out of a start tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DefaultValue {
+    /// The attribute must be given
+    Required,
+    /// The attribute may be left out and then has no value
+    Implied,
+    /// The attribute always has this value
+    Fixed(String),
+    /// The attribute has this value unless it is given
+    Default(String),
+}
+
+/// An attribute declared with `<!ATTLIST>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeDeclaration {
+    name: String,
+    attribute_type: AttributeType,
+    default_value: DefaultValue,
+}
+
+impl AttributeDeclaration {
+    pub fn new(
+        name: &str,
+        attribute_type: AttributeType,
+        default_value: DefaultValue,
+    ) -> AttributeDeclaration {
+        AttributeDeclaration {
+            name: name.to_owned(),
+            attribute_type,
+            default_value,
+        }
+    }
+
+    /// The qualified name of the attribute, as written in the
+    /// declaration
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn attribute_type(&self) -> &AttributeType {
+        &self.attribute_type
+    }
+
+    /// References in a default value have already been replaced
+    pub fn default_value(&self) -> &DefaultValue {
+        &self.default_value
+    }
+}
+
+/// The content allowed in an element declared with `<!ELEMENT>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentSpec {
+    /// The element must not have any content
+    Empty,
+    /// Any content, as long as the child elements are declared
+    Any,
+    /// Character data mixed with any number of the listed elements,
+    /// in any order
+    Mixed(Vec<String>),
+    /// Only child elements, matching the content model. Whitespace
+    /// may appear between them.
+    Children(ContentParticle),
+}
+
+/// How many times a content particle may appear
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Repetition {
+    Once,
+    /// `?`
+    Optional,
+    /// `*`
+    ZeroOrMore,
+    /// `+`
+    OneOrMore,
+}
+
+/// A part of a content model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Particle {
+    /// An element with this qualified name
+    Name(String),
+    /// Each of the particles, in order
+    Sequence(Vec<ContentParticle>),
+    /// One of the particles
+    Choice(Vec<ContentParticle>),
+}
+
+impl Particle {
+    /// Every position in `names` where a match that started at one of
+    /// `starts` could end
+    fn ends(&self, names: &[String], starts: &BTreeSet<usize>) -> BTreeSet<usize> {
+        match *self {
+            Particle::Name(ref name) => starts
+                .iter()
+                .filter(|&&i| names.get(i) == Some(name))
+                .map(|&i| i + 1)
+                .collect(),
+            Particle::Sequence(ref particles) => particles
+                .iter()
+                .fold(starts.clone(), |starts, p| p.ends(names, &starts)),
+            Particle::Choice(ref particles) => particles
+                .iter()
+                .flat_map(|p| p.ends(names, starts))
+                .collect(),
+        }
+    }
+}
+
+/// A particle together with how many times it may appear
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentParticle {
+    particle: Particle,
+    repetition: Repetition,
+}
+
+impl ContentParticle {
+    pub fn new(particle: Particle, repetition: Repetition) -> ContentParticle {
+        ContentParticle {
+            particle,
+            repetition,
+        }
+    }
+
+    pub fn particle(&self) -> &Particle {
+        &self.particle
+    }
+
+    pub fn repetition(&self) -> Repetition {
+        self.repetition
+    }
+
+    /// Whether the child element names match this content model
+    fn matches(&self, names: &[String]) -> bool {
+        let starts = iter::once(0).collect();
+        self.ends(names, &starts).contains(&names.len())
+    }
+
+    fn ends(&self, names: &[String], starts: &BTreeSet<usize>) -> BTreeSet<usize> {
+        let once = self.particle.ends(names, starts);
+
+        match self.repetition {
+            Repetition::Once => once,
+            Repetition::Optional => once.union(starts).cloned().collect(),
+            Repetition::ZeroOrMore => self.repeat(names, starts.clone(), once),
+            Repetition::OneOrMore => self.repeat(names, BTreeSet::new(), once),
+        }
+    }
+
+    /// Keeps matching from the ends that have not been seen before,
+    /// until there are none left
+    fn repeat(
+        &self,
+        names: &[String],
+        mut ends: BTreeSet<usize>,
+        mut found: BTreeSet<usize>,
+    ) -> BTreeSet<usize> {
+        loop {
+            let new: BTreeSet<_> = found.difference(&ends).cloned().collect();
+            if new.is_empty() {
+                return ends;
+            }
+            ends.extend(&new);
+            found = self.particle.ends(names, &new);
+        }
+    }
+}
+
+/// The declarations of a document type definition
+#[derive(Debug, Clone, Default)]
+pub struct Dtd {
+    entities: HashMap<String, Entity>,
+    parameter_entities: HashMap<String, Entity>,
+    elements: HashMap<String, ContentSpec>,
+    attributes: HashMap<String, Vec<AttributeDeclaration>>,
+}
+
+impl Dtd {
+    pub fn new() -> Dtd {
+        Default::default()
+    }
+
+    /// Parses the declarations of an internal subset, the text between
+    /// the `[` and `]` of a `<!DOCTYPE>`.
+    pub fn parse(internal_subset: &str) -> Result<Dtd, parser::Error> {
+        parser::parse_internal_subset(internal_subset)
+    }
+
+    /// The general entity with the given name, referenced as `&name;`
+    pub fn entity(&self, name: &str) -> Option<&Entity> {
+        self.entities.get(name)
+    }
+
+    /// The parameter entity with the given name, referenced as `%name;`
+    pub fn parameter_entity(&self, name: &str) -> Option<&Entity> {
+        self.parameter_entities.get(name)
+    }
+
+    /// The content allowed in the element with the given qualified
+    /// name
+    pub fn element(&self, name: &str) -> Option<&ContentSpec> {
+        self.elements.get(name)
+    }
+
+    /// The attributes declared for the element with the given qualified
+    /// name
+    pub fn attributes(&self, element: &str) -> &[AttributeDeclaration] {
+        self.attributes.get(element).map_or(&[], |a| &a[..])
+    }
+
+    /// When an entity is declared more than once, the first
+    /// declaration is used.
+    pub(crate) fn add_entity(&mut self, name: &str, entity: Entity) {
+        self.entities.entry(name.to_owned()).or_insert(entity);
+    }
+
+    pub(crate) fn add_parameter_entity(&mut self, name: &str, entity: Entity) {
+        self.parameter_entities
+            .entry(name.to_owned())
+            .or_insert(entity);
+    }
+
+    pub(crate) fn add_element(&mut self, name: &str, content: ContentSpec) {
+        self.elements.entry(name.to_owned()).or_insert(content);
+    }
+
+    /// As with entities, the first declaration of an attribute is used.
+    pub(crate) fn add_attribute(&mut self, element: &str, attribute: AttributeDeclaration) {
+        let attributes = self.attributes.entry(element.to_owned()).or_default();
+
+        if attributes.iter().all(|a| a.name != attribute.name) {
+            attributes.push(attribute);
+        }
+    }
+}
+
+/// The ways a document can fail to match its document type definition
+///
+/// More kinds may be added in the future, so matches on this type
+/// need a wildcard arm.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum DiagnosticKind {
+    /// The root element does not have the name given in the
+    /// `<!DOCTYPE>`
+    RootElementMismatch,
+    UndeclaredElement,
+    /// The children of the element do not match its declared content
+    InvalidContent,
+    UndeclaredAttribute(String),
+    MissingRequiredAttribute(String),
+    /// The value does not match the declared type, enumeration or
+    /// `#FIXED` value of the attribute
+    InvalidAttributeValue(String),
+    /// More than one attribute of type `ID` has this value
+    DuplicateId(String),
+    /// An attribute of type `IDREF` or `IDREFS` refers to an `ID` that
+    /// is not in the document
+    UnknownId(String),
+
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::DiagnosticKind::*;
+
+        match *self {
+            RootElementMismatch => f.write_str("root element does not match the document type"),
+            UndeclaredElement => f.write_str("element is not declared"),
+            InvalidContent => f.write_str("content does not match the declaration"),
+            UndeclaredAttribute(ref name) => write!(f, "attribute '{}' is not declared", name),
+            MissingRequiredAttribute(ref name) => {
+                write!(f, "required attribute '{}' is missing", name)
+            }
+            InvalidAttributeValue(ref name) => {
+                write!(f, "attribute '{}' has an invalid value", name)
+            }
+            DuplicateId(ref id) => write!(f, "ID '{}' is used more than once", id),
+            UnknownId(ref id) => write!(f, "ID '{}' does not exist", id),
+            __Nonexhaustive => unreachable!("not a validation error"),
+        }
+    }
+}
+
+/// A way in which an element does not match the document type
+/// definition
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic<'d> {
+    element: dom::Element<'d>,
+    kind: DiagnosticKind,
+}
+
+impl<'d> Diagnostic<'d> {
+    pub fn element(&self) -> dom::Element<'d> {
+        self.element
+    }
+
+    pub fn kind(&self) -> &DiagnosticKind {
+        &self.kind
+    }
+}
+
+impl<'d> fmt::Display for Diagnostic<'d> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}>: {}", qualified_name(self.element), self.kind)
+    }
+}
+
+impl<'d> error::Error for Diagnostic<'d> {}
+
+/// Checks the document against the element and attribute
+/// declarations of a document type definition. The declarations from
+/// the document's own internal subset can be read with `Dtd::parse`.
+///
+/// Names are compared using the preferred prefix of elements and
+/// attributes, as that is how the parser records the prefix written
+/// in the document. Returns every problem found, in document order,
+/// or an empty list if the document is valid.
+///
+/// ### Example
+///
+/// ```
+/// use sxd_document::{dtd, parser};
+///
+/// let dtd = dtd::Dtd::parse("<!ELEMENT list (item+)> <!ELEMENT item (#PCDATA)>").unwrap();
+/// let package = parser::parse("<list><item>one</item>two</list>").expect("Failed to parse");
+/// let doc = package.as_document();
+///
+/// let diagnostics = dtd::validate(&doc, &dtd);
+/// assert_eq!(diagnostics.len(), 1);
+/// assert_eq!(diagnostics[0].kind(), &dtd::DiagnosticKind::InvalidContent);
+/// ```
+pub fn validate<'d>(doc: &dom::Document<'d>, dtd: &Dtd) -> Vec<Diagnostic<'d>> {
+    let mut validator = Validator {
+        dtd,
+        ids: HashSet::new(),
+        references: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    let children = doc.root().children();
+    let document_type = children.iter().find_map(|c| c.document_type());
+
+    if let Some(element) = children.iter().find_map(|c| c.element()) {
+        if let Some(document_type) = document_type {
+            if document_type.name() != qualified_name(element) {
+                validator.report(element, DiagnosticKind::RootElementMismatch);
+            }
+        }
+
+        validator.validate_element(element);
+    }
+
+    let Validator {
+        ids,
+        references,
+        mut diagnostics,
+        ..
+    } = validator;
+
+    for (element, id) in references {
+        if !ids.contains(id) {
+            let kind = DiagnosticKind::UnknownId(id.into());
+            diagnostics.push(Diagnostic { element, kind });
+        }
+    }
+
+    diagnostics
+}
+
+/// The name of an element as it would be written in the document
+fn qualified_name(element: dom::Element<'_>) -> String {
+    prefixed(element.preferred_prefix(), element.name().local_part())
+}
+
+fn prefixed(prefix: Option<&str>, local_part: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}:{}", prefix, local_part),
+        None => local_part.to_owned(),
+    }
+}
+
+/// The space-separated parts of an attribute value
+fn tokens(value: &str) -> Vec<&str> {
+    value
+        .split(|c: char| c.is_space_char())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+fn is_name(s: &str) -> bool {
+    s.end_of_name() == Some(s.len())
+}
+
+fn is_nmtoken(s: &str) -> bool {
+    s.end_of_nmtoken() == Some(s.len())
+}
+
+struct Validator<'a, 'd> {
+    dtd: &'a Dtd,
+    ids: HashSet<&'d str>,
+    references: Vec<(dom::Element<'d>, &'d str)>,
+    diagnostics: Vec<Diagnostic<'d>>,
+}
+
+impl<'a, 'd> Validator<'a, 'd> {
+    fn report(&mut self, element: dom::Element<'d>, kind: DiagnosticKind) {
+        self.diagnostics.push(Diagnostic { element, kind });
+    }
+
+    fn validate_element(&mut self, element: dom::Element<'d>) {
+        let name = qualified_name(element);
+        let children = element.children();
+
+        match self.dtd.element(&name) {
+            Some(content) => {
+                if !content_is_valid(content, &children) {
+                    self.report(element, DiagnosticKind::InvalidContent);
+                }
+            }
+            None => self.report(element, DiagnosticKind::UndeclaredElement),
+        }
+
+        self.validate_attributes(element, &name);
+
+        for child in children {
+            if let Some(child) = child.element() {
+                self.validate_element(child);
+            }
+        }
+    }
+
+    fn validate_attributes(&mut self, element: dom::Element<'d>, element_name: &str) {
+        let declarations = self.dtd.attributes(element_name);
+
+        for attribute in element.attributes() {
+            let name = prefixed(attribute.preferred_prefix(), attribute.name().local_part());
+
+            match declarations.iter().find(|d| d.name() == name) {
+                Some(declaration) => {
+                    if !self.value_is_valid(element, declaration, attribute.value()) {
+                        self.report(element, DiagnosticKind::InvalidAttributeValue(name));
+                    }
+                }
+                None => self.report(element, DiagnosticKind::UndeclaredAttribute(name)),
+            }
+        }
+
+        // Namespace declarations are not attributes in the DOM
+        let required = declarations.iter().filter(|d| {
+            d.default_value == DefaultValue::Required
+                && d.name != "xmlns"
+                && !d.name.starts_with("xmlns:")
+        });
+
+        for declaration in required {
+            let present = element
+                .attributes()
+                .iter()
+                .any(|a| prefixed(a.preferred_prefix(), a.name().local_part()) == declaration.name);
+
+            if !present {
+                let kind = DiagnosticKind::MissingRequiredAttribute(declaration.name.clone());
+                self.report(element, kind);
+            }
+        }
+    }
+
+    /// Records the IDs and references to IDs, which are checked once
+    /// the whole document has been seen
+    fn value_is_valid(
+        &mut self,
+        element: dom::Element<'d>,
+        declaration: &AttributeDeclaration,
+        value: &'d str,
+    ) -> bool {
+        let tokens = tokens(value);
+        let single = if tokens.len() == 1 {
+            Some(tokens[0])
+        } else {
+            None
+        };
+
+        if let DefaultValue::Fixed(ref fixed) = declaration.default_value {
+            let matches = match declaration.attribute_type {
+                AttributeType::CData => value == fixed,
+                _ => tokens == self::tokens(fixed),
+            };
+            if !matches {
+                return false;
+            }
+        }
+
+        let dtd = self.dtd;
+        let is_unparsed_entity = |name: &str| match dtd.entity(name) {
+            Some(Entity::External { notation, .. }) => notation.is_some(),
+            _ => false,
+        };
+
+        match declaration.attribute_type {
+            AttributeType::CData => true,
+            AttributeType::Id => match single {
+                Some(id) if is_name(id) => {
+                    if !self.ids.insert(id) {
+                        self.report(element, DiagnosticKind::DuplicateId(id.into()));
+                    }
+                    true
+                }
+                _ => false,
+            },
+            AttributeType::IdRef if single.is_none() => false,
+            AttributeType::IdRef | AttributeType::IdRefs => {
+                let valid = !tokens.is_empty() && tokens.iter().all(|t| is_name(t));
+                if valid {
+                    self.references.extend(tokens.iter().map(|&t| (element, t)));
+                }
+                valid
+            }
+            AttributeType::Entity => single.filter(|&e| is_unparsed_entity(e)).is_some(),
+            AttributeType::Entities => {
+                !tokens.is_empty() && tokens.iter().all(|t| is_unparsed_entity(t))
+            }
+            AttributeType::NmToken => single.filter(|t| is_nmtoken(t)).is_some(),
+            AttributeType::NmTokens => !tokens.is_empty() && tokens.iter().all(|t| is_nmtoken(t)),
+            AttributeType::Notation(ref allowed) | AttributeType::Enumeration(ref allowed) => {
+                single.filter(|v| allowed.iter().any(|a| a == v)).is_some()
+            }
+        }
+    }
+}
+
+fn content_is_valid(content: &ContentSpec, children: &[dom::ChildOfElement<'_>]) -> bool {
+    let mut names = Vec::new();
+    let mut has_text = false;
+
+    for child in children {
+        if let Some(element) = child.element() {
+            names.push(qualified_name(element));
+        } else if let Some(text) = child.text() {
+            has_text |= !text.text().chars().all(|c| c.is_space_char());
+        }
+    }
+
+    match *content {
+        ContentSpec::Empty => children.is_empty(),
+        ContentSpec::Any => true,
+        ContentSpec::Mixed(ref allowed) => names.iter().all(|n| allowed.contains(n)),
+        ContentSpec::Children(ref particle) => !has_text && particle.matches(&names),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn internal(text: &str) -> Entity {
+        Entity::Internal(text.into())
+    }
+
+    #[test]
+    fn character_references_are_replaced() {
+        let dtd = Dtd::parse("<!ENTITY a 'x&#60;&#x3E;y'>").unwrap();
+        assert_eq!(dtd.entity("a"), Some(&internal("x<>y")));
+    }
+
+    #[test]
+    fn entity_references_are_kept() {
+        let dtd = Dtd::parse(r#"<!ENTITY a "&b; &amp;">"#).unwrap();
+        assert_eq!(dtd.entity("a"), Some(&internal("&b; &amp;")));
+    }
+
+    #[test]
+    fn the_first_declaration_is_used() {
+        let dtd = Dtd::parse("<!ENTITY a 'one'> <!ENTITY a 'two'>").unwrap();
+        assert_eq!(dtd.entity("a"), Some(&internal("one")));
+    }
+
+    #[test]
+    fn external_and_unparsed_entities() {
+        let dtd = Dtd::parse(
+            "<!ENTITY a SYSTEM 'a.xml'>\
+             <!ENTITY b PUBLIC '-//B//EN' 'b.png' NDATA png>",
+        )
+        .unwrap();
+
+        assert_eq!(
+            dtd.entity("a"),
+            Some(&Entity::External {
+                public_id: None,
+                system_id: "a.xml".into(),
+                notation: None,
+            })
+        );
+        assert_eq!(
+            dtd.entity("b"),
+            Some(&Entity::External {
+                public_id: Some("-//B//EN".into()),
+                system_id: "b.png".into(),
+                notation: Some("png".into()),
+            })
+        );
+    }
+
+    #[test]
+    fn other_declarations_are_skipped() {
+        let dtd = Dtd::parse(
+            "<!ELEMENT a (#PCDATA)>\
+             <!NOTATION png SYSTEM 'image/png'>\
+             <!-- <!ENTITY c 'comment'> -->\
+             <?pi <!ENTITY c 'pi'>?>\
+             <!ENTITY c 'entity'>",
+        )
+        .unwrap();
+        assert_eq!(dtd.entity("c"), Some(&internal("entity")));
+    }
+
+    #[test]
+    fn attribute_lists() {
+        let dtd = Dtd::parse(
+            "<!ENTITY v 'value'>\
+             <!ATTLIST a \
+               id ID #REQUIRED \
+               refs IDREFS #IMPLIED \
+               xmlns:x CDATA #FIXED 'urn:x' \
+               kind (big | small) 'small' \
+               format NOTATION (png|gif) 'png' \
+               text CDATA 'a &v; &#33;'>\
+             <!ATTLIST a id CDATA 'ignored' size NMTOKEN #IMPLIED>",
+        )
+        .unwrap();
+
+        assert_eq!(
+            dtd.attributes("a"),
+            &[
+                AttributeDeclaration::new("id", AttributeType::Id, DefaultValue::Required),
+                AttributeDeclaration::new("refs", AttributeType::IdRefs, DefaultValue::Implied),
+                AttributeDeclaration::new(
+                    "xmlns:x",
+                    AttributeType::CData,
+                    DefaultValue::Fixed("urn:x".into())
+                ),
+                AttributeDeclaration::new(
+                    "kind",
+                    AttributeType::Enumeration(vec!["big".into(), "small".into()]),
+                    DefaultValue::Default("small".into())
+                ),
+                AttributeDeclaration::new(
+                    "format",
+                    AttributeType::Notation(vec!["png".into(), "gif".into()]),
+                    DefaultValue::Default("png".into())
+                ),
+                AttributeDeclaration::new(
+                    "text",
+                    AttributeType::CData,
+                    DefaultValue::Default("a value !".into())
+                ),
+                AttributeDeclaration::new("size", AttributeType::NmToken, DefaultValue::Implied),
+            ][..]
+        );
+        assert!(dtd.attributes("b").is_empty());
+    }
+
+    fn name(name: &str, repetition: Repetition) -> ContentParticle {
+        ContentParticle::new(Particle::Name(name.into()), repetition)
+    }
+
+    #[test]
+    fn element_declarations() {
+        let dtd = Dtd::parse(
+            "<!ELEMENT a EMPTY>\
+             <!ELEMENT b ANY>\
+             <!ELEMENT c (#PCDATA)>\
+             <!ELEMENT d ( #PCDATA | a | b )*>\
+             <!ELEMENT e (a, (b | c)*, d?)+>",
+        )
+        .unwrap();
+
+        assert_eq!(dtd.element("a"), Some(&ContentSpec::Empty));
+        assert_eq!(dtd.element("b"), Some(&ContentSpec::Any));
+        assert_eq!(dtd.element("c"), Some(&ContentSpec::Mixed(vec![])));
+        assert_eq!(
+            dtd.element("d"),
+            Some(&ContentSpec::Mixed(vec!["a".into(), "b".into()]))
+        );
+
+        let choice = Particle::Choice(vec![
+            name("b", Repetition::Once),
+            name("c", Repetition::Once),
+        ]);
+        let sequence = Particle::Sequence(vec![
+            name("a", Repetition::Once),
+            ContentParticle::new(choice, Repetition::ZeroOrMore),
+            name("d", Repetition::Optional),
+        ]);
+        assert_eq!(
+            dtd.element("e"),
+            Some(&ContentSpec::Children(ContentParticle::new(
+                sequence,
+                Repetition::OneOrMore
+            )))
+        );
+        assert_eq!(dtd.element("f"), None);
+    }
+
+    #[test]
+    fn malformed_element_declarations_are_an_error() {
+        assert!(Dtd::parse("<!ELEMENT a (#PCDATA | b)>").is_err());
+        assert!(Dtd::parse("<!ELEMENT a (b | c, d)>").is_err());
+        assert!(Dtd::parse("<!ELEMENT a b>").is_err());
+    }
+
+    #[test]
+    fn attribute_defaults_cannot_contain_less_than() {
+        assert!(Dtd::parse("<!ATTLIST a b CDATA '<'>").is_err());
+        assert!(Dtd::parse("<!ENTITY lt2 '<'><!ATTLIST a b CDATA '&lt2;'>").is_err());
+    }
+
+    #[test]
+    fn parameter_entities_are_kept_separately() {
+        let dtd = Dtd::parse("<!ENTITY % a 'parameter'><!ENTITY a 'general'>").unwrap();
+        assert_eq!(dtd.parameter_entity("a"), Some(&internal("parameter")));
+        assert_eq!(dtd.entity("a"), Some(&internal("general")));
+    }
+
+    #[test]
+    fn parameter_entity_references_are_expanded_between_declarations() {
+        let dtd = Dtd::parse(r#"<!ENTITY % decl "<!ENTITY a 'from parameter'>"> %decl;"#).unwrap();
+        assert_eq!(dtd.entity("a"), Some(&internal("from parameter")));
+    }
+
+    #[test]
+    fn parameter_entity_references_cannot_be_used_in_declarations() {
+        let r = Dtd::parse("<!ENTITY % p 'x'><!ENTITY a '%p;'>");
+        assert_eq!(
+            r.unwrap_err().kind(),
+            parser::ErrorKind::ParameterEntityInInternalSubset
+        );
+    }
+
+    #[test]
+    fn recursive_parameter_entities_are_an_error() {
+        let r = Dtd::parse("<!ENTITY % a '&#37;a;'> %a;");
+        assert_eq!(
+            r.unwrap_err().kind(),
+            parser::ErrorKind::RecursiveEntityReference
+        );
+    }
+
+    fn diagnostics(xml: &str) -> Vec<(String, DiagnosticKind)> {
+        let package = parser::parse(xml).expect("Failed to parse the XML string");
+        let doc = package.as_document();
+        let internal_subset = doc
+            .root()
+            .children()
+            .iter()
+            .find_map(|c| c.document_type())
+            .and_then(|d| d.internal_subset())
+            .unwrap_or("");
+        let dtd = Dtd::parse(internal_subset).expect("Failed to parse the DTD");
+
+        validate(&doc, &dtd)
+            .into_iter()
+            .map(|d| (qualified_name(d.element()), d.kind().clone()))
+            .collect()
+    }
+
+    fn invalid(element: &str, kind: DiagnosticKind) -> (String, DiagnosticKind) {
+        (element.into(), kind)
+    }
+
+    #[test]
+    fn a_valid_document_has_no_diagnostics() {
+        let diagnostics = diagnostics(
+            "<!DOCTYPE doc [\
+               <!ELEMENT doc (head?, (p | list)*, foot+)>\
+               <!ELEMENT head EMPTY>\
+               <!ELEMENT p (#PCDATA | b)*>\
+               <!ELEMENT b (#PCDATA)>\
+               <!ELEMENT list ANY>\
+               <!ELEMENT foot EMPTY>\
+             ]>\
+             <doc>\
+               <head/>\
+               <p>Some <b>bold</b> text</p>\
+               <!-- a comment -->\
+               <list><p/>text</list>\
+               <foot/><foot></foot>\
+             </doc>",
+        );
+
+        assert_eq!(diagnostics, []);
+    }
+
+    #[test]
+    fn content_that_does_not_match_the_model() {
+        let diagnostics = diagnostics(
+            "<!DOCTYPE doc [\
+               <!ELEMENT doc (a, b)+>\
+               <!ELEMENT a EMPTY>\
+               <!ELEMENT b (#PCDATA | a)*>\
+             ]>\
+             <doc><a/><b><b/></b><a> </a>text</doc>",
+        );
+
+        assert_eq!(
+            diagnostics,
+            [
+                invalid("doc", DiagnosticKind::InvalidContent),
+                invalid("b", DiagnosticKind::InvalidContent),
+                invalid("a", DiagnosticKind::InvalidContent),
+            ]
+        );
+    }
+
+    #[test]
+    fn undeclared_elements_and_the_root_element_name() {
+        let diagnostics = diagnostics(
+            "<!DOCTYPE doc [<!ELEMENT other ANY>]>\
+             <other><unknown/></other>",
+        );
+
+        assert_eq!(
+            diagnostics,
+            [
+                invalid("other", DiagnosticKind::RootElementMismatch),
+                invalid("unknown", DiagnosticKind::UndeclaredElement),
+            ]
+        );
+    }
+
+    #[test]
+    fn required_and_undeclared_attributes() {
+        let diagnostics = diagnostics(
+            "<!DOCTYPE x:doc [\
+               <!ELEMENT x:doc EMPTY>\
+               <!ATTLIST x:doc \
+                 xmlns:x CDATA #REQUIRED \
+                 x:a CDATA #REQUIRED \
+                 b CDATA #REQUIRED \
+                 c CDATA #IMPLIED>\
+             ]>\
+             <x:doc xmlns:x='urn:x' x:a='1' d='2'/>",
+        );
+
+        assert_eq!(
+            diagnostics,
+            [
+                invalid("x:doc", DiagnosticKind::UndeclaredAttribute("d".into())),
+                invalid(
+                    "x:doc",
+                    DiagnosticKind::MissingRequiredAttribute("b".into())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn attribute_values_must_match_their_type() {
+        let diagnostics = diagnostics(
+            "<!DOCTYPE doc [\
+               <!ELEMENT doc (e*)>\
+               <!ELEMENT e EMPTY>\
+               <!ENTITY pic SYSTEM 'pic.png' NDATA png>\
+               <!ENTITY text 'text'>\
+               <!ATTLIST e \
+                 size (small | large) #IMPLIED \
+                 version CDATA #FIXED '1' \
+                 token NMTOKEN #IMPLIED \
+                 image ENTITY #IMPLIED>\
+             ]>\
+             <doc>\
+               <e size=' large ' version='1' token='a-1' image='pic'/>\
+               <e size='medium'/>\
+               <e version='2'/>\
+               <e token='a b'/>\
+               <e image='text'/>\
+             </doc>",
+        );
+
+        assert_eq!(
+            diagnostics,
+            [
+                invalid("e", DiagnosticKind::InvalidAttributeValue("size".into())),
+                invalid("e", DiagnosticKind::InvalidAttributeValue("version".into())),
+                invalid("e", DiagnosticKind::InvalidAttributeValue("token".into())),
+                invalid("e", DiagnosticKind::InvalidAttributeValue("image".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn ids_are_unique_and_references_must_exist() {
+        let diagnostics = diagnostics(
+            "<!DOCTYPE doc [\
+               <!ELEMENT doc (e*)>\
+               <!ELEMENT e EMPTY>\
+               <!ATTLIST e id ID #IMPLIED ref IDREF #IMPLIED refs IDREFS #IMPLIED>\
+             ]>\
+             <doc>\
+               <e id='one' ref='two'/>\
+               <e id='two' refs='one two'/>\
+               <e id='one' refs='one three'/>\
+               <e id='1'/>\
+             </doc>",
+        );
+
+        assert_eq!(
+            diagnostics,
+            [
+                invalid("e", DiagnosticKind::DuplicateId("one".into())),
+                invalid("e", DiagnosticKind::InvalidAttributeValue("id".into())),
+                invalid("e", DiagnosticKind::UnknownId("three".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn diagnostics_display_the_element() {
+        let package = parser::parse("<a/>").unwrap();
+        let doc = package.as_document();
+        let diagnostics = validate(&doc, &Dtd::new());
+
+        assert_eq!(diagnostics[0].to_string(), "<a>: element is not declared");
+    }
+
+    #[test]
+    fn malformed_declarations_are_an_error() {
+        let e = Dtd::parse("<!ENTITY a 'x'").unwrap_err();
+        assert_eq!(e.location(), 14);
+        assert!(Dtd::parse("<!ENTITY 'x'>").is_err());
+        assert!(Dtd::parse("junk").is_err());
+    }
+}
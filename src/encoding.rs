@@ -0,0 +1,304 @@
+//! Conversions between bytes and text for the character encodings
+//! that documents may be stored in.
+
+#[allow(unused, deprecated)] // rust-lang/rust#46510
+use std::ascii::AsciiExt;
+use std::{char, str};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16BigEndian,
+    Utf16LittleEndian,
+    Latin1,
+    Ascii,
+}
+
+use self::Encoding::*;
+
+impl Encoding {
+    /// Finds the encoding with the given name, ignoring case. The name
+    /// `UTF-16` does not specify a byte order, so the byte order of
+    /// `detected` is used, defaulting to big endian.
+    pub fn from_name(name: &str, detected: Encoding) -> Option<Encoding> {
+        let name = name.to_ascii_uppercase();
+
+        let encoding = match &name[..] {
+            "UTF-8" | "UTF8" => Utf8,
+            "UTF-16" if detected == Utf16LittleEndian => Utf16LittleEndian,
+            "UTF-16" | "UTF-16BE" => Utf16BigEndian,
+            "UTF-16LE" => Utf16LittleEndian,
+            "ISO-8859-1" | "ISO_8859-1" | "LATIN1" | "L1" => Latin1,
+            "US-ASCII" | "ASCII" => Ascii,
+            _ => return None,
+        };
+
+        Some(encoding)
+    }
+
+    pub fn is_utf16(self) -> bool {
+        self == Utf16BigEndian || self == Utf16LittleEndian
+    }
+
+    /// Appends the decoded bytes to `text`. On failure, returns the
+    /// length of `text` at the first byte that could not be decoded.
+    pub fn decode(self, bytes: &[u8], text: &mut String) -> Result<(), usize> {
+        match self {
+            Utf8 => match str::from_utf8(bytes) {
+                Ok(s) => text.push_str(s),
+                Err(e) => return Err(text.len() + e.valid_up_to()),
+            },
+            Utf16BigEndian | Utf16LittleEndian => {
+                if bytes.len() & 1 == 1 {
+                    return Err(text.len());
+                }
+
+                for c in char::decode_utf16(self.code_units(bytes)) {
+                    match c {
+                        Ok(c) => text.push(c),
+                        Err(_) => return Err(text.len()),
+                    }
+                }
+            }
+            Latin1 => text.extend(bytes.iter().map(|&b| char::from(b))),
+            Ascii => {
+                if let Some(i) = bytes.iter().position(|&b| b >= 0x80) {
+                    return Err(text.len() + i);
+                }
+                text.extend(bytes.iter().map(|&b| char::from(b)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the character can be written in this encoding.
+    pub fn can_encode(self, c: char) -> bool {
+        match self {
+            Utf8 | Utf16BigEndian | Utf16LittleEndian => true,
+            Latin1 => (c as u32) < 0x100,
+            Ascii => c.is_ascii(),
+        }
+    }
+
+    /// Appends the encoded text to `bytes`. On failure, returns the
+    /// first character that cannot be written in this encoding.
+    pub fn encode(self, text: &str, bytes: &mut Vec<u8>) -> Result<(), char> {
+        if let Some(c) = text.chars().find(|&c| !self.can_encode(c)) {
+            return Err(c);
+        }
+
+        match self {
+            Utf8 => bytes.extend_from_slice(text.as_bytes()),
+            Utf16BigEndian => {
+                for u in text.encode_utf16() {
+                    bytes.push((u >> 8) as u8);
+                    bytes.push(u as u8);
+                }
+            }
+            Utf16LittleEndian => {
+                for u in text.encode_utf16() {
+                    bytes.push(u as u8);
+                    bytes.push((u >> 8) as u8);
+                }
+            }
+            Latin1 | Ascii => bytes.extend(text.chars().map(|c| c as u8)),
+        }
+
+        Ok(())
+    }
+
+    /// The byte order mark that starts a document in this encoding, if
+    /// one is required.
+    pub fn byte_order_mark(self) -> &'static [u8] {
+        match self {
+            Utf16BigEndian => b"\xFE\xFF",
+            Utf16LittleEndian => b"\xFF\xFE",
+            _ => b"",
+        }
+    }
+
+    /// Decodes the start of the bytes, up to and including the first
+    /// `>`. This is only good enough to read the XML declaration,
+    /// which is written using ASCII characters.
+    pub fn decode_declaration(self, bytes: &[u8]) -> String {
+        if self.is_utf16() {
+            let units = self
+                .code_units(bytes)
+                .take_while(|&u| u != u16::from(b'>'))
+                .chain(Some(u16::from(b'>')));
+            char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect()
+        } else {
+            let end = bytes
+                .iter()
+                .position(|&b| b == b'>')
+                .map_or(bytes.len(), |i| i + 1);
+            bytes[..end].iter().map(|&b| char::from(b)).collect()
+        }
+    }
+
+    fn code_units<'a>(self, bytes: &'a [u8]) -> impl Iterator<Item = u16> + 'a {
+        let big_endian = self == Utf16BigEndian;
+        bytes.chunks(2).filter(|c| c.len() == 2).map(move |c| {
+            let (high, low) = if big_endian {
+                (c[0], c[1])
+            } else {
+                (c[1], c[0])
+            };
+            u16::from(high) << 8 | u16::from(low)
+        })
+    }
+}
+
+/// Guesses the encoding from the first bytes of a document, as
+/// described in [Appendix F][F] of the XML specification. Returns the
+/// encoding and the length of the byte order mark, or `None` if the
+/// document uses an encoding family that is not supported.
+///
+/// [F]: https://www.w3.org/TR/xml/#sec-guessing-no-ext-info
+pub fn detect(bytes: &[u8]) -> Option<(Encoding, usize)> {
+    const UNSUPPORTED: &[&[u8]] = &[
+        // UCS-4 in all byte orders
+        b"\x00\x00\xFE\xFF",
+        b"\xFF\xFE\x00\x00",
+        b"\x00\x00\xFF\xFE",
+        b"\xFE\xFF\x00\x00",
+        b"\x00\x00\x00\x3C",
+        b"\x3C\x00\x00\x00",
+        b"\x00\x00\x3C\x00",
+        b"\x00\x3C\x00\x00",
+        // EBCDIC
+        b"\x4C\x6F\xA7\x94",
+    ];
+
+    if UNSUPPORTED.iter().any(|u| bytes.starts_with(u)) {
+        return None;
+    }
+
+    let detected = if bytes.starts_with(b"\xEF\xBB\xBF") {
+        (Utf8, 3)
+    } else if bytes.starts_with(b"\xFE\xFF") {
+        (Utf16BigEndian, 2)
+    } else if bytes.starts_with(b"\xFF\xFE") {
+        (Utf16LittleEndian, 2)
+    } else if bytes.starts_with(b"\x00\x3C\x00\x3F") {
+        (Utf16BigEndian, 0)
+    } else if bytes.starts_with(b"\x3C\x00\x3F\x00") {
+        (Utf16LittleEndian, 0)
+    } else {
+        (Utf8, 0)
+    };
+
+    Some(detected)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(encoding: Encoding, bytes: &[u8]) -> Result<String, usize> {
+        let mut text = String::new();
+        encoding.decode(bytes, &mut text).map(|_| text)
+    }
+
+    #[test]
+    fn names_are_case_insensitive() {
+        assert_eq!(Encoding::from_name("utf-8", Utf8), Some(Utf8));
+        assert_eq!(Encoding::from_name("iso-8859-1", Utf8), Some(Latin1));
+        assert_eq!(Encoding::from_name("EBCDIC", Utf8), None);
+    }
+
+    #[test]
+    fn utf_16_takes_the_detected_byte_order() {
+        assert_eq!(
+            Encoding::from_name("UTF-16", Utf16LittleEndian),
+            Some(Utf16LittleEndian)
+        );
+        assert_eq!(Encoding::from_name("UTF-16", Utf8), Some(Utf16BigEndian));
+    }
+
+    #[test]
+    fn detects_byte_order_marks() {
+        assert_eq!(detect(b"\xEF\xBB\xBF<a/>"), Some((Utf8, 3)));
+        assert_eq!(detect(b"\xFE\xFF\x00<"), Some((Utf16BigEndian, 2)));
+        assert_eq!(detect(b"\xFF\xFE<\x00"), Some((Utf16LittleEndian, 2)));
+    }
+
+    #[test]
+    fn detects_utf_16_from_the_declaration() {
+        assert_eq!(detect(b"\x00<\x00?\x00x"), Some((Utf16BigEndian, 0)));
+        assert_eq!(detect(b"<\x00?\x00x\x00"), Some((Utf16LittleEndian, 0)));
+    }
+
+    #[test]
+    fn defaults_to_utf_8() {
+        assert_eq!(detect(b"<?xml"), Some((Utf8, 0)));
+        assert_eq!(detect(b""), Some((Utf8, 0)));
+    }
+
+    #[test]
+    fn rejects_ucs_4() {
+        assert_eq!(detect(b"\x00\x00\x00<"), None);
+    }
+
+    #[test]
+    fn decodes_utf_16_surrogate_pairs() {
+        assert_eq!(
+            decode(Utf16BigEndian, b"\xD8\x3D\xDE\x00"),
+            Ok("\u{1F600}".into())
+        );
+        assert_eq!(
+            decode(Utf16LittleEndian, b"\x3D\xD8\x00\xDE"),
+            Ok("\u{1F600}".into())
+        );
+    }
+
+    #[test]
+    fn reports_where_decoding_failed() {
+        assert_eq!(decode(Utf8, b"ab\xFF"), Err(2));
+        assert_eq!(decode(Ascii, b"ab\xE9"), Err(2));
+        assert_eq!(decode(Utf16BigEndian, b"\x00a\xDC\x00"), Err(1));
+        assert_eq!(decode(Utf16BigEndian, b"\x00a\x00"), Err(0));
+    }
+
+    #[test]
+    fn decodes_latin_1() {
+        assert_eq!(decode(Latin1, b"caf\xE9"), Ok("caf\u{e9}".into()));
+    }
+
+    fn encode(encoding: Encoding, text: &str) -> Result<Vec<u8>, char> {
+        let mut bytes = Vec::new();
+        encoding.encode(text, &mut bytes).map(|_| bytes)
+    }
+
+    #[test]
+    fn encodes_utf_16_surrogate_pairs() {
+        assert_eq!(
+            encode(Utf16BigEndian, "\u{1F600}"),
+            Ok(b"\xD8\x3D\xDE\x00".to_vec())
+        );
+        assert_eq!(
+            encode(Utf16LittleEndian, "\u{1F600}"),
+            Ok(b"\x3D\xD8\x00\xDE".to_vec())
+        );
+    }
+
+    #[test]
+    fn reports_characters_that_cannot_be_encoded() {
+        assert_eq!(encode(Latin1, "caf\u{e9}"), Ok(b"caf\xE9".to_vec()));
+        assert_eq!(encode(Latin1, "\u{20AC}"), Err('\u{20AC}'));
+        assert_eq!(encode(Ascii, "caf\u{e9}"), Err('\u{e9}'));
+    }
+
+    #[test]
+    fn decodes_only_the_declaration() {
+        let bytes = b"<\x00?\x00x\x00>\x00z\x00";
+        assert_eq!(
+            Utf16LittleEndian.decode_declaration(bytes),
+            "<?x>".to_string()
+        );
+        assert_eq!(Latin1.decode_declaration(b"<?x?>\xE9"), "<?x?>".to_string());
+    }
+}
@@ -57,6 +57,8 @@ extern crate peresil;
 
 use std::fmt;
 
+mod encoding;
+mod html_entities;
 mod lazy_hash_map;
 mod raw;
 mod str;
@@ -64,7 +66,10 @@ mod str_ext;
 mod string_pool;
 
 pub mod dom;
+pub mod dtd;
 pub mod parser;
+pub mod resolver;
+pub mod sax;
 #[doc(hidden)]
 pub mod thindom;
 pub mod writer;
@@ -73,6 +78,8 @@ pub use crate::str::XmlChar;
 
 static XML_NS_PREFIX: &str = "xml";
 static XML_NS_URI: &str = "http://www.w3.org/XML/1998/namespace";
+static XMLNS_NS_PREFIX: &str = "xmlns";
+static XMLNS_NS_URI: &str = "http://www.w3.org/2000/xmlns/";
 
 /// A prefixed name. This represents what is found in the string form
 /// of an XML document, and does not apply any namespace mapping.
@@ -17,21 +17,42 @@
 #[allow(unused, deprecated)] // rust-lang/rust#46510
 use std::ascii::AsciiExt;
 use std::{
-    char,
+    borrow::Cow,
+    cell::Cell,
+    char, cmp,
     collections::{BTreeSet, HashMap},
-    error, fmt, iter,
-    mem::replace,
+    error, fmt,
+    io::{self, BufRead},
+    iter,
+    mem::{self, replace},
     ops::Deref,
+    rc::Rc,
+    str,
 };
 
 use peresil::{self, ParseMaster, Recoverable, StringPoint};
 
 use self::Reference::*;
 
-use super::{dom, str::XmlStr, PrefixedName, QName};
+use super::{
+    dom::{self, SourceSpan},
+    dtd::{self, Dtd},
+    encoding::Encoding,
+    html_entities::XHTML_ENTITIES,
+    resolver::{DenyAll, Resolver},
+    str::{XmlChar, XmlStr},
+    PrefixedName, QName,
+};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-enum SpecificError {
+/// The kinds of problems that can be found while parsing.
+///
+/// The `Expected` kinds describe what the parser was looking for at
+/// the location of the error. More kinds may be added in the future,
+/// so matches on this type need a wildcard arm.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum ErrorKind {
+    /// A specific string, such as `=` or `?>`
     Expected(&'static str),
 
     ExpectedAttribute,
@@ -61,6 +82,11 @@ enum SpecificError {
     ExpectedDocumentTypeName,
     ExpectedIntSubset,
     ExpectedSystemLiteral,
+    ExpectedPublicIdLiteral,
+    ExpectedEntityValue,
+    ExpectedNotationName,
+    ExpectedNameToken,
+    ExpectedAttributeType,
 
     ExpectedClosingQuote(&'static str),
     ExpectedOpeningQuote(&'static str),
@@ -79,18 +105,91 @@ enum SpecificError {
     InvalidDecimalReference,
     InvalidHexReference,
     UnknownNamedReference,
+    RecursiveEntityReference,
+    UnparsedEntityReference,
+    UnresolvedExternalEntity,
+    ExternalEntityInAttributeValue,
+    LessThanInAttributeValue,
+    ParameterEntityInInternalSubset,
 
     DuplicateAttribute,
     RedefinedNamespace,
     RedefinedDefaultNamespace,
     EmptyNamespace,
+    /// Two attributes have different prefixes bound to the same
+    /// namespace URI and the same local name
+    DuplicateExpandedAttribute,
+    /// The `xml` prefix is bound to a URI other than the XML namespace
+    RedefinedXmlPrefix,
+    /// The `xmlns` prefix is declared
+    DeclaredXmlnsPrefix,
+    /// A prefix other than `xml`, or the default namespace, is bound
+    /// to the XML namespace, or anything is bound to the `xmlns`
+    /// namespace
+    ReservedNamespace,
     UnknownNamespacePrefix,
     UnclosedElement,
+
+    UnsupportedEncoding,
+    MismatchedEncoding,
+    InvalidEncodedData,
+
+    /// A character outside of the `Char` production, written out or as
+    /// a reference
+    InvalidCharacter,
+
+    /// An event reader met a reference to an entity whose replacement
+    /// text contains markup, which it cannot report as events
+    EntityWithMarkup,
+
+    /// The document goes beyond one of the `ParseLimits`
+    LimitExceeded(Limit),
+
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+/// The limits that can be set with `ParseLimits`
+///
+/// More limits may be added in the future, so matches on this type
+/// need a wildcard arm.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum Limit {
+    Depth,
+    Attributes,
+    NameLength,
+    TextLength,
+    Nodes,
+    EntityDepth,
+    EntityAmplification,
+
+    #[doc(hidden)]
+    __Nonexhaustive,
 }
 
-impl Recoverable for SpecificError {
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::Limit::*;
+
+        let what = match *self {
+            Depth => "element nesting depth",
+            Attributes => "number of attributes of an element",
+            NameLength => "length of a name",
+            TextLength => "length of text",
+            Nodes => "number of nodes",
+            EntityDepth => "entity nesting depth",
+            EntityAmplification => "size of entity expansions",
+            __Nonexhaustive => "limit",
+        };
+
+        f.write_str(what)
+    }
+}
+
+impl Recoverable for ErrorKind {
     fn recoverable(&self) -> bool {
-        use self::SpecificError::*;
+        use self::ErrorKind::*;
 
         match *self {
             ExpectedEncoding
@@ -100,82 +199,132 @@ impl Recoverable for SpecificError {
             | InvalidDecimalReference
             | InvalidHexReference
             | UnknownNamedReference
+            | RecursiveEntityReference
+            | UnparsedEntityReference
+            | UnresolvedExternalEntity
+            | ExternalEntityInAttributeValue
+            | LessThanInAttributeValue
+            | ParameterEntityInInternalSubset
             | DuplicateAttribute
             | RedefinedNamespace
             | RedefinedDefaultNamespace
             | EmptyNamespace
+            | DuplicateExpandedAttribute
+            | RedefinedXmlPrefix
+            | DeclaredXmlnsPrefix
+            | ReservedNamespace
             | UnknownNamespacePrefix
-            | UnclosedElement => false,
+            | UnclosedElement
+            | UnsupportedEncoding
+            | MismatchedEncoding
+            | InvalidEncodedData
+            | InvalidCharacter
+            | EntityWithMarkup
+            | LimitExceeded(..) => false,
             _ => true,
         }
     }
 }
 
-impl fmt::Display for SpecificError {
-    #[allow(deprecated)]
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use self::{error::Error, SpecificError::*};
+impl ErrorKind {
+    /// What the parser was looking for, if this is an `Expected` kind
+    fn expectation(self) -> Option<Cow<'static, str>> {
+        use self::ErrorKind::*;
+
+        let what = match self {
+            Expected(s) => return Some(format!("'{}'", s).into()),
+            ExpectedClosingQuote(q) => return Some(format!("a closing quote ({})", q).into()),
+            ExpectedOpeningQuote(q) => return Some(format!("an opening quote ({})", q).into()),
+            ExpectedAttribute => "an attribute",
+            ExpectedAttributeValue => "an attribute value",
+            ExpectedCData => "a CDATA section",
+            ExpectedCharacterData => "character data",
+            ExpectedComment => "a comment",
+            ExpectedCommentBody => "the end of the comment",
+            ExpectedElement => "an element",
+            ExpectedElementName => "an element name",
+            ExpectedElementEnd => "'>'",
+            ExpectedElementSelfClosed => "'/>'",
+            ExpectedProcessingInstruction => "a processing instruction",
+            ExpectedProcessingInstructionTarget => "a processing instruction target",
+            ExpectedProcessingInstructionValue => "the end of the processing instruction",
+            ExpectedVersionNumber => "a version number",
+            ExpectedEncoding => "an encoding name",
+            ExpectedYesNo => "'yes' or 'no'",
+            ExpectedWhitespace => "whitespace",
+            ExpectedDocumentTypeName => "a document type name",
+            ExpectedIntSubset => "an internal subset",
+            ExpectedSystemLiteral => "a system literal",
+            ExpectedPublicIdLiteral => "a public identifier literal",
+            ExpectedEntityValue => "an entity value",
+            ExpectedNotationName => "a notation name",
+            ExpectedNameToken => "a name token",
+            ExpectedAttributeType => "an attribute type",
+            ExpectedDecimalReferenceValue => "a decimal number",
+            ExpectedHexReferenceValue => "a hexadecimal number",
+            ExpectedNamedReferenceValue => "an entity name",
+            ExpectedDecimalReference => "a decimal character reference",
+            ExpectedHexReference => "a hexadecimal character reference",
+            ExpectedNamedReference => "an entity reference",
+            _ => return None,
+        };
 
-        match *self {
-            Expected(s) | ExpectedClosingQuote(s) | ExpectedOpeningQuote(s) => {
-                write!(f, "Parser error: {} {}", self.description(), s)
-            }
-            _ => write!(f, "Parser error: {}", self.description()),
-        }
+        Some(what.into())
     }
 }
 
-impl error::Error for SpecificError {
-    fn description(&self) -> &str {
-        use self::SpecificError::*;
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::ErrorKind::*;
 
-        match *self {
-            Expected(_) => "expected",
-            ExpectedAttribute => "expected attribute",
-            ExpectedAttributeValue => "expected attribute value",
-            ExpectedCData => "expected CDATA",
-            ExpectedCharacterData => "expected character data",
-            ExpectedComment => "expected comment",
-            ExpectedCommentBody => "expected comment body",
-            ExpectedElement => "expected element",
-            ExpectedElementName => "expected element name",
-            ExpectedElementEnd => "expected element end",
-            ExpectedElementSelfClosed => "expected element self closed",
-            ExpectedProcessingInstruction => "expected processing instruction",
-            ExpectedProcessingInstructionTarget => "expected processing instruction target",
-            ExpectedProcessingInstructionValue => "expected processing instruction value",
-            ExpectedVersionNumber => "expected version number",
-            ExpectedEncoding => "expected encoding",
-            ExpectedYesNo => "expected yes or no",
-            ExpectedWhitespace => "expected whitespace",
-            ExpectedDocumentTypeName => "expected document type name",
-            ExpectedIntSubset => "expected int subset",
-            ExpectedSystemLiteral => "expected system literal",
-            ExpectedClosingQuote(_) => "expected closing quote",
-            ExpectedOpeningQuote(_) => "expected opening quote",
-            ExpectedDecimalReferenceValue => "expected decimal reference value",
-            ExpectedHexReferenceValue => "expected hex reference value",
-            ExpectedNamedReferenceValue => "expected named reference value",
-            ExpectedDecimalReference => "expected decimal reference",
-            ExpectedHexReference => "expected hex reference",
-            ExpectedNamedReference => "expected named reference",
-            InvalidProcessingInstructionTarget => "invalid processing instruction target",
-            MismatchedElementEndName => "mismatched element end name",
-            InvalidDecimalReference => "invalid decimal reference",
-            InvalidHexReference => "invalid hex reference",
-            UnknownNamedReference => "unknown named reference",
+        if let Some(what) = self.expectation() {
+            return write!(f, "expected {}", what);
+        }
+
+        if let LimitExceeded(limit) = *self {
+            return write!(f, "the {} is over the limit", limit);
+        }
+
+        let message = match *self {
+            InvalidProcessingInstructionTarget => "processing instruction target is reserved",
+            MismatchedElementEndName => "end tag does not match the start tag",
+            InvalidDecimalReference => "decimal character reference is not a character",
+            InvalidHexReference => "hexadecimal character reference is not a character",
+            UnknownNamedReference => "unknown entity",
+            RecursiveEntityReference => "entity refers to itself",
+            UnparsedEntityReference => "unparsed entity cannot be referenced",
+            UnresolvedExternalEntity => "external entity is not loaded",
+            ExternalEntityInAttributeValue => "attribute value refers to an external entity",
+            LessThanInAttributeValue => "entity in an attribute value contains '<'",
+            ParameterEntityInInternalSubset => {
+                "parameter entity reference inside a declaration in the internal subset"
+            }
             DuplicateAttribute => "duplicate attribute",
-            RedefinedNamespace => "redefined namespace",
-            RedefinedDefaultNamespace => "redefined default namespace",
-            EmptyNamespace => "empty namespace",
+            RedefinedNamespace => "namespace prefix is declared more than once",
+            RedefinedDefaultNamespace => "default namespace is declared more than once",
+            EmptyNamespace => "namespace prefix is bound to an empty URI",
+            DuplicateExpandedAttribute => "attributes with the same namespace and local name",
+            RedefinedXmlPrefix => "prefix 'xml' is bound to a URI other than the XML namespace",
+            DeclaredXmlnsPrefix => "prefix 'xmlns' cannot be declared",
+            ReservedNamespace => "namespace is reserved for the 'xml' or 'xmlns' prefix",
             UnknownNamespacePrefix => "unknown namespace prefix",
             UnclosedElement => "unclosed element",
-        }
+            UnsupportedEncoding => "unsupported encoding",
+            MismatchedEncoding => "encoding does not match the declared encoding",
+            InvalidEncodedData => "invalid data for the encoding",
+            InvalidCharacter => "character is not allowed in XML",
+            EntityWithMarkup => "entity contains markup, which an event reader cannot expand",
+            _ => "malformed XML",
+        };
+
+        f.write_str(message)
     }
 }
 
-type XmlMaster<'a> = peresil::ParseMaster<StringPoint<'a>, SpecificError>;
-type XmlProgress<'a, T> = peresil::Progress<StringPoint<'a>, T, SpecificError>;
+impl error::Error for ErrorKind {}
+
+type XmlMaster<'a> = peresil::ParseMaster<StringPoint<'a>, ErrorKind>;
+type XmlProgress<'a, T> = peresil::Progress<StringPoint<'a>, T, ErrorKind>;
 
 fn success<T>(data: T, point: StringPoint<'_>) -> XmlProgress<'_, T> {
     peresil::Progress {
@@ -217,6 +366,13 @@ impl<T> Span<T> {
             offset: self.offset,
         }
     }
+
+    fn at(self, offset: usize) -> Span<T> {
+        Span {
+            value: self.value,
+            offset,
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -226,6 +382,16 @@ enum Reference<'a> {
     HexChar(Span<&'a str>),
 }
 
+impl<'a> Reference<'a> {
+    fn at(self, offset: usize) -> Reference<'a> {
+        match self {
+            Entity(span) => Entity(span.at(offset)),
+            DecimalChar(span) => DecimalChar(span.at(offset)),
+            HexChar(span) => HexChar(span.at(offset)),
+        }
+    }
+}
+
 /// Common reusable XML parsing methods
 pub trait XmlParseExt<'a> {
     /// Parse XML whitespace
@@ -274,11 +440,16 @@ impl<'a> XmlParseExt<'a> for StringPoint<'a> {
 
 trait PrivateXmlParseExt<'a> {
     fn consume_attribute_value(&self, quote: &str) -> XmlProgress<'a, &'a str>;
+    fn consume_system_literal(&self, quote: &str) -> XmlProgress<'a, &'a str>;
+    fn consume_pubid_literal(&self, quote: &str) -> XmlProgress<'a, &'a str>;
+    fn consume_entity_value(&self, quote: &str) -> XmlProgress<'a, &'a str>;
     fn consume_name(&self) -> peresil::Progress<StringPoint<'a>, &'a str, ()>;
+    fn consume_nmtoken(&self) -> XmlProgress<'a, &'a str>;
     fn consume_hex_chars(&self) -> XmlProgress<'a, &'a str>;
     fn consume_char_data(&self) -> XmlProgress<'a, &'a str>;
     fn consume_cdata(&self) -> XmlProgress<'a, &'a str>;
     fn consume_int_subset(&self) -> XmlProgress<'a, &'a str>;
+    fn consume_declaration(&self) -> XmlProgress<'a, &'a str>;
     fn consume_comment(&self) -> XmlProgress<'a, &'a str>;
     fn consume_pi_value(&self) -> XmlProgress<'a, &'a str>;
     fn consume_start_tag(&self) -> XmlProgress<'a, &'a str>;
@@ -288,51 +459,76 @@ trait PrivateXmlParseExt<'a> {
 impl<'a> PrivateXmlParseExt<'a> for StringPoint<'a> {
     fn consume_attribute_value(&self, quote: &str) -> XmlProgress<'a, &'a str> {
         self.consume_to(self.s.end_of_attribute(quote))
-            .map_err(|_| SpecificError::ExpectedAttributeValue)
+            .map_err(|_| ErrorKind::ExpectedAttributeValue)
+    }
+
+    fn consume_system_literal(&self, quote: &str) -> XmlProgress<'a, &'a str> {
+        self.consume_to(self.s.end_of_system_literal(quote))
+            .map_err(|_| ErrorKind::ExpectedSystemLiteral)
+    }
+
+    fn consume_pubid_literal(&self, quote: &str) -> XmlProgress<'a, &'a str> {
+        self.consume_to(self.s.end_of_pubid_literal(quote))
+            .map_err(|_| ErrorKind::ExpectedPublicIdLiteral)
+    }
+
+    fn consume_entity_value(&self, quote: &str) -> XmlProgress<'a, &'a str> {
+        self.consume_to(self.s.end_of_entity_value(quote))
+            .map_err(|_| ErrorKind::ExpectedEntityValue)
     }
 
     fn consume_name(&self) -> peresil::Progress<StringPoint<'a>, &'a str, ()> {
         self.consume_to(self.s.end_of_name())
     }
 
+    fn consume_nmtoken(&self) -> XmlProgress<'a, &'a str> {
+        self.consume_to(self.s.end_of_nmtoken())
+            .map_err(|_| ErrorKind::ExpectedNameToken)
+    }
+
     fn consume_hex_chars(&self) -> XmlProgress<'a, &'a str> {
         self.consume_to(self.s.end_of_hex_chars())
-            .map_err(|_| SpecificError::ExpectedHexReferenceValue)
+            .map_err(|_| ErrorKind::ExpectedHexReferenceValue)
     }
 
     fn consume_char_data(&self) -> XmlProgress<'a, &'a str> {
         self.consume_to(self.s.end_of_char_data())
-            .map_err(|_| SpecificError::ExpectedCharacterData)
+            .map_err(|_| ErrorKind::ExpectedCharacterData)
     }
 
     fn consume_cdata(&self) -> XmlProgress<'a, &'a str> {
         self.consume_to(self.s.end_of_cdata())
-            .map_err(|_| SpecificError::ExpectedCData)
+            .map_err(|_| ErrorKind::ExpectedCData)
     }
 
     fn consume_int_subset(&self) -> XmlProgress<'a, &'a str> {
         self.consume_to(self.s.end_of_int_subset())
-            .map_err(|_| SpecificError::ExpectedIntSubset)
+            .map_err(|_| ErrorKind::ExpectedIntSubset)
+    }
+
+    fn consume_declaration(&self) -> XmlProgress<'a, &'a str> {
+        self.consume_to(self.s.end_of_declaration())
+            .map_err(|_| ErrorKind::Expected(">"))
     }
 
     fn consume_comment(&self) -> XmlProgress<'a, &'a str> {
         self.consume_to(self.s.end_of_comment())
-            .map_err(|_| SpecificError::ExpectedCommentBody)
+            .map_err(|_| ErrorKind::ExpectedCommentBody)
     }
 
     fn consume_pi_value(&self) -> XmlProgress<'a, &'a str> {
         self.consume_to(self.s.end_of_pi_value())
-            .map_err(|_| SpecificError::ExpectedProcessingInstructionValue)
+            .map_err(|_| ErrorKind::ExpectedProcessingInstructionValue)
     }
 
     fn consume_start_tag(&self) -> XmlProgress<'a, &'a str> {
         self.consume_to(self.s.end_of_start_tag())
-            .map_err(|_| SpecificError::ExpectedElement)
+            .map_err(|_| ErrorKind::ExpectedElement)
     }
 
     fn consume_encoding(&self) -> XmlProgress<'a, &'a str> {
         self.consume_to(self.s.end_of_encoding())
-            .map_err(|_| SpecificError::ExpectedEncoding)
+            .map_err(|_| ErrorKind::ExpectedEncoding)
     }
 }
 
@@ -344,19 +540,23 @@ trait X<'a> {
 impl<'a> X<'a> for StringPoint<'a> {
     fn expect_space(&self) -> XmlProgress<'a, &'a str> {
         self.consume_space()
-            .map_err(|_| SpecificError::ExpectedWhitespace)
+            .map_err(|_| ErrorKind::ExpectedWhitespace)
     }
 
     fn expect_literal(&self, s: &'static str) -> XmlProgress<'a, &'a str> {
-        self.consume_literal(s)
-            .map_err(|_| SpecificError::Expected(s))
+        self.consume_literal(s).map_err(|_| ErrorKind::Expected(s))
     }
 }
 
 #[derive(Debug, Copy, Clone)]
 enum Token<'a> {
-    XmlDeclaration,
-    DocumentTypeDeclaration,
+    XmlDeclaration(&'a str, Option<Span<&'a str>>, Option<&'a str>),
+    DocumentTypeDeclaration(
+        &'a str,
+        Option<&'a str>,
+        Option<Span<&'a str>>,
+        Option<Span<&'a str>>,
+    ),
     Comment(&'a str),
     ProcessingInstruction(&'a str, Option<&'a str>),
     Whitespace(&'a str),
@@ -373,37 +573,215 @@ enum Token<'a> {
     ContentReference(Reference<'a>),
 }
 
+impl<'a> Token<'a> {
+    /// Copies the borrowed text into the document so that the token
+    /// no longer refers to the input.
+    fn intern<'d>(self, doc: dom::Document<'d>) -> Token<'d> {
+        use self::Token::*;
+
+        let s = |s: &str| doc.intern(s);
+        let name = |n: Span<PrefixedName<'_>>| {
+            n.map(|n| PrefixedName::with_prefix(n.prefix.map(s), s(n.local_part)))
+        };
+        let reference = |r: Reference<'_>| match r {
+            Entity(span) => Entity(span.map(s)),
+            DecimalChar(span) => DecimalChar(span.map(s)),
+            HexChar(span) => HexChar(span.map(s)),
+        };
+
+        match self {
+            XmlDeclaration(v, e, sa) => XmlDeclaration(s(v), e.map(|e| e.map(s)), sa.map(s)),
+            DocumentTypeDeclaration(n, public_id, system_id, subset) => {
+                let subset = subset.map(|subset| subset.map(s));
+                let system_id = system_id.map(|id| id.map(s));
+                DocumentTypeDeclaration(s(n), public_id.map(s), system_id, subset)
+            }
+            Comment(c) => Comment(s(c)),
+            ProcessingInstruction(t, v) => ProcessingInstruction(s(t), v.map(s)),
+            Whitespace(w) => Whitespace(s(w)),
+            ElementStart(n) => ElementStart(name(n)),
+            ElementStartClose => ElementStartClose,
+            ElementSelfClose => ElementSelfClose,
+            ElementClose(n) => ElementClose(name(n)),
+            AttributeStart(n, q) => AttributeStart(name(n), q),
+            AttributeEnd => AttributeEnd,
+            LiteralAttributeValue(v) => LiteralAttributeValue(s(v)),
+            ReferenceAttributeValue(r) => ReferenceAttributeValue(reference(r)),
+            CharData(t) => CharData(s(t)),
+            CData(t) => CData(s(t)),
+            ContentReference(r) => ContentReference(reference(r)),
+        }
+    }
+
+    /// Moves every position in the token to `offset`. Tokens read from
+    /// the replacement text of an entity are reported at the reference.
+    fn at(self, offset: usize) -> Token<'a> {
+        use self::Token::*;
+
+        match self {
+            XmlDeclaration(v, e, sa) => XmlDeclaration(v, e.map(|e| e.at(offset)), sa),
+            DocumentTypeDeclaration(n, public_id, system_id, subset) => DocumentTypeDeclaration(
+                n,
+                public_id,
+                system_id.map(|s| s.at(offset)),
+                subset.map(|s| s.at(offset)),
+            ),
+            ElementStart(n) => ElementStart(n.at(offset)),
+            ElementClose(n) => ElementClose(n.at(offset)),
+            AttributeStart(n, q) => AttributeStart(n.at(offset), q),
+            ReferenceAttributeValue(r) => ReferenceAttributeValue(r.at(offset)),
+            ContentReference(r) => ContentReference(r.at(offset)),
+            t => t,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 enum State {
     AtBeginning,
     AfterDeclaration,
+    AfterDocumentType,
     AfterElementStart(usize),
     AfterAttributeStart(usize, &'static str),
     Content(usize),
     AfterMainElement,
+    /// Between the elements of a fragment of content
+    Fragment,
 }
 
+type TokenResult<'a> = Result<Token<'a>, (usize, Vec<ErrorKind>)>;
+
+/// A parsed token along with the position and state to continue from
+type Step<'a> = (TokenResult<'a>, StringPoint<'a>, State);
+
 #[derive(Debug)]
 struct PullParser<'a> {
     pm: XmlMaster<'a>,
     xml: StringPoint<'a>,
     state: State,
+    fragment: bool,
+    peeked: Option<Option<Step<'a>>>,
 }
 
 impl<'a> PullParser<'a> {
     fn new(xml: &str) -> PullParser<'_> {
+        PullParser::resume(xml, 0, State::AtBeginning)
+    }
+
+    /// Continues parsing a document in the given state. `xml` is the
+    /// remaining input, which starts at `offset` in the document.
+    fn resume(xml: &str, offset: usize, state: State) -> PullParser<'_> {
         PullParser {
             pm: ParseMaster::new(),
-            xml: StringPoint::new(xml),
-            state: State::AtBeginning,
+            xml: StringPoint { s: xml, offset },
+            state,
+            fragment: false,
+            peeked: None,
+        }
+    }
+
+    /// Parses content that may appear inside of an element, such as
+    /// the replacement text of an entity. Instead of an error, the
+    /// parser stops at the end of the input, even if elements are
+    /// left open.
+    fn fragment(xml: &str) -> PullParser<'_> {
+        PullParser {
+            fragment: true,
+            ..PullParser::resume(xml, 0, State::Fragment)
+        }
+    }
+
+    /// Looks at the next token without consuming it. The position and
+    /// state of the parser are not changed.
+    fn peek(&mut self) -> Option<&TokenResult<'a>> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.step());
         }
+
+        match self.peeked {
+            Some(Some((ref token, _, _))) => Some(token),
+            _ => None,
+        }
+    }
+
+    /// Skips the input that could not be parsed, so that parsing can
+    /// continue after an error. Returns the tokens that stand in for
+    /// the skipped input, or `None` if there is nothing left to skip.
+    ///
+    /// In content, the skipped input is kept as text. In a start tag,
+    /// the rest of the tag is skipped.
+    fn recover(&mut self) -> Option<Vec<Token<'a>>> {
+        let s = self.xml.s;
+        let until_markup = |markup: &[char]| {
+            let start = s.chars().next().map_or(0, char::len_utf8);
+            s[start..].find(markup).map_or(s.len(), |i| start + i)
+        };
+
+        let (len, tokens, state) = match self.state {
+            // The tag is closed even at the end of the input
+            State::AfterElementStart(d) | State::AfterAttributeStart(d, _) => {
+                let len = s.find('>').map_or(s.len(), |i| i + 1);
+                let mut tokens = Vec::new();
+
+                if let State::AfterAttributeStart(..) = self.state {
+                    tokens.push(Token::AttributeEnd);
+                }
+
+                if s[..len].ends_with("/>") {
+                    tokens.push(Token::ElementSelfClose);
+                    let state = match d {
+                        0 if self.fragment => State::Fragment,
+                        0 => State::AfterMainElement,
+                        d => State::Content(d - 1),
+                    };
+                    (len, tokens, state)
+                } else {
+                    tokens.push(Token::ElementStartClose);
+                    (len, tokens, State::Content(d))
+                }
+            }
+            _ if s.is_empty() => return None,
+            State::Content(..) | State::Fragment => {
+                let len = until_markup(&['<', '&']);
+                (len, vec![Token::CharData(&s[..len])], self.state)
+            }
+            state => (until_markup(&['<']), Vec::new(), state),
+        };
+
+        self.peeked = None;
+        self.xml = StringPoint {
+            s: &s[len..],
+            offset: self.xml.offset + len,
+        };
+        self.state = state;
+        Some(tokens)
+    }
+
+    /// Continues in content after recovering from an error by closing
+    /// elements, leaving `open_elements` open
+    fn resume_content(&mut self, open_elements: usize) {
+        self.peeked = None;
+        self.state = match open_elements {
+            0 => State::AfterMainElement,
+            n => State::Content(n - 1),
+        };
+    }
+
+    /// The offset in the document of the first unconsumed character
+    fn offset(&self) -> usize {
+        self.xml.offset
+    }
+
+    /// The offset in the document of the end of the input
+    fn end_offset(&self) -> usize {
+        self.xml.offset + self.xml.s.len()
     }
 }
 
 fn parse_comment<'a>(xml: StringPoint<'a>) -> XmlProgress<'a, Token<'_>> {
     let (xml, _) = try_parse!(xml
         .consume_literal("<!--")
-        .map_err(|_| SpecificError::ExpectedComment));
+        .map_err(|_| ErrorKind::ExpectedComment));
     let (xml, text) = try_parse!(xml.consume_comment());
     let (xml, _) = try_parse!(xml.expect_literal("-->"));
 
@@ -421,11 +799,11 @@ where
     let mut f = f;
     let (xml, _) = try_parse!(xml
         .consume_literal(quote)
-        .map_err(|_| SpecificError::ExpectedOpeningQuote(quote)));
+        .map_err(|_| ErrorKind::ExpectedOpeningQuote(quote)));
     let (xml, value) = try_parse!(f(xml));
     let (xml, _) = try_parse!(xml
         .consume_literal(quote)
-        .map_err(|_| SpecificError::ExpectedClosingQuote(quote)));
+        .map_err(|_| ErrorKind::ExpectedClosingQuote(quote)));
 
     success(value, xml)
 }
@@ -470,7 +848,7 @@ fn parse_version_info<'a>(
     let (xml, _) = try_parse!(xml.expect_literal("version"));
     let (xml, _) = try_parse!(parse_eq(xml));
     let (xml, version) = try_parse!(parse_quoted_value(pm, xml, |_, xml, _| version_num(xml)
-        .map_err(|_| SpecificError::ExpectedVersionNumber)));
+        .map_err(|_| ErrorKind::ExpectedVersionNumber)));
 
     success(version, xml)
 }
@@ -478,12 +856,14 @@ fn parse_version_info<'a>(
 fn parse_encoding_declaration<'a>(
     pm: &mut XmlMaster<'a>,
     xml: StringPoint<'a>,
-) -> XmlProgress<'a, &'a str> {
+) -> XmlProgress<'a, Span<&'a str>> {
     let (xml, _) = try_parse!(xml.expect_space());
     let (xml, _) = try_parse!(xml.expect_literal("encoding"));
     let (xml, _) = try_parse!(parse_eq(xml));
-    let (xml, encoding) =
-        try_parse!(parse_quoted_value(pm, xml, |_, xml, _| xml.consume_encoding()));
+    let (xml, encoding) = try_parse!(parse_quoted_value(pm, xml, |_, xml, _| Span::parse(
+        xml,
+        |xml| xml.consume_encoding()
+    )));
 
     success(encoding, xml)
 }
@@ -500,7 +880,7 @@ fn parse_standalone_declaration<'a>(
             .one(|_| xml.expect_literal("yes"))
             .one(|_| xml.expect_literal("no"))
             .finish()
-            .map_err(|_| SpecificError::ExpectedYesNo)
+            .map_err(|_| ErrorKind::ExpectedYesNo)
     }));
 
     success(standalone, xml)
@@ -511,40 +891,92 @@ fn parse_xml_declaration<'a>(
     xml: StringPoint<'a>,
 ) -> XmlProgress<'a, Token<'a>> {
     let (xml, _) = try_parse!(xml.expect_literal("<?xml"));
-    let (xml, _version) = try_parse!(parse_version_info(pm, xml));
-    let (xml, _encoding) =
+    let (xml, version) = try_parse!(parse_version_info(pm, xml));
+    let (xml, encoding) =
         try_parse!(pm.optional(xml, |pm, xml| { parse_encoding_declaration(pm, xml) }));
-    let (xml, _standalone) =
+    let (xml, standalone) =
         try_parse!(pm.optional(xml, |pm, xml| { parse_standalone_declaration(pm, xml) }));
     let (xml, _) = xml.consume_space().optional(xml);
     let (xml, _) = try_parse!(xml.expect_literal("?>"));
 
-    success(Token::XmlDeclaration, xml)
+    success(Token::XmlDeclaration(version, encoding, standalone), xml)
+}
+
+/// Parses the declaration that may start an external entity
+fn parse_text_declaration<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, Span<&'a str>> {
+    let (xml, _) = try_parse!(xml.expect_literal("<?xml"));
+    let (xml, _) = try_parse!(pm.optional(xml, |pm, xml| parse_version_info(pm, xml)));
+    let (xml, encoding) = try_parse!(parse_encoding_declaration(pm, xml));
+    let (xml, _) = xml.consume_space().optional(xml);
+    let (xml, _) = try_parse!(xml.expect_literal("?>"));
+
+    success(encoding, xml)
 }
 
-/* only the SYSTEM variant */
-fn parse_external_id<'a>(pm: &mut XmlMaster<'a>, xml: StringPoint<'a>) -> XmlProgress<'a, &'a str> {
+fn parse_system_literal<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, Span<&'a str>> {
     let (xml, _) = try_parse!(xml.expect_space());
+    parse_quoted_value(pm, xml, |_, xml, quote| {
+        Span::parse(xml, |xml| xml.consume_system_literal(quote))
+    })
+}
+
+fn parse_system_id<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, (Option<&'a str>, Span<&'a str>)> {
     let (xml, _) = try_parse!(xml.expect_literal("SYSTEM"));
+    let (xml, system_id) = try_parse!(parse_system_literal(pm, xml));
+
+    success((None, system_id), xml)
+}
+
+fn parse_public_id<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, (Option<&'a str>, Span<&'a str>)> {
+    let (xml, _) = try_parse!(xml.expect_literal("PUBLIC"));
+    let (xml, _) = try_parse!(xml.expect_space());
+    let (xml, public_id) =
+        try_parse!(parse_quoted_value(pm, xml, |_, xml, quote| xml.consume_pubid_literal(quote)));
+    let (xml, system_id) = try_parse!(parse_system_literal(pm, xml));
+
+    success((Some(public_id), system_id), xml)
+}
+
+/// Parses an [ExternalID](https://www.w3.org/TR/xml/#NT-ExternalID),
+/// returning the public identifier, if any, and the system identifier.
+fn parse_external_id<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, (Option<&'a str>, Span<&'a str>)> {
     let (xml, _) = try_parse!(xml.expect_space());
-    let (xml, external_id) = try_parse!(parse_quoted_value(pm, xml, |_, xml, quote| xml
-        .consume_attribute_value(quote)
-        .map_err(|_| SpecificError::ExpectedSystemLiteral)));
 
-    success(external_id, xml)
+    pm.alternate()
+        .one(|pm| parse_system_id(pm, xml))
+        .one(|pm| parse_public_id(pm, xml))
+        .finish()
 }
 
-fn parse_int_subset<'a>(_pm: &mut XmlMaster<'a>, xml: StringPoint<'a>) -> XmlProgress<'a, &'a str> {
+fn parse_int_subset<'a>(
+    _pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, Span<&'a str>> {
     let (xml, _) = try_parse!(xml.expect_literal("["));
     let (xml, _) = xml.consume_space().optional(xml);
-    let (xml, elements) = try_parse!(xml
-        .consume_int_subset()
-        .map_err(|_| SpecificError::ExpectedIntSubset));
-    let (xml, _) = xml.consume_space().optional(xml);
+    let (xml, elements) = try_parse!(Span::parse(xml, |xml| xml.consume_int_subset()));
     let (xml, _) = try_parse!(xml.expect_literal("]"));
     let (xml, _) = xml.consume_space().optional(xml);
 
-    success(elements, xml)
+    success(
+        elements.map(|e| e.trim_end_matches(XmlChar::is_space_char)),
+        xml,
+    )
 }
 
 fn parse_document_type_declaration<'a>(
@@ -553,145 +985,567 @@ fn parse_document_type_declaration<'a>(
 ) -> XmlProgress<'a, Token<'a>> {
     let (xml, _) = try_parse!(xml.expect_literal("<!DOCTYPE"));
     let (xml, _) = try_parse!(xml.expect_space());
-    let (xml, _type_name) = try_parse!(xml
+    let (xml, type_name) = try_parse!(xml
         .consume_name()
-        .map_err(|_| SpecificError::ExpectedDocumentTypeName));
-    let (xml, _id) = try_parse!(pm.optional(xml, |p, x| parse_external_id(p, x)));
+        .map_err(|_| ErrorKind::ExpectedDocumentTypeName));
+    let (xml, id) = try_parse!(pm.optional(xml, |p, x| parse_external_id(p, x)));
     let (xml, _) = xml.consume_space().optional(xml);
-    let (xml, _int_subset) = try_parse!(pm.optional(xml, |p, x| parse_int_subset(p, x)));
+    let (xml, int_subset) = try_parse!(pm.optional(xml, |p, x| parse_int_subset(p, x)));
     let (xml, _) = try_parse!(xml.expect_literal(">"));
 
-    success(Token::DocumentTypeDeclaration, xml)
+    let (public_id, system_id) = match id {
+        Some((public_id, system_id)) => (public_id, Some(system_id)),
+        None => (None, None),
+    };
+
+    success(
+        Token::DocumentTypeDeclaration(type_name, public_id, system_id, int_subset),
+        xml,
+    )
 }
 
-fn parse_pi_value(xml: StringPoint<'_>) -> XmlProgress<'_, &str> {
-    let (xml, _) = try_parse!(xml.expect_space());
-    xml.consume_pi_value()
+/// A declaration, or something that may appear between declarations,
+/// in the internal subset
+#[derive(Debug)]
+enum Declaration<'a> {
+    Entity(Span<&'a str>, EntityDefinition<'a>),
+    ParameterEntity(Span<&'a str>, EntityDefinition<'a>),
+    ParameterEntityReference(Span<&'a str>),
+    AttributeList(Span<&'a str>, Vec<AttributeDefinition<'a>>),
+    Element(Span<&'a str>, dtd::ContentSpec),
+    Ignored,
 }
 
-fn parse_pi<'a>(xml: StringPoint<'a>) -> XmlProgress<'a, Token<'_>> {
-    let (xml, _) = try_parse!(xml
-        .consume_literal("<?")
-        .map_err(|_| SpecificError::ExpectedProcessingInstruction));
-    let target_xml = xml;
-    let (xml, target) = try_parse!(xml
-        .consume_name()
-        .map_err(|_| SpecificError::ExpectedProcessingInstructionTarget));
-    let (xml, value) = parse_pi_value(xml).optional(xml);
-    let (xml, _) = try_parse!(xml.expect_literal("?>"));
+#[derive(Debug)]
+struct AttributeDefinition<'a> {
+    name: Span<&'a str>,
+    attribute_type: dtd::AttributeType,
+    default_value: DefaultDeclaration<'a>,
+}
 
-    if target.eq_ignore_ascii_case("xml") {
-        return peresil::Progress::failure(
-            target_xml,
-            SpecificError::InvalidProcessingInstructionTarget,
-        );
-    }
+#[derive(Debug)]
+enum DefaultDeclaration<'a> {
+    Required,
+    Implied,
+    Fixed(Vec<AttributeValue<'a>>),
+    Default(Vec<AttributeValue<'a>>),
+}
 
-    success(Token::ProcessingInstruction(target, value), xml)
+#[derive(Debug)]
+enum EntityDefinition<'a> {
+    Value(Vec<EntityValue<'a>>),
+    External(Option<&'a str>, &'a str, Option<&'a str>),
 }
 
-fn parse_element_start(xml: StringPoint<'_>) -> XmlProgress<'_, Token<'_>> {
-    let (xml, _) = try_parse!(xml.consume_start_tag());
+#[derive(Debug)]
+enum EntityValue<'a> {
+    Literal(&'a str),
+    Reference(Reference<'a>),
+    ParameterEntityReference(Span<&'a str>),
+}
+
+fn parse_parameter_entity_reference(xml: StringPoint<'_>) -> XmlProgress<'_, Span<&str>> {
+    let (xml, _) = try_parse!(xml.expect_literal("%"));
     let (xml, name) = try_parse!(Span::parse(xml, |xml| xml
-        .consume_prefixed_name()
-        .map_err(|_| SpecificError::ExpectedElementName)));
+        .consume_name()
+        .map_err(|_| ErrorKind::ExpectedNamedReferenceValue)));
+    let (xml, _) = try_parse!(xml.expect_literal(";"));
 
-    success(Token::ElementStart(name), xml)
+    success(name, xml)
 }
 
-fn parse_element_start_close(xml: StringPoint<'_>) -> XmlProgress<'_, Token<'_>> {
-    let (xml, _) = xml.expect_space().optional(xml);
+fn parse_entity_value<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, EntityDefinition<'a>> {
+    let (xml, _) = try_parse!(xml.expect_space());
+    let (xml, value) = try_parse!(parse_quoted_value(pm, xml, |pm, xml, quote| {
+        pm.zero_or_more(xml, |pm, xml| {
+            pm.alternate()
+                .one(|_| xml.consume_entity_value(quote).map(EntityValue::Literal))
+                .one(|pm| parse_reference(pm, xml).map(EntityValue::Reference))
+                .one(|_| {
+                    parse_parameter_entity_reference(xml).map(EntityValue::ParameterEntityReference)
+                })
+                .finish()
+        })
+    }));
 
-    xml.consume_literal(">")
-        .map(|_| Token::ElementStartClose)
-        .map_err(|_| SpecificError::ExpectedElementEnd)
+    success(EntityDefinition::Value(value), xml)
 }
 
-fn parse_element_self_close(xml: StringPoint<'_>) -> XmlProgress<'_, Token<'_>> {
-    let (xml, _) = xml.expect_space().optional(xml);
-
-    xml.consume_literal("/>")
-        .map(|_| Token::ElementSelfClose)
-        .map_err(|_| SpecificError::ExpectedElementSelfClosed)
+fn parse_notation_declaration(xml: StringPoint<'_>) -> XmlProgress<'_, &str> {
+    let (xml, _) = try_parse!(xml.expect_space());
+    let (xml, _) = try_parse!(xml.expect_literal("NDATA"));
+    let (xml, _) = try_parse!(xml.expect_space());
+    xml.consume_name()
+        .map_err(|_| ErrorKind::ExpectedNotationName)
 }
 
-fn parse_element_close(xml: StringPoint<'_>) -> XmlProgress<'_, Token<'_>> {
-    let (xml, _) = try_parse!(xml.expect_literal("</"));
+fn parse_external_entity<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+    parameter: bool,
+) -> XmlProgress<'a, EntityDefinition<'a>> {
+    let (xml, (public_id, system_id)) = try_parse!(parse_external_id(pm, xml));
+    let system_id = system_id.value;
+    let (xml, notation) = if parameter {
+        (xml, None)
+    } else {
+        try_parse!(pm.optional(xml, |_, xml| parse_notation_declaration(xml)))
+    };
+
+    success(
+        EntityDefinition::External(public_id, system_id, notation),
+        xml,
+    )
+}
 
+fn parse_entity_declaration<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, Declaration<'a>> {
+    let (xml, _) = try_parse!(xml.expect_literal("<!ENTITY"));
+    let (xml, _) = try_parse!(xml.expect_space());
+    let (xml, parameter) = try_parse!(pm.optional(xml, |_, xml| {
+        let (xml, _) = try_parse!(xml.expect_literal("%"));
+        xml.expect_space()
+    }));
+    let parameter = parameter.is_some();
     let (xml, name) = try_parse!(Span::parse(xml, |xml| xml
-        .consume_prefixed_name()
-        .map_err(|_| SpecificError::ExpectedElementName)));
-
+        .consume_name()
+        .map_err(|_| ErrorKind::ExpectedNamedReferenceValue)));
+    let (xml, definition) = try_parse!(pm
+        .alternate()
+        .one(|pm| parse_entity_value(pm, xml))
+        .one(|pm| parse_external_entity(pm, xml, parameter))
+        .finish());
     let (xml, _) = xml.consume_space().optional(xml);
     let (xml, _) = try_parse!(xml.expect_literal(">"));
 
-    success(Token::ElementClose(name), xml)
-}
+    let declaration = if parameter {
+        Declaration::ParameterEntity(name, definition)
+    } else {
+        Declaration::Entity(name, definition)
+    };
 
-const QUOT: &str = r#"""#;
-const APOS: &str = r#"'"#;
+    success(declaration, xml)
+}
 
-fn parse_attribute_start<'a>(
+/// Parses `(a | b | c)`, where each item is read by `item`
+fn parse_enumerated_values<'a, F>(
     pm: &mut XmlMaster<'a>,
     xml: StringPoint<'a>,
-) -> XmlProgress<'a, Token<'a>> {
-    let (xml, _) = try_parse!(xml.expect_space());
-
-    let (xml, name) = try_parse!(Span::parse(xml, |xml| xml
-        .consume_prefixed_name()
-        .map_err(|_| SpecificError::ExpectedAttribute)));
-
-    let (xml, _) = try_parse!(parse_eq(xml));
+    item: F,
+) -> XmlProgress<'a, Vec<String>>
+where
+    F: Fn(StringPoint<'a>) -> XmlProgress<'a, &'a str>,
+{
+    let (xml, _) = try_parse!(xml.expect_literal("("));
+    let (xml, _) = xml.consume_space().optional(xml);
+    let (xml, first) = try_parse!(item(xml));
+    let (xml, rest) = try_parse!(pm.zero_or_more(xml, |_, xml| {
+        let (xml, _) = xml.consume_space().optional(xml);
+        let (xml, _) = try_parse!(xml.expect_literal("|"));
+        let (xml, _) = xml.consume_space().optional(xml);
+        item(xml)
+    }));
+    let (xml, _) = xml.consume_space().optional(xml);
+    let (xml, _) = try_parse!(xml.expect_literal(")"));
 
-    let (xml, q) = try_parse!(pm
-        .alternate()
-        .one(|_| xml
-            .expect_literal(QUOT)
-            .map_err(|_| SpecificError::ExpectedOpeningQuote(QUOT)))
-        .one(|_| xml
-            .expect_literal(APOS)
-            .map_err(|_| SpecificError::ExpectedOpeningQuote(APOS)))
-        .finish());
+    let values = iter::once(first).chain(rest).map(Into::into).collect();
+    success(values, xml)
+}
 
-    let q = if q == QUOT { QUOT } else { APOS };
+fn parse_attribute_type<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, dtd::AttributeType> {
+    use crate::dtd::AttributeType::*;
+
+    // Longer keywords come first, as IDREFS starts with ID
+    let keywords = [
+        ("CDATA", CData),
+        ("IDREFS", IdRefs),
+        ("IDREF", IdRef),
+        ("ID", Id),
+        ("ENTITIES", Entities),
+        ("ENTITY", Entity),
+        ("NMTOKENS", NmTokens),
+        ("NMTOKEN", NmToken),
+    ];
+
+    if let Some(&(keyword, ref attribute_type)) = keywords.iter().find(|k| xml.s.starts_with(k.0)) {
+        let (xml, _) = try_parse!(xml.expect_literal(keyword));
+        return success(attribute_type.clone(), xml);
+    }
 
-    success(Token::AttributeStart(name, q), xml)
+    pm.alternate()
+        .one(|pm| {
+            let (xml, _) = try_parse!(xml.expect_literal("NOTATION"));
+            let (xml, _) = try_parse!(xml.expect_space());
+            parse_enumerated_values(pm, xml, |xml| {
+                xml.consume_name()
+                    .map_err(|_| ErrorKind::ExpectedNotationName)
+            })
+            .map(Notation)
+        })
+        .one(|pm| parse_enumerated_values(pm, xml, |xml| xml.consume_nmtoken()).map(Enumeration))
+        .finish()
+        .map_err(|_| ErrorKind::ExpectedAttributeType)
 }
 
-fn parse_attribute_end<'a>(
+fn parse_default_value<'a>(
+    pm: &mut XmlMaster<'a>,
     xml: StringPoint<'a>,
-    quote: &'static str,
-) -> XmlProgress<'a, Token<'a>> {
-    xml.consume_literal(quote)
-        .map(|_| Token::AttributeEnd)
-        .map_err(|_| SpecificError::ExpectedClosingQuote(quote))
+) -> XmlProgress<'a, Vec<AttributeValue<'a>>> {
+    parse_quoted_value(pm, xml, |pm, xml, quote| {
+        pm.zero_or_more(xml, |pm, xml| {
+            pm.alternate()
+                .one(|_| {
+                    xml.consume_attribute_value(quote)
+                        .map(AttributeValue::LiteralAttributeValue)
+                })
+                .one(|pm| parse_reference(pm, xml).map(AttributeValue::ReferenceAttributeValue))
+                .finish()
+        })
+    })
 }
 
-fn parse_attribute_literal<'a>(xml: StringPoint<'a>, quote: &str) -> XmlProgress<'a, Token<'a>> {
-    let (xml, val) = try_parse!(xml.consume_attribute_value(quote));
-
-    success(Token::LiteralAttributeValue(val), xml)
+fn parse_default_declaration<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, DefaultDeclaration<'a>> {
+    pm.alternate()
+        .one(|_| {
+            xml.expect_literal("#REQUIRED")
+                .map(|_| DefaultDeclaration::Required)
+        })
+        .one(|_| {
+            xml.expect_literal("#IMPLIED")
+                .map(|_| DefaultDeclaration::Implied)
+        })
+        .one(|pm| {
+            let (xml, _) = try_parse!(xml.expect_literal("#FIXED"));
+            let (xml, _) = try_parse!(xml.expect_space());
+            parse_default_value(pm, xml).map(DefaultDeclaration::Fixed)
+        })
+        .one(|pm| parse_default_value(pm, xml).map(DefaultDeclaration::Default))
+        .finish()
 }
 
-fn parse_entity_ref(xml: StringPoint<'_>) -> XmlProgress<'_, Reference<'_>> {
-    let (xml, _) = try_parse!(xml
-        .consume_literal("&")
-        .map_err(|_| SpecificError::ExpectedNamedReference));
+fn parse_attribute_definition<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, AttributeDefinition<'a>> {
+    let (xml, _) = try_parse!(xml.expect_space());
     let (xml, name) = try_parse!(Span::parse(xml, |xml| xml
         .consume_name()
-        .map_err(|_| SpecificError::ExpectedNamedReferenceValue)));
-    let (xml, _) = try_parse!(xml.expect_literal(";"));
-
-    success(Entity(name), xml)
+        .map_err(|_| ErrorKind::ExpectedAttribute)));
+    let (xml, _) = try_parse!(xml.expect_space());
+    let (xml, attribute_type) = try_parse!(parse_attribute_type(pm, xml));
+    let (xml, _) = try_parse!(xml.expect_space());
+    let (xml, default_value) = try_parse!(parse_default_declaration(pm, xml));
+
+    let definition = AttributeDefinition {
+        name,
+        attribute_type,
+        default_value,
+    };
+    success(definition, xml)
 }
 
-fn parse_decimal_char_ref(xml: StringPoint<'_>) -> XmlProgress<'_, Reference<'_>> {
-    let (xml, _) = try_parse!(xml
-        .consume_literal("&#")
-        .map_err(|_| SpecificError::ExpectedDecimalReference));
-    let (xml, dec) = try_parse!(Span::parse(xml, |xml| xml
+fn parse_attribute_list_declaration<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, Declaration<'a>> {
+    let (xml, _) = try_parse!(xml.expect_literal("<!ATTLIST"));
+    let (xml, _) = try_parse!(xml.expect_space());
+    let (xml, element) = try_parse!(Span::parse(xml, |xml| xml
+        .consume_name()
+        .map_err(|_| ErrorKind::ExpectedElementName)));
+    let (xml, definitions) =
+        try_parse!(pm.zero_or_more(xml, |pm, xml| parse_attribute_definition(pm, xml)));
+    let (xml, _) = xml.consume_space().optional(xml);
+    let (xml, _) = try_parse!(xml.expect_literal(">"));
+
+    success(Declaration::AttributeList(element, definitions), xml)
+}
+
+fn parse_repetition(xml: StringPoint<'_>) -> XmlProgress<'_, dtd::Repetition> {
+    use crate::dtd::Repetition::*;
+
+    for &(symbol, repetition) in &[("?", Optional), ("*", ZeroOrMore), ("+", OneOrMore)] {
+        if let (xml, Some(_)) = xml.expect_literal(symbol).optional(xml) {
+            return success(repetition, xml);
+        }
+    }
+
+    success(Once, xml)
+}
+
+fn parse_content_particle<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, dtd::ContentParticle> {
+    let (xml, particle) = try_parse!(pm
+        .alternate()
+        .one(|_| {
+            xml.consume_name()
+                .map(|name| dtd::Particle::Name(name.into()))
+                .map_err(|_| ErrorKind::ExpectedElementName)
+        })
+        .one(|pm| parse_content_group(pm, xml))
+        .finish());
+    let (xml, repetition) = try_parse!(parse_repetition(xml));
+
+    success(dtd::ContentParticle::new(particle, repetition), xml)
+}
+
+/// Parses `(a, b, c)` or `(a | b | c)`
+fn parse_content_group<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, dtd::Particle> {
+    let (xml, _) = try_parse!(xml.expect_literal("("));
+    let (xml, _) = xml.consume_space().optional(xml);
+    let (xml, first) = try_parse!(parse_content_particle(pm, xml));
+
+    // The first separator decides what the rest must be separated by
+    let (next, _) = xml.consume_space().optional(xml);
+    let choice = next.s.starts_with('|');
+    let separator = if choice { "|" } else { "," };
+
+    let (xml, rest) = try_parse!(pm.zero_or_more(xml, |pm, xml| {
+        let (xml, _) = xml.consume_space().optional(xml);
+        let (xml, _) = try_parse!(xml.expect_literal(separator));
+        let (xml, _) = xml.consume_space().optional(xml);
+        parse_content_particle(pm, xml)
+    }));
+    let (xml, _) = xml.consume_space().optional(xml);
+    let (xml, _) = try_parse!(xml.expect_literal(")"));
+
+    let particles = iter::once(first).chain(rest).collect();
+    let particle = if choice {
+        dtd::Particle::Choice(particles)
+    } else {
+        dtd::Particle::Sequence(particles)
+    };
+    success(particle, xml)
+}
+
+/// Parses `(#PCDATA | a | b)*`
+fn parse_mixed_content<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, dtd::ContentSpec> {
+    let (xml, _) = try_parse!(xml.expect_literal("("));
+    let (xml, _) = xml.consume_space().optional(xml);
+    let (xml, _) = try_parse!(xml.expect_literal("#PCDATA"));
+    let (xml, names) = try_parse!(pm.zero_or_more(xml, |_, xml| {
+        let (xml, _) = xml.consume_space().optional(xml);
+        let (xml, _) = try_parse!(xml.expect_literal("|"));
+        let (xml, _) = xml.consume_space().optional(xml);
+        xml.consume_name()
+            .map_err(|_| ErrorKind::ExpectedElementName)
+    }));
+    let (xml, _) = xml.consume_space().optional(xml);
+    let (xml, _) = try_parse!(xml.expect_literal(")"));
+
+    // Only `(#PCDATA)` may leave out the `*`
+    let (xml, _) = if names.is_empty() {
+        xml.expect_literal("*").optional(xml)
+    } else {
+        let (xml, star) = try_parse!(xml.expect_literal("*"));
+        (xml, Some(star))
+    };
+
+    let names = names.into_iter().map(Into::into).collect();
+    success(dtd::ContentSpec::Mixed(names), xml)
+}
+
+fn parse_element_declaration<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, Declaration<'a>> {
+    let (xml, _) = try_parse!(xml.expect_literal("<!ELEMENT"));
+    let (xml, _) = try_parse!(xml.expect_space());
+    let (xml, name) = try_parse!(Span::parse(xml, |xml| xml
+        .consume_name()
+        .map_err(|_| ErrorKind::ExpectedElementName)));
+    let (xml, _) = try_parse!(xml.expect_space());
+    let (xml, content) = try_parse!(pm
+        .alternate()
+        .one(|_| { xml.expect_literal("EMPTY").map(|_| dtd::ContentSpec::Empty) })
+        .one(|_| xml.expect_literal("ANY").map(|_| dtd::ContentSpec::Any))
+        .one(|pm| parse_mixed_content(pm, xml))
+        .one(|pm| {
+            let (xml, particle) = try_parse!(parse_content_group(pm, xml));
+            let (xml, repetition) = try_parse!(parse_repetition(xml));
+            let particle = dtd::ContentParticle::new(particle, repetition);
+            success(dtd::ContentSpec::Children(particle), xml)
+        })
+        .finish());
+    let (xml, _) = xml.consume_space().optional(xml);
+    let (xml, _) = try_parse!(xml.expect_literal(">"));
+
+    success(Declaration::Element(name, content), xml)
+}
+
+/// Notation declarations are read but not yet used
+fn parse_ignored_declaration(xml: StringPoint<'_>) -> XmlProgress<'_, Declaration<'_>> {
+    let (xml, _) = try_parse!(xml.expect_literal("<!NOTATION"));
+    let (xml, _) = try_parse!(xml.expect_space());
+    let (xml, _) = try_parse!(xml.consume_declaration());
+    let (xml, _) = try_parse!(xml.expect_literal(">"));
+
+    success(Declaration::Ignored, xml)
+}
+
+fn parse_declaration<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, Declaration<'a>> {
+    pm.alternate()
+        .one(|pm| parse_entity_declaration(pm, xml))
+        .one(|pm| parse_attribute_list_declaration(pm, xml))
+        .one(|pm| parse_element_declaration(pm, xml))
+        .one(|_| parse_ignored_declaration(xml))
+        .one(|_| parse_comment(xml).map(|_| Declaration::Ignored))
+        .one(|_| parse_pi(xml).map(|_| Declaration::Ignored))
+        .one(|_| parse_parameter_entity_reference(xml).map(Declaration::ParameterEntityReference))
+        .one(|_| xml.expect_space().map(|_| Declaration::Ignored))
+        .finish()
+}
+
+fn parse_pi_value(xml: StringPoint<'_>) -> XmlProgress<'_, &str> {
+    let (xml, _) = try_parse!(xml.expect_space());
+    xml.consume_pi_value()
+}
+
+fn parse_pi<'a>(xml: StringPoint<'a>) -> XmlProgress<'a, Token<'_>> {
+    let (xml, _) = try_parse!(xml
+        .consume_literal("<?")
+        .map_err(|_| ErrorKind::ExpectedProcessingInstruction));
+    let target_xml = xml;
+    let (xml, target) = try_parse!(xml
+        .consume_name()
+        .map_err(|_| ErrorKind::ExpectedProcessingInstructionTarget));
+    let (xml, value) = parse_pi_value(xml).optional(xml);
+    let (xml, _) = try_parse!(xml.expect_literal("?>"));
+
+    if target.eq_ignore_ascii_case("xml") {
+        return peresil::Progress::failure(
+            target_xml,
+            ErrorKind::InvalidProcessingInstructionTarget,
+        );
+    }
+
+    success(Token::ProcessingInstruction(target, value), xml)
+}
+
+fn parse_element_start(xml: StringPoint<'_>) -> XmlProgress<'_, Token<'_>> {
+    let (xml, _) = try_parse!(xml.consume_start_tag());
+    let (xml, name) = try_parse!(Span::parse(xml, |xml| xml
+        .consume_prefixed_name()
+        .map_err(|_| ErrorKind::ExpectedElementName)));
+
+    success(Token::ElementStart(name), xml)
+}
+
+fn parse_element_start_close(xml: StringPoint<'_>) -> XmlProgress<'_, Token<'_>> {
+    let (xml, _) = xml.expect_space().optional(xml);
+
+    xml.consume_literal(">")
+        .map(|_| Token::ElementStartClose)
+        .map_err(|_| ErrorKind::ExpectedElementEnd)
+}
+
+fn parse_element_self_close(xml: StringPoint<'_>) -> XmlProgress<'_, Token<'_>> {
+    let (xml, _) = xml.expect_space().optional(xml);
+
+    xml.consume_literal("/>")
+        .map(|_| Token::ElementSelfClose)
+        .map_err(|_| ErrorKind::ExpectedElementSelfClosed)
+}
+
+fn parse_element_close(xml: StringPoint<'_>) -> XmlProgress<'_, Token<'_>> {
+    let (xml, _) = try_parse!(xml.expect_literal("</"));
+
+    let (xml, name) = try_parse!(Span::parse(xml, |xml| xml
+        .consume_prefixed_name()
+        .map_err(|_| ErrorKind::ExpectedElementName)));
+
+    let (xml, _) = xml.consume_space().optional(xml);
+    let (xml, _) = try_parse!(xml.expect_literal(">"));
+
+    success(Token::ElementClose(name), xml)
+}
+
+const QUOT: &str = r#"""#;
+const APOS: &str = r#"'"#;
+
+fn parse_attribute_start<'a>(
+    pm: &mut XmlMaster<'a>,
+    xml: StringPoint<'a>,
+) -> XmlProgress<'a, Token<'a>> {
+    let (xml, _) = try_parse!(xml.expect_space());
+
+    let (xml, name) = try_parse!(Span::parse(xml, |xml| xml
+        .consume_prefixed_name()
+        .map_err(|_| ErrorKind::ExpectedAttribute)));
+
+    let (xml, _) = try_parse!(parse_eq(xml));
+
+    let (xml, q) = try_parse!(pm
+        .alternate()
+        .one(|_| xml
+            .expect_literal(QUOT)
+            .map_err(|_| ErrorKind::ExpectedOpeningQuote(QUOT)))
+        .one(|_| xml
+            .expect_literal(APOS)
+            .map_err(|_| ErrorKind::ExpectedOpeningQuote(APOS)))
+        .finish());
+
+    let q = if q == QUOT { QUOT } else { APOS };
+
+    success(Token::AttributeStart(name, q), xml)
+}
+
+fn parse_attribute_end<'a>(
+    xml: StringPoint<'a>,
+    quote: &'static str,
+) -> XmlProgress<'a, Token<'a>> {
+    xml.consume_literal(quote)
+        .map(|_| Token::AttributeEnd)
+        .map_err(|_| ErrorKind::ExpectedClosingQuote(quote))
+}
+
+fn parse_attribute_literal<'a>(xml: StringPoint<'a>, quote: &str) -> XmlProgress<'a, Token<'a>> {
+    let (xml, val) = try_parse!(xml.consume_attribute_value(quote));
+
+    success(Token::LiteralAttributeValue(val), xml)
+}
+
+fn parse_entity_ref(xml: StringPoint<'_>) -> XmlProgress<'_, Reference<'_>> {
+    let (xml, _) = try_parse!(xml
+        .consume_literal("&")
+        .map_err(|_| ErrorKind::ExpectedNamedReference));
+    let (xml, name) = try_parse!(Span::parse(xml, |xml| xml
+        .consume_name()
+        .map_err(|_| ErrorKind::ExpectedNamedReferenceValue)));
+    let (xml, _) = try_parse!(xml.expect_literal(";"));
+
+    success(Entity(name), xml)
+}
+
+fn parse_decimal_char_ref(xml: StringPoint<'_>) -> XmlProgress<'_, Reference<'_>> {
+    let (xml, _) = try_parse!(xml
+        .consume_literal("&#")
+        .map_err(|_| ErrorKind::ExpectedDecimalReference));
+    let (xml, dec) = try_parse!(Span::parse(xml, |xml| xml
         .consume_decimal_chars()
-        .map_err(|_| SpecificError::ExpectedDecimalReferenceValue)));
+        .map_err(|_| ErrorKind::ExpectedDecimalReferenceValue)));
     let (xml, _) = try_parse!(xml.expect_literal(";"));
 
     success(DecimalChar(dec), xml)
@@ -700,7 +1554,7 @@ fn parse_decimal_char_ref(xml: StringPoint<'_>) -> XmlProgress<'_, Reference<'_>
 fn parse_hex_char_ref(xml: StringPoint<'_>) -> XmlProgress<'_, Reference<'_>> {
     let (xml, _) = try_parse!(xml
         .consume_literal("&#x")
-        .map_err(|_| SpecificError::ExpectedHexReference));
+        .map_err(|_| ErrorKind::ExpectedHexReference));
     let (xml, hex) = try_parse!(Span::parse(xml, |xml| xml.consume_hex_chars()));
     let (xml, _) = try_parse!(xml.expect_literal(";"));
 
@@ -747,17 +1601,20 @@ fn parse_content_reference<'a>(
     success(Token::ContentReference(r), xml)
 }
 
-impl<'a> Iterator for PullParser<'a> {
-    type Item = Result<Token<'a>, (usize, Vec<SpecificError>)>;
-
-    fn next(&mut self) -> Option<Self::Item> {
+impl<'a> PullParser<'a> {
+    fn step(&mut self) -> Option<Step<'a>> {
         let pm = &mut self.pm;
         let xml = self.xml;
 
+        if self.fragment && xml.is_empty() {
+            return None;
+        }
+
         let r = match self.state {
             State::AtBeginning => pm
                 .alternate()
                 .one(|pm| parse_xml_declaration(pm, xml))
+                .one(|pm| parse_document_type_declaration(pm, xml))
                 .one(|_| parse_element_start(xml))
                 .one(|_| xml.expect_space().map(Token::Whitespace))
                 .one(|_| parse_comment(xml))
@@ -773,6 +1630,14 @@ impl<'a> Iterator for PullParser<'a> {
                 .one(|_| parse_pi(xml))
                 .finish(),
 
+            State::AfterDocumentType => pm
+                .alternate()
+                .one(|_| parse_element_start(xml))
+                .one(|_| xml.expect_space().map(Token::Whitespace))
+                .one(|_| parse_comment(xml))
+                .one(|_| parse_pi(xml))
+                .finish(),
+
             State::AfterElementStart(..) => pm
                 .alternate()
                 .one(|pm| parse_attribute_start(pm, xml))
@@ -798,6 +1663,16 @@ impl<'a> Iterator for PullParser<'a> {
                 .one(|_| parse_pi(xml))
                 .finish(),
 
+            State::Fragment => pm
+                .alternate()
+                .one(|_| parse_element_start(xml))
+                .one(|_| parse_char_data(xml))
+                .one(|_| parse_cdata(xml))
+                .one(|pm| parse_content_reference(pm, xml))
+                .one(|_| parse_comment(xml))
+                .one(|_| parse_pi(xml))
+                .finish(),
+
             State::AfterMainElement => {
                 if xml.is_empty() {
                     return None;
@@ -820,7 +1695,7 @@ impl<'a> Iterator for PullParser<'a> {
                 status: peresil::Status::Failure(e),
                 point,
             } => {
-                return Some(Err((point.offset, e)));
+                return Some((Err((point.offset, e)), xml, self.state));
             }
         };
 
@@ -829,18 +1704,26 @@ impl<'a> Iterator for PullParser<'a> {
         }
 
         let next_state = match (self.state, r) {
-            (State::AtBeginning, Token::XmlDeclaration)
+            (State::AtBeginning, Token::XmlDeclaration(..))
             | (State::AtBeginning, Token::ProcessingInstruction(..))
             | (State::AtBeginning, Token::Comment(..))
             | (State::AtBeginning, Token::Whitespace(..)) => State::AfterDeclaration,
+            (State::AtBeginning, Token::DocumentTypeDeclaration(..)) => State::AfterDocumentType,
             (State::AtBeginning, Token::ElementStart(..)) => State::AfterElementStart(0),
 
             (State::AfterDeclaration, Token::ProcessingInstruction(..))
             | (State::AfterDeclaration, Token::Comment(..))
             | (State::AfterDeclaration, Token::Whitespace(..)) => State::AfterDeclaration,
-            (State::AfterDeclaration, Token::DocumentTypeDeclaration) => State::AfterDeclaration,
+            (State::AfterDeclaration, Token::DocumentTypeDeclaration(..)) => {
+                State::AfterDocumentType
+            }
             (State::AfterDeclaration, Token::ElementStart(..)) => State::AfterElementStart(0),
 
+            (State::AfterDocumentType, Token::ProcessingInstruction(..))
+            | (State::AfterDocumentType, Token::Comment(..))
+            | (State::AfterDocumentType, Token::Whitespace(..)) => State::AfterDocumentType,
+            (State::AfterDocumentType, Token::ElementStart(..)) => State::AfterElementStart(0),
+
             (State::AfterElementStart(d), Token::AttributeStart(_, q)) => {
                 State::AfterAttributeStart(d, q)
             }
@@ -867,90 +1750,822 @@ impl<'a> Iterator for PullParser<'a> {
             | (State::AfterMainElement, Token::ProcessingInstruction(..))
             | (State::AfterMainElement, Token::Whitespace(..)) => State::AfterMainElement,
 
+            (State::Fragment, Token::CharData(..))
+            | (State::Fragment, Token::CData(..))
+            | (State::Fragment, Token::ContentReference(..))
+            | (State::Fragment, Token::Comment(..))
+            | (State::Fragment, Token::ProcessingInstruction(..)) => State::Fragment,
+            (State::Fragment, Token::ElementStart(..)) => State::AfterElementStart(0),
+
             (s, t) => {
                 unreachable!("Transitioning from {:?} to {:?} is impossible", s, t);
             }
         };
 
-        self.state = next_state;
-        self.xml = pt;
+        // A fragment may contain more than one top-level element
+        let next_state = match next_state {
+            State::AfterMainElement if self.fragment => State::Fragment,
+            s => s,
+        };
 
-        Some(Ok(r))
+        Some((Ok(r), pt, next_state))
     }
 }
 
-struct DomBuilder<'d> {
-    doc: dom::Document<'d>,
-    elements: Vec<dom::Element<'d>>,
-    element_names: Vec<Span<PrefixedName<'d>>>,
-    attributes: Vec<DeferredAttribute<'d>>,
-    seen_top_element: bool,
-}
+impl<'a> Iterator for PullParser<'a> {
+    type Item = TokenResult<'a>;
 
-impl<'d> DomBuilder<'d> {
-    fn new(doc: dom::Document<'d>) -> DomBuilder<'d> {
-        DomBuilder {
-            doc,
-            elements: vec![],
-            element_names: Vec::new(),
-            attributes: Vec::new(),
-            seen_top_element: false,
-        }
-    }
+    fn next(&mut self) -> Option<Self::Item> {
+        let step = match self.peeked.take() {
+            Some(step) => step,
+            None => self.step(),
+        };
 
-    fn append_to_either<T>(&self, child: T)
-    where
-        T: Into<dom::ChildOfRoot<'d>>,
-    {
-        match self.elements.last() {
-            None => self.doc.root().append_child(child),
-            Some(parent) => parent.append_child(child.into()),
-        }
+        step.map(|(token, xml, state)| {
+            self.xml = xml;
+            self.state = state;
+            token
+        })
     }
+}
 
-    fn default_namespace_uri(&self) -> Option<&str> {
-        self.elements
-            .last()
-            .and_then(|e| e.recursive_default_namespace_uri())
-    }
+/// An attribute of an element, as reported by `EventReader`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute<'a> {
+    name: PrefixedName<'a>,
+    value: Cow<'a, str>,
+}
 
-    fn namespace_uri_for_prefix(&self, prefix: &str) -> Option<&str> {
-        self.elements
-            .last()
-            .and_then(|e| e.namespace_uri_for_prefix(prefix))
+impl<'a> Attribute<'a> {
+    pub fn name(&self) -> PrefixedName<'a> {
+        self.name
     }
 
-    fn finish_opening_tag(&mut self) -> DomBuilderResult<()> {
-        let deferred_element = self.element_names.last().expect("Unknown element name");
-        let attributes = DeferredAttributes::new(replace(&mut self.attributes, Vec::new()));
+    /// The value with all references decoded and its whitespace
+    /// normalized
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
 
-        attributes.check_duplicates()?;
-        let default_namespace = attributes.default_namespace()?;
+/// A piece of an XML document, as reported by `EventReader`.
+///
+/// Character and entity references have already been decoded, and line
+/// ends have been normalized to `\n`. A self-closing element is
+/// reported as a `StartElement` immediately followed by an
+/// `EndElement`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event<'a> {
+    /// The `<?xml ... ?>` declaration at the start of the document
+    XmlDeclaration {
+        version: &'a str,
+        encoding: Option<&'a str>,
+        standalone: Option<bool>,
+    },
+    /// The `<!DOCTYPE ...>` declaration
+    DocumentType {
+        name: &'a str,
+        public_id: Option<&'a str>,
+        system_id: Option<&'a str>,
+        internal_subset: Option<&'a str>,
+    },
+    StartElement {
+        name: PrefixedName<'a>,
+        attributes: Vec<Attribute<'a>>,
+    },
+    EndElement {
+        name: PrefixedName<'a>,
+    },
+    /// A run of character data. Adjacent text and references are
+    /// combined into one event.
+    Text(Cow<'a, str>),
+    CData(Cow<'a, str>),
+    Comment(Cow<'a, str>),
+    ProcessingInstruction {
+        target: &'a str,
+        value: Option<Cow<'a, str>>,
+    },
+}
 
-        let mut new_prefix_mappings = HashMap::new();
-        for ns in attributes.namespaces() {
-            let value = AttributeValueBuilder::convert(&ns.values)?;
+/// Reads a string as a stream of `Event`s without building a DOM.
+///
+/// Parsing stops after the first error is reported. Entities declared
+/// in the internal subset are expanded when their replacement text is
+/// only text; referring to one that contains markup, such as elements
+/// or comments, is an `EntityWithMarkup` error. External entities are
+/// not loaded.
+///
+/// ### Example
+///
+/// ```
+/// use sxd_document::parser::{Event, EventReader};
+///
+/// let mut names = Vec::new();
+/// for event in EventReader::new("<a><b/>text</a>") {
+///     if let Event::StartElement { name, .. } = event.expect("Failed to parse") {
+///         names.push(name.local_part().to_owned());
+///     }
+/// }
+///
+/// assert_eq!(names, ["a", "b"]);
+/// ```
+pub struct EventReader<'a> {
+    xml: &'a str,
+    tokens: PullParser<'a>,
+    open_elements: OpenElements,
+    /// Where the name of the most recent start tag and the name of
+    /// each of its attributes are
+    start_offsets: Vec<usize>,
+    /// The declarations of the internal subset
+    dtd: Dtd,
+    expansion: Expansion,
+    xml_1_1: bool,
+    pending: Option<Event<'a>>,
+    finished: bool,
+}
 
-            if value.is_empty() {
-                return Err(ns.name.map(|_| SpecificError::EmptyNamespace));
-            }
+impl<'a> EventReader<'a> {
+    pub fn new(xml: &'a str) -> EventReader<'a> {
+        EventReader::resume(xml, 0, State::AtBeginning)
+    }
+
+    fn resume(xml: &'a str, offset: usize, state: State) -> EventReader<'a> {
+        EventReader {
+            xml,
+            tokens: PullParser::resume(xml, offset, state),
+            open_elements: OpenElements::default(),
+            start_offsets: Vec::new(),
+            dtd: Dtd::new(),
+            expansion: Expansion::new(&ParseLimits::default()),
+            xml_1_1: false,
+            pending: None,
+            finished: false,
+        }
+    }
 
-            new_prefix_mappings.insert(ns.name.value.local_part, value);
+    fn next_token(&mut self) -> Result<Option<Token<'a>>, Error> {
+        match self.tokens.next() {
+            Some(Ok(t)) => Ok(Some(t)),
+            Some(Err(e)) => Err(e.into()),
+            None => Ok(None),
         }
-        let new_prefix_mappings = new_prefix_mappings;
+    }
 
-        let element_name = &deferred_element.value;
+    fn next_event(&mut self) -> Result<Option<Event<'a>>, Error> {
+        loop {
+            let token = match self.next_token()? {
+                Some(t) => t,
+                None => {
+                    if self.open_elements.is_empty() {
+                        return Ok(None);
+                    }
+                    return Err(Error::new(
+                        self.tokens.end_offset(),
+                        ErrorKind::UnclosedElement,
+                    ));
+                }
+            };
+
+            let event = match token {
+                Token::XmlDeclaration(version, encoding, standalone) => {
+                    self.xml_1_1 = version == "1.1";
+                    Event::XmlDeclaration {
+                        version,
+                        encoding: encoding.map(|e| e.value),
+                        standalone: standalone.map(|s| s == "yes"),
+                    }
+                }
+                Token::DocumentTypeDeclaration(name, public_id, system_id, internal_subset) => {
+                    if let Some(subset) = internal_subset {
+                        let xml = StringPoint {
+                            s: subset.value,
+                            offset: subset.offset,
+                        };
+                        let mut expanding = Vec::new();
+                        read_declarations(
+                            &mut self.dtd,
+                            xml,
+                            &mut expanding,
+                            &DenyAll,
+                            &self.expansion,
+                        )?;
+                    }
+
+                    Event::DocumentType {
+                        name,
+                        public_id,
+                        system_id: system_id.map(|s| s.value),
+                        internal_subset: internal_subset.map(|s| s.value),
+                    }
+                }
+                Token::ElementStart(name) => self.start_element(name)?,
+                Token::ElementClose(name) => {
+                    let open = self.open_elements.last().expect("No open element");
+                    if name.value != open.value {
+                        return Err(name.map(|_| ErrorKind::MismatchedElementEndName).into());
+                    }
+                    self.open_elements.pop();
+                    Event::EndElement { name: name.value }
+                }
+                Token::CharData(t) => self.text(normalize_line_ends(t, self.xml_1_1))?,
+                Token::ContentReference(r) => {
+                    let mut text = String::new();
+                    self.decode_reference(r, &mut text)?;
+                    self.text(Cow::Owned(text))?
+                }
+                Token::CData(t) => Event::CData(normalize_line_ends(t, self.xml_1_1)),
+                Token::Comment(c) => Event::Comment(normalize_line_ends(c, self.xml_1_1)),
+                Token::ProcessingInstruction(target, value) => Event::ProcessingInstruction {
+                    target,
+                    value: value.map(|v| normalize_line_ends(v, self.xml_1_1)),
+                },
+                Token::Whitespace(..) => continue,
+                t => unreachable!("{:?} can only occur inside of a start tag", t),
+            };
+
+            return Ok(Some(event));
+        }
+    }
+
+    fn start_element(&mut self, name: Span<PrefixedName<'a>>) -> Result<Event<'a>, Error> {
+        let mut attributes: Vec<DeferredAttribute<'a>> = Vec::new();
+        let mut self_closed = false;
+        self.open_elements.push(name);
+
+        loop {
+            let token = match self.next_token()? {
+                Some(t) => t,
+                None => {
+                    return Err(Error::new(
+                        self.tokens.end_offset(),
+                        ErrorKind::UnclosedElement,
+                    ))
+                }
+            };
+
+            match token {
+                Token::AttributeStart(n, _) => attributes.push(DeferredAttribute {
+                    name: n,
+                    values: Vec::new(),
+                    specified: true,
+                    source_span: None,
+                }),
+                Token::LiteralAttributeValue(v) => {
+                    let a = attributes.last_mut().expect("No open attribute");
+                    a.values.push(AttributeValue::LiteralAttributeValue(v));
+                }
+                Token::ReferenceAttributeValue(r) => {
+                    let a = attributes.last_mut().expect("No open attribute");
+                    a.values.push(AttributeValue::ReferenceAttributeValue(r));
+                }
+                Token::AttributeEnd => {}
+                Token::ElementStartClose => break,
+                Token::ElementSelfClose => {
+                    self_closed = true;
+                    break;
+                }
+                t => unreachable!("{:?} cannot occur inside of a start tag", t),
+            }
+        }
+
+        DeferredAttributes::new(attributes.clone()).check_duplicates()?;
+
+        self.start_offsets.clear();
+        self.start_offsets.push(name.offset);
+        self.start_offsets
+            .extend(attributes.iter().map(|a| a.name.offset));
+
+        let attributes = attributes
+            .iter()
+            .map(|a| {
+                let tokenized = is_tokenized(&self.dtd, name.value, a.name.value);
+                let value = match a.values[..] {
+                    [] => Cow::Borrowed(""),
+                    [AttributeValue::LiteralAttributeValue(v)] if !tokenized => {
+                        normalize_attribute_literal(v, self.xml_1_1)
+                    }
+                    _ => {
+                        let mut builder = AttributeValueBuilder::new(&self.dtd, &self.expansion);
+                        builder.ingest_normalized(&a.values, self.xml_1_1)?;
+                        if tokenized {
+                            builder.collapse_spaces();
+                        }
+                        Cow::Owned(builder.implode())
+                    }
+                };
+                Ok(Attribute {
+                    name: a.name.value,
+                    value,
+                })
+            })
+            .collect::<DomBuilderResult<_>>()?;
+
+        if self_closed {
+            self.open_elements.pop();
+            self.pending = Some(Event::EndElement { name: name.value });
+        }
+
+        Ok(Event::StartElement {
+            name: name.value,
+            attributes,
+        })
+    }
+
+    fn text(&mut self, text: Cow<'a, str>) -> Result<Event<'a>, Error> {
+        let mut text = text;
+
+        loop {
+            match self.tokens.peek() {
+                Some(&Ok(Token::CharData(t))) => text
+                    .to_mut()
+                    .push_str(&normalize_line_ends(t, self.xml_1_1)),
+                Some(&Ok(Token::ContentReference(r))) => self.decode_reference(r, text.to_mut())?,
+                _ => break,
+            }
+            self.tokens.next();
+        }
+
+        Ok(Event::Text(text))
+    }
+
+    /// Adds the text a reference in content stands for
+    fn decode_reference(
+        &self,
+        reference: Reference<'_>,
+        text: &mut String,
+    ) -> DomBuilderResult<()> {
+        expand_text_reference(reference, &self.dtd, &mut Vec::new(), &self.expansion, text)
+    }
+
+    /// An error in the most recent start tag, found after it was
+    /// reported. `index` 0 is the element name and the attributes
+    /// follow in the order they were written.
+    pub(crate) fn start_tag_error(&self, index: usize, kind: ErrorKind) -> Error {
+        let name = match self.pending {
+            Some(Event::EndElement { name }) => name,
+            _ => self.open_elements.last().expect("No open element").value,
+        };
+        let element = Span {
+            offset: self.start_offsets[0],
+            value: name,
+        };
+
+        Error::new(self.start_offsets[index], kind)
+            .in_element(element)
+            .located_in(self.xml)
+    }
+}
+
+impl<'a> Iterator for EventReader<'a> {
+    type Item = Result<Event<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.pending.take() {
+            return Some(Ok(event));
+        }
+
+        if self.finished {
+            return None;
+        }
+
+        match self.next_event() {
+            Ok(Some(event)) => Some(Ok(event)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                let e = self.open_elements.in_current_element(e);
+                Some(Err(e.located_in(self.xml)))
+            }
+        }
+    }
+}
+
+/// Reads a document from a reader as a stream of `Event`s without
+/// building a DOM. Only the part of the document needed for the
+/// current event is kept in memory.
+///
+/// Each event borrows from the reader's buffer, so this cannot be an
+/// `Iterator`; call `next_event` in a loop instead. Reading stops
+/// after the first error is reported.
+///
+/// ### Example
+///
+/// ```
+/// use sxd_document::parser::{Event, StreamingEventReader};
+///
+/// let mut events = StreamingEventReader::new("<a><b/>text</a>".as_bytes());
+/// let mut names = Vec::new();
+/// while let Some(event) = events.next_event() {
+///     if let Event::StartElement { name, .. } = event.expect("Failed to parse") {
+///         names.push(name.local_part().to_owned());
+///     }
+/// }
+///
+/// assert_eq!(names, ["a", "b"]);
+/// ```
+pub struct StreamingEventReader<R> {
+    input: ChunkedInput<R>,
+    state: State,
+    open_elements: OpenElements,
+    /// The input used by the previous event, which may still be borrowed
+    used: usize,
+    /// The name of the most recent self-closing element
+    self_closed: String,
+    /// If the end of the most recent self-closing element is unreported
+    end_pending: bool,
+    /// As in `EventReader`
+    start_offsets: Vec<usize>,
+    dtd: Dtd,
+    expansion: Expansion,
+    xml_1_1: bool,
+    finished: bool,
+}
+
+impl<R: BufRead> StreamingEventReader<R> {
+    pub fn new(reader: R) -> StreamingEventReader<R> {
+        StreamingEventReader {
+            input: ChunkedInput::new(reader),
+            state: State::AtBeginning,
+            open_elements: OpenElements::default(),
+            used: 0,
+            self_closed: String::new(),
+            end_pending: false,
+            start_offsets: Vec::new(),
+            dtd: Dtd::new(),
+            expansion: Expansion::new(&ParseLimits::default()),
+            xml_1_1: false,
+            finished: false,
+        }
+    }
+
+    /// Returns the next event, or `None` at the end of the document
+    pub fn next_event(&mut self) -> Option<Result<Event<'_>, ReadError>> {
+        self.input.consume(replace(&mut self.used, 0));
+
+        if self.end_pending {
+            self.end_pending = false;
+            let name = split_qualified_name(&self.self_closed);
+            return Some(Ok(Event::EndElement { name }));
+        }
+        if self.finished {
+            return None;
+        }
+
+        if let Err(e) = self.input.fill_event(self.state) {
+            self.finished = true;
+            return Some(Err(e.into()));
+        }
+
+        let (xml, offset) = self.input.unconsumed();
+        let mut reader = EventReader::resume(xml, offset, self.state);
+        mem::swap(&mut reader.open_elements, &mut self.open_elements);
+        mem::swap(&mut reader.start_offsets, &mut self.start_offsets);
+        mem::swap(&mut reader.dtd, &mut self.dtd);
+        mem::swap(&mut reader.expansion, &mut self.expansion);
+        reader.xml_1_1 = self.xml_1_1;
+
+        let event = reader.next_event();
+
+        self.used = reader.tokens.offset() - offset;
+        self.state = reader.tokens.state;
+        self.xml_1_1 = reader.xml_1_1;
+        mem::swap(&mut reader.open_elements, &mut self.open_elements);
+        mem::swap(&mut reader.start_offsets, &mut self.start_offsets);
+        mem::swap(&mut reader.dtd, &mut self.dtd);
+        mem::swap(&mut reader.expansion, &mut self.expansion);
+
+        if let Some(Event::EndElement { name }) = reader.pending {
+            self.self_closed.clear();
+            push_qualified_name(&mut self.self_closed, name);
+            self.end_pending = true;
+        }
+
+        match event {
+            Ok(Some(event)) => Some(Ok(event)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                let e = self.open_elements.in_current_element(e);
+                Some(Err(self.input.locate(e).into()))
+            }
+        }
+    }
+
+    /// As `EventReader::start_tag_error`
+    pub(crate) fn start_tag_error(&self, index: usize, kind: ErrorKind) -> ReadError {
+        let name = if self.end_pending {
+            split_qualified_name(&self.self_closed)
+        } else {
+            self.open_elements.last().expect("No open element").value
+        };
+        let element = Span {
+            offset: self.start_offsets[0],
+            value: name,
+        };
+
+        let e = Error::new(self.start_offsets[index], kind).in_element(element);
+        self.input.locate(e).into()
+    }
+}
+
+/// The qualified names of the currently open elements and where they
+/// start. The names are copied so that they can outlive the input they
+/// were read from.
+#[derive(Debug, Default)]
+struct OpenElements {
+    names: String,
+    ends: Vec<usize>,
+    offsets: Vec<usize>,
+}
+
+impl OpenElements {
+    fn push(&mut self, name: Span<PrefixedName<'_>>) {
+        push_qualified_name(&mut self.names, name.value);
+        self.ends.push(self.names.len());
+        self.offsets.push(name.offset);
+    }
+
+    fn pop(&mut self) {
+        self.ends.pop();
+        self.offsets.pop();
+        let start = self.ends.last().cloned().unwrap_or(0);
+        self.names.truncate(start);
+    }
+
+    /// The innermost open element
+    fn last(&self) -> Option<Span<PrefixedName<'_>>> {
+        let end = *self.ends.last()?;
+        let start = self.ends.iter().rev().nth(1).cloned().unwrap_or(0);
+        let offset = *self.offsets.last()?;
+
+        Some(Span {
+            offset,
+            value: split_qualified_name(&self.names[start..end]),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    fn in_current_element(&self, error: Error) -> Error {
+        match self.last() {
+            Some(name) => error.in_element(name),
+            None => error,
+        }
+    }
+}
+
+fn push_qualified_name(s: &mut String, name: PrefixedName<'_>) {
+    if let Some(prefix) = name.prefix {
+        s.push_str(prefix);
+        s.push(':');
+    }
+    s.push_str(name.local_part);
+}
+
+fn split_qualified_name(name: &str) -> PrefixedName<'_> {
+    match name.find(':') {
+        Some(i) => PrefixedName::with_prefix(Some(&name[..i]), &name[i + 1..]),
+        None => PrefixedName::new(name),
+    }
+}
+
+struct DomBuilder<'d> {
+    doc: dom::Document<'d>,
+    elements: Vec<dom::Element<'d>>,
+    element_names: Vec<Span<PrefixedName<'d>>>,
+    attributes: Vec<DeferredAttribute<'d>>,
+    seen_top_element: bool,
+    namespace_aware: bool,
+    apply_attribute_defaults: bool,
+    preserve_cdata: bool,
+    whitespace: WhitespaceMode,
+    source_spans: bool,
+    dtd: Dtd,
+    entities: HashMap<String, String>,
+    html_entities: bool,
+    /// The entities whose replacement text is being added
+    expanding: Vec<String>,
+    resolver: Rc<dyn Resolver>,
+    limits: ParseLimits,
+    expansion: Expansion,
+    nodes: usize,
+}
+
+impl<'d> DomBuilder<'d> {
+    fn new(doc: dom::Document<'d>, parser: &Parser) -> DomBuilder<'d> {
+        let mut builder = DomBuilder {
+            doc,
+            elements: vec![],
+            element_names: Vec::new(),
+            attributes: Vec::new(),
+            seen_top_element: false,
+            namespace_aware: parser.namespace_aware,
+            apply_attribute_defaults: parser.apply_attribute_defaults,
+            preserve_cdata: parser.preserve_cdata,
+            whitespace: parser.whitespace,
+            source_spans: parser.source_spans,
+            dtd: Dtd::new(),
+            entities: parser.entities.clone(),
+            html_entities: parser.html_entities,
+            expanding: Vec::new(),
+            resolver: parser.resolver.clone(),
+            limits: parser.limits,
+            expansion: Expansion::new(&parser.limits),
+            nodes: 0,
+        };
+        builder.add_parser_entities();
+        builder
+    }
+
+    /// Builds content inside of an existing element, which is treated
+    /// as already open
+    fn fragment(element: dom::Element<'d>, parser: &Parser) -> DomBuilder<'d> {
+        DomBuilder {
+            elements: vec![element],
+            seen_top_element: true,
+            ..DomBuilder::new(element.document(), parser)
+        }
+    }
+
+    /// Declares the entities given to the parser, after any declared
+    /// by the document so that those take precedence. The XHTML
+    /// entities come last of all.
+    fn add_parser_entities(&mut self) {
+        for (name, text) in &self.entities {
+            let entity = dtd::Entity::Internal(text.clone());
+            self.dtd.add_entity(name, entity);
+        }
+
+        if self.html_entities {
+            for &(name, c) in XHTML_ENTITIES {
+                self.dtd
+                    .add_entity(name, dtd::Entity::Internal(c.to_string()));
+            }
+        }
+    }
+
+    fn check_limit(
+        &self,
+        value: usize,
+        max: usize,
+        limit: Limit,
+        offset: usize,
+    ) -> Result<(), Error> {
+        if value > max {
+            return Err(Error::new(offset, ErrorKind::LimitExceeded(limit)));
+        }
+        Ok(())
+    }
+
+    fn add_node(&mut self, offset: usize) -> Result<(), Error> {
+        self.nodes += 1;
+        self.check_limit(self.nodes, self.limits.max_nodes, Limit::Nodes, offset)
+    }
+
+    fn check_name(&self, name: PrefixedName<'_>, offset: usize) -> Result<(), Error> {
+        let len = name.prefix.map_or(0, |p| p.len() + 1) + name.local_part.len();
+        self.check_limit(len, self.limits.max_name_length, Limit::NameLength, offset)
+    }
+
+    fn check_text(&self, text: &str, offset: usize) -> Result<(), Error> {
+        self.check_limit(
+            text.len(),
+            self.limits.max_text_length,
+            Limit::TextLength,
+            offset,
+        )
+    }
+
+    fn is_xml_1_1(&self) -> bool {
+        self.doc.xml_version() == "1.1"
+    }
+
+    /// Replaces the line ends in literal text with `\n`. Text from a
+    /// character reference is left as it is, as is the replacement
+    /// text of an entity, which was normalized when it was declared.
+    fn normalize_line_ends(&self, text: &'d str) -> &'d str {
+        if !self.expanding.is_empty() {
+            return text;
+        }
+
+        match normalize_line_ends(text, self.is_xml_1_1()) {
+            Cow::Borrowed(text) => text,
+            Cow::Owned(text) => self.doc.intern(&text),
+        }
+    }
+
+    /// Normalizes the line ends of a literal attribute value, then
+    /// replaces each whitespace character with a space
+    fn normalize_attribute_value(&self, value: &'d str) -> &'d str {
+        match normalize_attribute_spaces(self.normalize_line_ends(value)) {
+            Cow::Borrowed(value) => value,
+            Cow::Owned(value) => self.doc.intern(&value),
+        }
+    }
+
+    fn append_to_either<T>(&self, child: T)
+    where
+        T: Into<dom::ChildOfRoot<'d>> + Into<dom::ChildOfElement<'d>>,
+    {
+        match self.elements.last() {
+            None => self.doc.root().append_child(child),
+            Some(parent) => parent.append_child(child),
+        }
+    }
+
+    fn default_namespace_uri(&self) -> Option<&str> {
+        self.elements
+            .last()
+            .and_then(|e| e.recursive_default_namespace_uri())
+    }
+
+    fn namespace_uri_for_prefix(&self, prefix: &str) -> Option<&str> {
+        self.elements
+            .last()
+            .and_then(|e| e.namespace_uri_for_prefix(prefix))
+    }
+
+    /// Adds the attributes that are missing from the start tag but
+    /// have a default value in the document type definition
+    fn add_default_attributes(&mut self) {
+        let element = *self.element_names.last().expect("Unknown element name");
+        let mut element_name = String::new();
+        push_qualified_name(&mut element_name, element.value);
+
+        for declaration in self.dtd.attributes(&element_name) {
+            let value = match *declaration.default_value() {
+                dtd::DefaultValue::Fixed(ref v) | dtd::DefaultValue::Default(ref v) => v,
+                _ => continue,
+            };
+
+            let name = split_qualified_name(self.doc.intern(declaration.name()));
+            if self.attributes.iter().any(|a| a.name.value == name) {
+                continue;
+            }
+
+            self.attributes.push(DeferredAttribute {
+                name: element.map(|_| name),
+                values: vec![AttributeValue::LiteralAttributeValue(
+                    self.doc.intern(value),
+                )],
+                specified: false,
+                source_span: None,
+            });
+        }
+    }
+
+    fn finish_opening_tag(&mut self) -> DomBuilderResult<()> {
+        if self.apply_attribute_defaults {
+            self.add_default_attributes();
+        }
+
+        if !self.namespace_aware {
+            return self.finish_opening_tag_without_namespaces();
+        }
+
+        let deferred_element = self.element_names.last().expect("Unknown element name");
+        let attributes = DeferredAttributes::new(replace(&mut self.attributes, Vec::new()));
+
+        attributes.check_duplicates()?;
+        let default_namespace = attributes.default_namespace(&self.dtd, &self.expansion)?;
+
+        let mut new_prefix_mappings = HashMap::new();
+        for ns in attributes.namespaces() {
+            let value = AttributeValueBuilder::convert(&ns.values, &self.dtd, &self.expansion)?;
+
+            // XML 1.1 allows a prefix to be undeclared
+            if value.is_empty() && !self.is_xml_1_1() {
+                return Err(ns.name.map(|_| ErrorKind::EmptyNamespace));
+            }
+            check_prefix_binding(Some(ns.name.value.local_part), &value)
+                .map_err(|kind| ns.name.map(|_| kind))?;
+
+            new_prefix_mappings.insert(ns.name.value.local_part, value);
+        }
+        let new_prefix_mappings = new_prefix_mappings;
+
+        let element_name = &deferred_element.value;
 
         let element = if let Some(prefix) = element_name.prefix {
             let ns_uri = new_prefix_mappings.get(prefix).map(|p| &p[..]);
-            let ns_uri = ns_uri.or_else(|| self.namespace_uri_for_prefix(prefix));
+            let ns_uri = ns_uri
+                .or_else(|| self.namespace_uri_for_prefix(prefix))
+                .filter(|ns_uri| !ns_uri.is_empty());
 
             if let Some(ns_uri) = ns_uri {
                 let element = self.doc.create_element((ns_uri, element_name.local_part));
                 element.set_preferred_prefix(Some(prefix));
                 element
             } else {
-                return Err(deferred_element.map(|_| SpecificError::UnknownNamespacePrefix));
+                return Err(deferred_element.map(|_| ErrorKind::UnknownNamespacePrefix));
             }
         } else if let Some(ns_uri) = default_namespace {
             if ns_uri.is_empty() {
@@ -982,34 +2597,141 @@ impl<'d> DomBuilder<'d> {
         self.append_to_either(element);
         self.elements.push(element);
 
-        let mut builder = AttributeValueBuilder::new();
+        let mut builder = AttributeValueBuilder::new(&self.dtd, &self.expansion);
 
         for attribute in attributes.attributes() {
             let name = &attribute.name.value;
 
             builder.clear();
             builder.ingest(&attribute.values)?;
+            if is_tokenized(&self.dtd, *element_name, attribute.name.value) {
+                builder.collapse_spaces();
+            }
+            self.check_attribute_value(attribute, &builder)?;
 
-            if let Some(prefix) = name.prefix {
+            let attr = if let Some(prefix) = name.prefix {
                 let ns_uri = new_prefix_mappings.get(prefix).map(|p| &p[..]);
-                let ns_uri = ns_uri.or_else(|| self.namespace_uri_for_prefix(prefix));
+                let ns_uri = ns_uri
+                    .or_else(|| self.namespace_uri_for_prefix(prefix))
+                    .filter(|ns_uri| !ns_uri.is_empty());
 
                 if let Some(ns_uri) = ns_uri {
+                    if element.attribute((ns_uri, name.local_part)).is_some() {
+                        let kind = ErrorKind::DuplicateExpandedAttribute;
+                        return Err(attribute.name.map(|_| kind));
+                    }
+
                     let attr = element.set_attribute_value((ns_uri, name.local_part), &builder);
                     attr.set_preferred_prefix(Some(prefix));
+                    attr
                 } else {
-                    return Err(attribute
-                        .name
-                        .map(|_| SpecificError::UnknownNamespacePrefix));
+                    return Err(attribute.name.map(|_| ErrorKind::UnknownNamespacePrefix));
                 }
             } else {
-                element.set_attribute_value(name.local_part, &builder);
+                element.set_attribute_value(name.local_part, &builder)
+            };
+
+            if !attribute.specified {
+                attr.set_specified(false);
+            }
+            attr.set_source_span(attribute.source_span);
+        }
+
+        Ok(())
+    }
+
+    /// Creates the element and its attributes with the names as they
+    /// were written
+    fn finish_opening_tag_without_namespaces(&mut self) -> DomBuilderResult<()> {
+        let deferred_element = self.element_names.last().expect("Unknown element name");
+        let attributes: Vec<_> = self.attributes.drain(..).collect();
+        DeferredAttributes::new(attributes.clone()).check_duplicates()?;
+
+        let mut name = String::new();
+        push_qualified_name(&mut name, deferred_element.value);
+        let element = self.doc.create_element(&name[..]);
+
+        self.append_to_either(element);
+        self.elements.push(element);
+
+        let mut builder = AttributeValueBuilder::new(&self.dtd, &self.expansion);
+
+        for attribute in &attributes {
+            builder.clear();
+            builder.ingest(&attribute.values)?;
+            if is_tokenized(&self.dtd, deferred_element.value, attribute.name.value) {
+                builder.collapse_spaces();
+            }
+            self.check_attribute_value(attribute, &builder)?;
+
+            name.clear();
+            push_qualified_name(&mut name, attribute.name.value);
+            let attr = element.set_attribute_value(&name[..], &builder);
+
+            if !attribute.specified {
+                attr.set_specified(false);
             }
+            attr.set_source_span(attribute.source_span);
+        }
+
+        Ok(())
+    }
+
+    fn check_attribute_value(
+        &self,
+        attribute: &DeferredAttribute<'_>,
+        value: &str,
+    ) -> DomBuilderResult<()> {
+        if value.len() > self.limits.max_text_length {
+            let kind = ErrorKind::LimitExceeded(Limit::TextLength);
+            return Err(attribute.name.map(|_| kind));
         }
 
+        // The literal parts were checked as they were read, but XML 1.0
+        // also refuses some characters that a reference may produce
+        if !self.is_xml_1_1() && !value.chars().all(XmlChar::is_char) {
+            return Err(attribute.name.map(|_| ErrorKind::InvalidCharacter));
+        }
         Ok(())
     }
 
+    /// Checks that text only holds characters that XML allows. `start`
+    /// is where the text is in the document. Text from a reference,
+    /// including the replacement text of an entity, may hold the
+    /// restricted characters of XML 1.1 and is reported at `span`.
+    fn check_chars(
+        &self,
+        text: &str,
+        span: SourceSpan,
+        start: usize,
+        referenced: bool,
+    ) -> Result<(), Error> {
+        let referenced = referenced || !self.expanding.is_empty();
+        let xml_1_1 = self.is_xml_1_1();
+        let allowed = |c: char| match (xml_1_1, referenced) {
+            (true, false) => c.is_char() && !c.is_restricted_char(),
+            (true, true) => c.is_char() || c.is_restricted_char(),
+            (false, _) => c.is_char(),
+        };
+
+        match text.char_indices().find(|&(_, c)| !allowed(c)) {
+            Some(_) if referenced => Err(Error::new(span.start(), ErrorKind::InvalidCharacter)),
+            Some((i, _)) => Err(Error::new(start + i, ErrorKind::InvalidCharacter)),
+            None => Ok(()),
+        }
+    }
+
+    /// Where the body of a token ending with `close` starts. Tokens
+    /// from the replacement text of an entity are reported at the
+    /// reference instead.
+    fn body_start(&self, body: &str, span: SourceSpan, close: &str) -> usize {
+        if self.expanding.is_empty() {
+            span.end() - close.len() - body.len()
+        } else {
+            span.start()
+        }
+    }
+
     fn add_attribute_value(&mut self, v: AttributeValue<'d>) {
         let a = self
             .attributes
@@ -1018,60 +2740,229 @@ impl<'d> DomBuilder<'d> {
         a.values.push(v);
     }
 
-    fn add_text_data(&self, text: &str) {
+    fn add_text_data(&mut self, text: &str, span: SourceSpan) -> Result<dom::Text<'d>, Error> {
+        self.check_text(text, span.start())?;
+        self.add_node(span.start())?;
+
         let e = self
             .elements
             .last()
             .expect("Cannot add text node without a parent");
         let t = self.doc.create_text(text);
+        t.set_source_span(self.source_span(span));
         e.append_child(t);
+        Ok(t)
     }
 
-    fn has_unclosed_elements(&self) -> bool {
-        !self.elements.is_empty()
-    }
+    /// A refused external subset is skipped, as a non-validating
+    /// parser is not required to read it
+    fn read_external_subset(
+        &mut self,
+        public_id: Option<&str>,
+        system_id: Span<&str>,
+    ) -> Result<(), Error> {
+        let text = match load_external(&*self.resolver, public_id, system_id.value, &self.expansion)
+        {
+            Ok(text) => text,
+            Err(None) => return Ok(()),
+            Err(Some(kind)) => return Err(system_id.map(|_| kind).into()),
+        };
 
-    fn consume(&mut self, token: Token<'d>) -> DomBuilderResult<()> {
-        use self::Token::*;
+        read_declarations(
+            &mut self.dtd,
+            StringPoint::new(&text),
+            &mut Vec::new(),
+            &*self.resolver,
+            &self.expansion,
+        )
+        .map_err(|e| Error::with_errors(system_id.offset, e.errors))
+    }
+
+    /// Adds the content of a declared entity in place of a reference
+    /// to it. Anything wrong with the content is reported at the
+    /// reference.
+    fn expand_entity(&mut self, name: Span<&str>, span: SourceSpan) -> Result<(), Error> {
+        let text = match self.dtd.entity(name.value) {
+            Some(&dtd::Entity::External {
+                ref public_id,
+                ref system_id,
+                notation: None,
+            }) if !self.expanding.iter().any(|e| e == name.value) => {
+                let public_id = public_id.as_ref().map(|p| &p[..]);
+                load_external(&*self.resolver, public_id, system_id, &self.expansion).map_err(
+                    |kind| {
+                        let kind = kind.unwrap_or(ErrorKind::UnresolvedExternalEntity);
+                        Error::new(name.offset, kind)
+                    },
+                )?
+            }
+            _ => replacement_text(&self.dtd, name, &self.expanding, false)?.to_owned(),
+        };
+        self.expansion.add(name, self.expanding.len(), text.len())?;
+        let depth = self.element_names.len();
 
-        match token {
-            XmlDeclaration => {}
+        self.expanding.push(name.value.to_owned());
+        for token in PullParser::fragment(&text) {
+            let token = token.map_err(|(_, kinds)| Error::from((name.offset, kinds)))?;
+            self.consume(token.intern(self.doc).at(name.offset), span)?;
+        }
+        self.expanding.pop();
 
-            DocumentTypeDeclaration => {}
+        // Elements must start and end in the same entity
+        if self.element_names.len() > depth {
+            return Err(Error::new(name.offset, ErrorKind::UnclosedElement));
+        }
 
-            ElementStart(n) => {
-                self.element_names.push(n);
-            }
+        Ok(())
+    }
 
-            ElementStartClose => {
+    fn has_unclosed_elements(&self) -> bool {
+        !self.elements.is_empty()
+    }
+
+    /// Removes whitespace from the finished document, as the
+    /// `WhitespaceMode` asks
+    fn finish(&self) {
+        let top = self.doc.root().children();
+        self.strip_whitespace(top.iter().filter_map(|c| c.element()));
+    }
+
+    fn strip_whitespace<I>(&self, elements: I)
+    where
+        I: IntoIterator<Item = dom::Element<'d>>,
+    {
+        let trim = match self.whitespace {
+            WhitespaceMode::RemoveBlank => false,
+            WhitespaceMode::Trim => true,
+            _ => return,
+        };
+
+        for element in elements {
+            element.strip_whitespace_text(trim);
+        }
+    }
+
+    fn in_current_element(&self, error: Error) -> Error {
+        match self.element_names.last() {
+            Some(&name) => error.in_element(name),
+            None => error,
+        }
+    }
+
+    /// The span to record for a node, if spans are recorded
+    fn source_span(&self, span: SourceSpan) -> Option<SourceSpan> {
+        if self.source_spans {
+            Some(span)
+        } else {
+            None
+        }
+    }
+
+    /// Records the span of the innermost open element, from its start
+    /// tag up to `end`
+    fn set_element_span(&self, end: usize) {
+        let name = self.element_names.last().expect("No open element");
+        let element = self.elements.last().expect("No open element");
+        element.set_source_span(self.source_span(SourceSpan::new(name.offset - 1, end)));
+    }
+
+    /// Adds a token to the DOM. `span` is where the token is in the
+    /// document, or the entity reference that produced it.
+    fn consume(&mut self, token: Token<'d>, span: SourceSpan) -> Result<(), Error> {
+        use self::Token::*;
+
+        let offset = span.start();
+
+        match token {
+            XmlDeclaration(version, encoding, standalone) => {
+                self.doc.set_xml_version(version);
+                self.doc.set_xml_encoding(encoding.map(|e| e.value));
+                self.doc.set_xml_standalone(standalone.map(|s| s == "yes"));
+            }
+
+            DocumentTypeDeclaration(name, public_id, system_id, internal_subset) => {
+                let doctype =
+                    self.doc
+                        .create_document_type(name, public_id, system_id.map(|s| s.value));
+                doctype.set_internal_subset(internal_subset.map(|s| s.value));
+                self.doc.root().append_child(doctype);
+
+                // The entities given to the parser are added again
+                // after the document's own declarations
+                self.dtd = Dtd::new();
+
+                if let Some(subset) = internal_subset {
+                    let xml = StringPoint {
+                        s: subset.value,
+                        offset: subset.offset,
+                    };
+                    read_declarations(
+                        &mut self.dtd,
+                        xml,
+                        &mut Vec::new(),
+                        &*self.resolver,
+                        &self.expansion,
+                    )?;
+                }
+
+                // Declarations in the internal subset take precedence,
+                // as the first declaration is the one that is used
+                if let Some(system_id) = system_id {
+                    self.read_external_subset(public_id, system_id)?;
+                }
+                self.add_parser_entities();
+            }
+
+            ElementStart(n) => {
+                let depth = self.element_names.len() + 1;
+                self.check_limit(depth, self.limits.max_depth, Limit::Depth, n.offset)?;
+                self.check_name(n.value, n.offset)?;
+                self.add_node(n.offset)?;
+                self.element_names.push(n);
+            }
+
+            ElementStartClose => {
                 self.finish_opening_tag()?;
+                self.set_element_span(span.end());
             }
 
             ElementSelfClose => {
                 self.finish_opening_tag()?;
+                self.set_element_span(span.end());
 
                 self.element_names.pop();
                 self.elements.pop();
             }
 
             ElementClose(n) => {
-                let open_name = self.element_names.pop().expect("No open element");
-                self.elements.pop();
-
+                let open_name = self.element_names.last().expect("No open element");
                 if n.value != open_name.value {
-                    return Err(n.map(|_| SpecificError::MismatchedElementEndName));
+                    return Err(n.map(|_| ErrorKind::MismatchedElementEndName).into());
                 }
+
+                self.set_element_span(span.end());
+                self.element_names.pop();
+                self.elements.pop();
             }
 
             AttributeStart(n, _) => {
+                let count = self.attributes.len() + 1;
+                let max = self.limits.max_attributes;
+                self.check_limit(count, max, Limit::Attributes, n.offset)?;
+                self.check_name(n.value, n.offset)?;
+
                 let attr = DeferredAttribute {
                     name: n,
                     values: Vec::new(),
+                    specified: true,
+                    source_span: self.source_span(SourceSpan::new(n.offset, n.offset)),
                 };
                 self.attributes.push(attr);
             }
 
             LiteralAttributeValue(v) => {
+                self.check_chars(v, span, offset, false)?;
+                let v = self.normalize_attribute_value(v);
                 self.add_attribute_value(AttributeValue::LiteralAttributeValue(v));
             }
 
@@ -1079,59 +2970,448 @@ impl<'d> DomBuilder<'d> {
                 self.add_attribute_value(AttributeValue::ReferenceAttributeValue(v));
             }
 
-            AttributeEnd => {}
+            AttributeEnd => {
+                let a = self
+                    .attributes
+                    .last_mut()
+                    .expect("Cannot end an attribute without an attribute");
+                if let Some(ref mut attr_span) = a.source_span {
+                    *attr_span = SourceSpan::new(attr_span.start(), span.end());
+                }
+            }
 
             Whitespace(..) => {}
 
-            CharData(t) | CData(t) => self.add_text_data(t),
+            CharData(t) => {
+                self.check_chars(t, span, offset, false)?;
+                let t = self.normalize_line_ends(t);
+                self.add_text_data(t, span)?;
+            }
+
+            CData(t) => {
+                let start = self.body_start(t, span, "]]>");
+                self.check_chars(t, span, start, false)?;
+                let t = self.normalize_line_ends(t);
+                let text = self.add_text_data(t, span)?;
+                text.set_cdata(self.preserve_cdata);
+            }
+
+            ContentReference(Entity(name)) if predefined_entity(name.value).is_none() => {
+                self.expand_entity(name, span)?;
+            }
 
             ContentReference(t) => {
-                decode_reference(t, |s| self.add_text_data(s))?;
+                let mut text = String::new();
+                decode_reference(t, |s| text.push_str(s))?;
+                self.check_chars(&text, span, offset, true)?;
+                self.add_text_data(&text, span)?;
             }
 
             Comment(c) => {
+                let start = self.body_start(c, span, "-->");
+                self.check_chars(c, span, start, false)?;
+                let c = self.normalize_line_ends(c);
+                self.check_text(c, offset)?;
+                self.add_node(offset)?;
                 let c = self.doc.create_comment(c);
+                c.set_source_span(self.source_span(span));
                 self.append_to_either(c);
             }
 
             ProcessingInstruction(t, v) => {
+                if let Some(v) = v {
+                    let start = self.body_start(v, span, "?>");
+                    self.check_chars(v, span, start, false)?;
+                }
+                let v = v.map(|v| self.normalize_line_ends(v));
+                self.check_name(PrefixedName::new(t), offset)?;
+                self.check_text(v.unwrap_or(""), offset)?;
+                self.add_node(offset)?;
                 let pi = self.doc.create_processing_instruction(t, v);
+                pi.set_source_span(self.source_span(span));
                 self.append_to_either(pi);
             }
         };
 
         Ok(())
     }
+
+    /// Adds a token to the DOM as `consume` does, but repairs the
+    /// document where it can instead of failing. What was wrong is
+    /// added to `errors`.
+    fn consume_recovering(&mut self, token: Token<'d>, span: SourceSpan, errors: &mut Vec<Error>) {
+        use self::Token::*;
+
+        match token {
+            ElementStartClose | ElementSelfClose => {
+                self.remove_duplicate_attributes(errors);
+
+                let depth = self.elements.len();
+                if let Err(e) = self.finish_opening_tag() {
+                    errors.push(self.in_current_element(e.into()));
+                    self.attributes.clear();
+
+                    // The element is kept without its attributes
+                    if self.elements.len() == depth {
+                        let mut name = String::new();
+                        let n = self.element_names.last().expect("Unknown element name");
+                        push_qualified_name(&mut name, n.value);
+                        let element = self.doc.create_element(&name[..]);
+                        self.append_to_either(element);
+                        self.elements.push(element);
+                    }
+                }
+                self.set_element_span(span.end());
+
+                if let ElementSelfClose = token {
+                    self.element_names.pop();
+                    self.elements.pop();
+                }
+            }
+
+            // An end tag closes the elements opened since the matching
+            // start tag, and is ignored if there is none
+            ElementClose(n) => match self
+                .element_names
+                .iter()
+                .rposition(|open| open.value == n.value)
+            {
+                Some(i) => {
+                    if i + 1 != self.element_names.len() {
+                        let e = Error::new(n.offset, ErrorKind::MismatchedElementEndName);
+                        errors.push(self.in_current_element(e));
+                    }
+
+                    while self.element_names.len() > i {
+                        let end = if self.element_names.len() == i + 1 {
+                            span.end()
+                        } else {
+                            span.start()
+                        };
+                        self.set_element_span(end);
+                        self.element_names.pop();
+                        self.elements.pop();
+                    }
+                }
+                None => {
+                    let e = Error::new(n.offset, ErrorKind::MismatchedElementEndName);
+                    errors.push(self.in_current_element(e));
+                }
+            },
+
+            token => {
+                let names = self.element_names.len();
+                let elements = self.elements.len();
+                let expanding = self.expanding.len();
+
+                if let Err(e) = self.consume(token, span) {
+                    errors.push(self.in_current_element(e));
+
+                    // Undo what an entity left half done
+                    self.element_names.truncate(names);
+                    self.elements.truncate(elements);
+                    self.expanding.truncate(expanding);
+                }
+            }
+        }
+    }
+
+    /// Keeps only the first of attributes with the same name
+    fn remove_duplicate_attributes(&mut self, errors: &mut Vec<Error>) {
+        let mut seen = Vec::new();
+        let element = *self.element_names.last().expect("Unknown element name");
+
+        self.attributes.retain(|a| {
+            let name = a.name.value;
+            if !seen.contains(&name) {
+                seen.push(name);
+                return true;
+            }
+
+            let kind = match (name.prefix, name.local_part) {
+                (Some("xmlns"), _) => ErrorKind::RedefinedNamespace,
+                (None, "xmlns") => ErrorKind::RedefinedDefaultNamespace,
+                _ => ErrorKind::DuplicateAttribute,
+            };
+            errors.push(Error::new(a.name.offset, kind).in_element(element));
+            false
+        });
+    }
+}
+
+/// Counts the lines and columns in a run of text. `\n`, `\r` and
+/// `\r\n` each end a line.
+#[derive(Debug, Copy, Clone, Default)]
+struct LineCounter {
+    lines: usize,
+    column: usize,
+    after_cr: bool,
+}
+
+impl LineCounter {
+    fn advance(&mut self, text: &str) {
+        for c in text.chars() {
+            match c {
+                '\n' if self.after_cr => {}
+                '\n' | '\r' => {
+                    self.lines += 1;
+                    self.column = 0;
+                }
+                _ => self.column += 1,
+            }
+            self.after_cr = c == '\r';
+        }
+    }
+}
+
+fn is_line_end(c: char) -> bool {
+    c == '\n' || c == '\r'
+}
+
+/// The most characters kept on either side of an error in its line text
+const LINE_TEXT_CONTEXT: usize = 80;
+
+/// Finds the lines and columns of increasing offsets in one pass over
+/// the text.
+#[derive(Debug)]
+struct Locator<'a> {
+    xml: &'a str,
+    offset: usize,
+    line_start: usize,
+    lines: LineCounter,
+}
+
+impl<'a> Locator<'a> {
+    fn new(xml: &'a str, lines: LineCounter) -> Self {
+        Locator {
+            xml,
+            offset: 0,
+            line_start: 0,
+            lines,
+        }
+    }
+
+    /// Offsets before the current one stay where they are
+    fn advance_to(&mut self, offset: usize) {
+        let offset = cmp::max(self.offset, cmp::min(offset, self.xml.len()));
+        let skipped = &self.xml[self.offset..offset];
+
+        self.lines.advance(skipped);
+        if let Some(i) = skipped.rfind(is_line_end) {
+            self.line_start = self.offset + i + 1;
+        }
+        self.offset = offset;
+    }
+
+    fn line(&self) -> usize {
+        self.lines.lines + 1
+    }
+
+    fn column(&self) -> usize {
+        self.lines.column + 1
+    }
+
+    /// The line around the current offset, shortened to a bounded
+    /// number of characters on either side of it, and the column
+    /// where it starts
+    fn line_text(&self) -> (&'a str, usize) {
+        let before = &self.xml[self.line_start..self.offset];
+        let mut start = self.offset;
+        let mut shown = 0;
+        for (i, _) in before.char_indices().rev().take(LINE_TEXT_CONTEXT) {
+            start = self.line_start + i;
+            shown += 1;
+        }
+
+        let end = self.xml[self.offset..]
+            .char_indices()
+            .enumerate()
+            .find(|&(n, (_, c))| n == LINE_TEXT_CONTEXT || is_line_end(c))
+            .map_or(self.xml.len(), |(_, (i, _))| self.offset + i);
+
+        (&self.xml[start..end], self.column() - shown)
+    }
+}
+
+/// The element that was being parsed when an error occurred
+#[derive(Debug)]
+struct ErrorElement {
+    name: String,
+    offset: usize,
+    line: Option<usize>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug)]
 pub struct Error {
     location: usize,
-    errors: BTreeSet<SpecificError>,
+    line: usize,
+    column: usize,
+    line_text: Box<str>,
+    line_text_column: usize,
+    errors: BTreeSet<ErrorKind>,
+    element: Option<ErrorElement>,
 }
 
 impl Error {
-    fn new(location: usize, error: SpecificError) -> Self {
+    fn new(location: usize, error: ErrorKind) -> Self {
         let mut errors = BTreeSet::new();
         errors.insert(error);
-        Error { location, errors }
+        Error::with_errors(location, errors)
+    }
+
+    fn with_errors(location: usize, errors: BTreeSet<ErrorKind>) -> Self {
+        Error {
+            location,
+            line: 0,
+            column: 0,
+            line_text: "".into(),
+            line_text_column: 1,
+            errors,
+            element: None,
+        }
+    }
+
+    fn in_element(mut self, name: Span<PrefixedName<'_>>) -> Self {
+        let mut qualified_name = String::new();
+        push_qualified_name(&mut qualified_name, name.value);
+
+        self.element = Some(ErrorElement {
+            name: qualified_name,
+            offset: name.offset,
+            line: None,
+        });
+        self
+    }
+
+    /// Fills in the line and column of the error. `xml` is the part of
+    /// the document starting at offset `start`, and `lines` has counted
+    /// the text before that.
+    fn locate(mut self, xml: &str, start: usize, lines: LineCounter) -> Self {
+        if let Some(ref mut element) = self.element {
+            // The start of the element may have been discarded already
+            if element.offset >= start && element.offset - start <= xml.len() {
+                let mut locator = Locator::new(xml, lines);
+                locator.advance_to(element.offset - start);
+                element.line = Some(locator.line());
+            }
+        }
+
+        let mut locator = Locator::new(xml, lines);
+        locator.advance_to(self.location.saturating_sub(start));
+        self.located_at(&locator);
+        self
+    }
+
+    fn located_in(self, xml: &str) -> Self {
+        self.locate(xml, 0, LineCounter::default())
+    }
+
+    fn located_at(&mut self, locator: &Locator<'_>) {
+        self.line = locator.line();
+        self.column = locator.column();
+        let (line_text, line_text_column) = locator.line_text();
+        self.line_text = line_text.into();
+        self.line_text_column = line_text_column;
+    }
+
+    /// Locates many errors in the same document without rescanning it
+    /// for each one
+    fn locate_all(mut errors: Vec<Error>, xml: &str) -> Vec<Error> {
+        {
+            let mut by_location: Vec<_> = errors.iter_mut().collect();
+            by_location.sort_by_key(|e| e.location);
+
+            let mut locator = Locator::new(xml, LineCounter::default());
+            for error in by_location {
+                locator.advance_to(error.location);
+                error.located_at(&locator);
+            }
+        }
+
+        let mut elements: Vec<_> = errors
+            .iter_mut()
+            .filter_map(|e| e.element.as_mut())
+            .collect();
+        elements.sort_by_key(|e| e.offset);
+
+        let mut locator = Locator::new(xml, LineCounter::default());
+        for element in elements {
+            locator.advance_to(element.offset);
+            element.line = Some(locator.line());
+        }
+
+        errors
     }
 
+    /// The byte offset of the error in the document
     pub fn location(&self) -> usize {
         self.location
     }
+
+    /// The line of the error, starting at 1
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The column of the error in characters, starting at 1
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// The text of the line containing the error, without the line
+    /// ending. Very long lines are shortened to the text around the
+    /// error, and `line_text_column` tells where that text starts. When
+    /// reading from an `io::BufRead`, the start of a very long line may
+    /// already have been discarded.
+    pub fn line_text(&self) -> &str {
+        &self.line_text
+    }
+
+    /// Where `line_text` starts in its line, as a column starting
+    /// at 1. It is past the start of the line when the start was left
+    /// out.
+    pub fn line_text_column(&self) -> usize {
+        self.line_text_column
+    }
+
+    /// The most relevant kind of problem found. A problem with what
+    /// was read, such as an unknown entity, is preferred over what
+    /// would have been acceptable instead.
+    pub fn kind(&self) -> ErrorKind {
+        *self
+            .errors
+            .iter()
+            .find(|k| k.expectation().is_none())
+            .or_else(|| self.errors.iter().next())
+            .expect("An error without a kind")
+    }
+
+    /// All of the problems found at the location of the error. When
+    /// parsing fails, there are usually several things that would have
+    /// been acceptable at that location.
+    pub fn kinds<'a>(&'a self) -> impl Iterator<Item = ErrorKind> + 'a {
+        self.errors.iter().cloned()
+    }
+}
+
+// The line and column are derived from the location
+impl PartialEq for Error {
+    fn eq(&self, other: &Error) -> bool {
+        self.location == other.location && self.errors == other.errors
+    }
 }
 
-impl From<(usize, Vec<SpecificError>)> for Error {
-    fn from(other: (usize, Vec<SpecificError>)) -> Self {
+impl Eq for Error {}
+
+impl From<(usize, Vec<ErrorKind>)> for Error {
+    fn from(other: (usize, Vec<ErrorKind>)) -> Self {
         let (location, errors) = other;
-        let errors = errors.into_iter().collect();
-        Error { location, errors }
+        Error::with_errors(location, errors.into_iter().collect())
     }
 }
 
-impl From<Span<SpecificError>> for Error {
-    fn from(other: Span<SpecificError>) -> Self {
+impl From<Span<ErrorKind>> for Error {
+    fn from(other: Span<ErrorKind>) -> Self {
         Self::new(other.offset, other.value)
     }
 }
@@ -1140,9 +3420,60 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "XML parsing error at {}: {:?}",
-            self.location, self.errors
-        )
+            "XML parsing error at line {}, column {}: ",
+            self.line, self.column
+        )?;
+
+        // Mentioning the name of the unclosed element is clearer than
+        // saying that something is wrong with it
+        let closing_tag = |kind: ErrorKind| match (kind, &self.element) {
+            (ErrorKind::UnclosedElement, &Some(ref e))
+            | (ErrorKind::MismatchedElementEndName, &Some(ref e)) => {
+                Some(format!("'</{}>'", e.name).into())
+            }
+            _ => None,
+        };
+        let expectation = |kind: ErrorKind| closing_tag(kind).or_else(|| kind.expectation());
+
+        let expected: Vec<Cow<'_, str>> = self.kinds().filter_map(expectation).collect();
+        let mut sentences: Vec<String> = Vec::new();
+        if !expected.is_empty() {
+            sentences.push(format!("expected {}", join_alternatives(&expected)));
+        }
+        sentences.extend(
+            self.kinds()
+                .filter(|&k| expectation(k).is_none())
+                .map(|k| k.to_string()),
+        );
+        f.write_str(&sentences.join("; "))?;
+
+        if let Some(ref element) = self.element {
+            let closing = [
+                ErrorKind::ExpectedElementEnd,
+                ErrorKind::ExpectedElementSelfClosed,
+                ErrorKind::MismatchedElementEndName,
+                ErrorKind::UnclosedElement,
+            ];
+            if self.kinds().any(|k| closing.contains(&k)) {
+                write!(f, " to close element <{}>", element.name)?;
+            } else {
+                write!(f, " in element <{}>", element.name)?;
+            }
+            if let Some(line) = element.line {
+                write!(f, " opened at line {}", line)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Joins the alternatives into a phrase like `a, b or c`
+fn join_alternatives(alternatives: &[Cow<'_, str>]) -> String {
+    match alternatives.split_last() {
+        Some((last, [])) => last.to_string(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => String::new(),
     }
 }
 
@@ -1152,740 +3483,3801 @@ impl error::Error for Error {
     }
 }
 
-/// Parses a string into a DOM. On failure, the location of the
-/// parsing failure and all possible failures will be returned.
-pub fn parse(xml: &str) -> Result<super::Package, Error> {
-    let parser = PullParser::new(xml);
-    let package = super::Package::new();
-
-    {
-        let doc = package.as_document();
-        let mut builder = DomBuilder::new(doc);
+/// Limits on the documents that a `Parser` accepts, to protect
+/// against hostile input. A document that goes beyond a limit fails
+/// to parse with `ErrorKind::LimitExceeded`.
+///
+/// By default, only entity expansion is limited: entities may be
+/// nested 32 deep, and may expand to 100 times the size of the
+/// document and the external entities read so far once they have
+/// produced 1 MiB of text. This stops a small document from
+/// expanding into gigabytes, as in the "billion laughs" attack.
+///
+/// ### Example
+///
+/// ```
+/// use sxd_document::parser::{ErrorKind, Limit, ParseLimits, Parser};
+///
+/// let limits = ParseLimits::new().set_max_depth(2);
+/// let parser = Parser::new().set_limits(limits);
+///
+/// assert!(parser.parse("<a><b/></a>").is_ok());
+/// let e = parser.parse("<a><b><c/></b></a>").unwrap_err();
+/// assert_eq!(e.kind(), ErrorKind::LimitExceeded(Limit::Depth));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParseLimits {
+    max_depth: usize,
+    max_attributes: usize,
+    max_name_length: usize,
+    max_text_length: usize,
+    max_nodes: usize,
+    max_entity_depth: usize,
+    max_entity_amplification: usize,
+}
 
-        for token in parser {
-            let token = token?;
-            builder.consume(token)?;
+#[allow(clippy::legacy_numeric_constants)] // `usize::MAX` needs Rust 1.43
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: std::usize::MAX,
+            max_attributes: std::usize::MAX,
+            max_name_length: std::usize::MAX,
+            max_text_length: std::usize::MAX,
+            max_nodes: std::usize::MAX,
+            max_entity_depth: 32,
+            max_entity_amplification: 100,
         }
+    }
+}
 
-        if builder.has_unclosed_elements() {
-            return Err(Error::new(xml.len(), SpecificError::UnclosedElement));
-        }
+impl ParseLimits {
+    /// Create new `ParseLimits` with the default limits.
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    Ok(package)
-}
+    /// Set how deeply elements may be nested.
+    pub fn set_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
 
-type DomBuilderResult<T> = Result<T, Span<SpecificError>>;
+    /// Set how many attributes, including namespace declarations, a
+    /// start tag may have.
+    pub fn set_max_attributes(mut self, max_attributes: usize) -> Self {
+        self.max_attributes = max_attributes;
+        self
+    }
 
-fn decode_reference<F>(ref_data: Reference<'_>, cb: F) -> DomBuilderResult<()>
-where
-    F: FnOnce(&str),
-{
-    match ref_data {
-        DecimalChar(span) => u32::from_str_radix(span.value, 10)
-            .ok()
-            .and_then(char::from_u32)
-            .ok_or_else(|| span.map(|_| SpecificError::InvalidDecimalReference))
-            .and_then(|c| {
-                let s: String = iter::repeat(c).take(1).collect();
-                cb(&s);
-                Ok(())
-            }),
-        HexChar(span) => u32::from_str_radix(span.value, 16)
-            .ok()
-            .and_then(char::from_u32)
-            .ok_or_else(|| span.map(|_| SpecificError::InvalidHexReference))
-            .and_then(|c| {
-                let s: String = iter::repeat(c).take(1).collect();
-                cb(&s);
-                Ok(())
-            }),
-        Entity(span) => {
-            let s = match span.value {
-                "amp" => "&",
-                "lt" => "<",
-                "gt" => ">",
-                "apos" => "'",
-                "quot" => "\"",
-                _ => return Err(span.map(|_| SpecificError::UnknownNamedReference)),
-            };
-            cb(s);
-            Ok(())
-        }
+    /// Set the longest name, in bytes, that an element, attribute or
+    /// processing instruction target may have. A prefix counts
+    /// towards the length.
+    pub fn set_max_name_length(mut self, max_name_length: usize) -> Self {
+        self.max_name_length = max_name_length;
+        self
     }
-}
 
-#[derive(Debug, Copy, Clone)]
-enum AttributeValue<'a> {
-    ReferenceAttributeValue(Reference<'a>),
-    LiteralAttributeValue(&'a str),
+    /// Set the longest text node or attribute value, in bytes.
+    pub fn set_max_text_length(mut self, max_text_length: usize) -> Self {
+        self.max_text_length = max_text_length;
+        self
+    }
+
+    /// Set how many elements, text nodes, comments and processing
+    /// instructions the document may have.
+    pub fn set_max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = max_nodes;
+        self
+    }
+
+    /// Set how deeply entity references may be nested in the
+    /// replacement text of other entities.
+    pub fn set_max_entity_depth(mut self, max_entity_depth: usize) -> Self {
+        self.max_entity_depth = max_entity_depth;
+        self
+    }
+
+    /// Set how many times larger than the input the text produced by
+    /// entity references may grow.
+    pub fn set_max_entity_amplification(mut self, max_entity_amplification: usize) -> Self {
+        self.max_entity_amplification = max_entity_amplification;
+        self
+    }
 }
 
-struct AttributeValueBuilder {
-    value: String,
+/// Entity expansions smaller than this are not checked for
+/// amplification, so that short documents may use larger entities
+const MIN_CHECKED_EXPANSION: usize = 1024 * 1024;
+
+/// Keeps track of the text produced by entity references, to enforce
+/// the entity limits
+#[derive(Debug)]
+struct Expansion {
+    max_depth: usize,
+    max_amplification: usize,
+    /// The length of all replacement text used so far
+    expanded: Cell<usize>,
+    /// The length of the external entities and DTD subset read so far
+    loaded: Cell<usize>,
 }
 
-impl AttributeValueBuilder {
-    fn convert(values: &[AttributeValue<'_>]) -> DomBuilderResult<String> {
-        let mut builder = AttributeValueBuilder::new();
-        builder.ingest(values)?;
-        Ok(builder.implode())
+impl Expansion {
+    fn new(limits: &ParseLimits) -> Expansion {
+        Expansion {
+            max_depth: limits.max_entity_depth,
+            max_amplification: limits.max_entity_amplification,
+            expanded: Cell::new(0),
+            loaded: Cell::new(0),
+        }
     }
 
-    fn new() -> AttributeValueBuilder {
-        AttributeValueBuilder {
-            value: String::new(),
+    /// Records that the reference will be replaced by `len` bytes of
+    /// text. `depth` is the number of entities that are being
+    /// expanded already.
+    fn add(&self, name: Span<&str>, depth: usize, len: usize) -> DomBuilderResult<()> {
+        if depth >= self.max_depth {
+            return Err(name.map(|_| ErrorKind::LimitExceeded(Limit::EntityDepth)));
         }
-    }
 
-    fn ingest(&mut self, values: &[AttributeValue<'_>]) -> DomBuilderResult<()> {
-        use self::AttributeValue::*;
+        let expanded = self.expanded.get().saturating_add(len);
+        self.expanded.set(expanded);
 
-        for value in values.iter() {
-            match *value {
-                LiteralAttributeValue(v) => self.value.push_str(v),
-                ReferenceAttributeValue(r) => decode_reference(r, |s| self.value.push_str(s))?,
-            }
+        let input = name.offset.saturating_add(self.loaded.get()).max(1);
+        if expanded > MIN_CHECKED_EXPANSION && expanded / input >= self.max_amplification {
+            return Err(name.map(|_| ErrorKind::LimitExceeded(Limit::EntityAmplification)));
         }
 
         Ok(())
     }
 
-    fn clear(&mut self) {
-        self.value.clear();
+    fn load(&self, len: usize) {
+        self.loaded.set(self.loaded.get().saturating_add(len));
     }
+}
 
-    fn implode(self) -> String {
-        self.value
-    }
+/// What the parser does with whitespace in text. Whitespace is always
+/// kept inside elements where `xml:space="preserve"` is set, on the
+/// element or an ancestor, and in CDATA sections kept with
+/// `Parser::set_preserve_cdata`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(clippy::manual_non_exhaustive)]
+pub enum WhitespaceMode {
+    /// Keep all text as it is written
+    Keep,
+    /// Remove the text between tags that is only whitespace, as is
+    /// usually added to indent a document
+    RemoveBlank,
+    /// Remove the text that is only whitespace, and the whitespace at
+    /// the start and end of all other text
+    Trim,
+
+    #[doc(hidden)]
+    __Nonexhaustive,
 }
 
-impl Deref for AttributeValueBuilder {
-    type Target = str;
+/// Parses documents into a DOM with non-default settings. The free
+/// functions in this module use the default settings.
+///
+/// ### Example
+///
+/// ```
+/// use sxd_document::parser::Parser;
+///
+/// let xml = "<!DOCTYPE a [<!ATTLIST a kind CDATA 'plain'>]><a/>";
+/// let package = Parser::new()
+///     .set_apply_attribute_defaults(true)
+///     .parse(xml)
+///     .expect("Failed to parse");
+///
+/// let doc = package.as_document();
+/// let a = doc.root().children()[1].element().unwrap();
+/// let kind = a.attribute("kind").unwrap();
+/// assert_eq!(kind.value(), "plain");
+/// assert!(!kind.is_specified());
+/// ```
+///
+/// Settings can be combined, and the same `Parser` can be used for
+/// any number of documents:
+///
+/// ```
+/// use sxd_document::parser::{ParseLimits, Parser, WhitespaceMode};
+///
+/// let parser = Parser::new()
+///     .set_preserve_cdata(true)
+///     .set_whitespace(WhitespaceMode::RemoveBlank)
+///     .add_entity("nbsp", "&#160;")
+///     .set_limits(ParseLimits::new().set_max_depth(64));
+///
+/// let package = parser
+///     .parse("<p>\n  <b>a&nbsp;b</b>\n</p>")
+///     .expect("Failed to parse");
+///
+/// let doc = package.as_document();
+/// let p = doc.root().children()[0].element().unwrap();
+/// assert_eq!(p.children().len(), 1);
+/// ```
+#[derive(Clone)]
+pub struct Parser {
+    namespace_aware: bool,
+    apply_attribute_defaults: bool,
+    preserve_cdata: bool,
+    whitespace: WhitespaceMode,
+    entities: HashMap<String, String>,
+    html_entities: bool,
+    source_spans: bool,
+    resolver: Rc<dyn Resolver>,
+    limits: ParseLimits,
+}
 
-    fn deref(&self) -> &str {
-        &self.value
+impl Default for Parser {
+    fn default() -> Self {
+        Self {
+            namespace_aware: true,
+            apply_attribute_defaults: false,
+            preserve_cdata: false,
+            whitespace: WhitespaceMode::Keep,
+            entities: HashMap::new(),
+            html_entities: false,
+            source_spans: false,
+            resolver: Rc::new(DenyAll),
+            limits: ParseLimits::default(),
+        }
     }
 }
 
-#[derive(Debug)]
-struct DeferredAttribute<'d> {
-    name: Span<PrefixedName<'d>>,
-    values: Vec<AttributeValue<'d>>,
+impl fmt::Debug for Parser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Parser")
+            .field("namespace_aware", &self.namespace_aware)
+            .field("apply_attribute_defaults", &self.apply_attribute_defaults)
+            .field("preserve_cdata", &self.preserve_cdata)
+            .field("whitespace", &self.whitespace)
+            .field("entities", &self.entities)
+            .field("html_entities", &self.html_entities)
+            .field("source_spans", &self.source_spans)
+            .field("limits", &self.limits)
+            .finish()
+    }
 }
 
-struct DeferredAttributes<'a> {
-    attributes: Vec<DeferredAttribute<'a>>,
-    namespaces: Vec<DeferredAttribute<'a>>,
-    default_namespaces: Vec<DeferredAttribute<'a>>,
-}
+impl Parser {
+    /// Create a new `Parser` with default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether namespace declarations are processed. Without them,
+    /// `xmlns` attributes are kept as ordinary attributes and names are
+    /// kept as written, with any prefix as part of the local name.
+    /// Defaults to `true`.
+    pub fn set_namespace_aware(mut self, namespace_aware: bool) -> Self {
+        self.namespace_aware = namespace_aware;
+        self
+    }
+
+    /// Set whether attributes declared with a default or `#FIXED`
+    /// value in an `<!ATTLIST>` are added to
+    /// elements that leave them out. Added attributes report `false`
+    /// from `Attribute::is_specified`. Defaults to `false`.
+    pub fn set_apply_attribute_defaults(mut self, apply_attribute_defaults: bool) -> Self {
+        self.apply_attribute_defaults = apply_attribute_defaults;
+        self
+    }
+
+    /// Set whether CDATA sections are kept as text nodes that report
+    /// `true` from `Text::is_cdata`, so that they are written back out
+    /// as CDATA sections. Defaults to `false`, which treats them as
+    /// any other text.
+    pub fn set_preserve_cdata(mut self, preserve_cdata: bool) -> Self {
+        self.preserve_cdata = preserve_cdata;
+        self
+    }
+
+    /// Set what is done with whitespace in text. Defaults to
+    /// `WhitespaceMode::Keep`.
+    pub fn set_whitespace(mut self, whitespace: WhitespaceMode) -> Self {
+        self.whitespace = whitespace;
+        self
+    }
+
+    /// Declare an entity for every document that is parsed, as if it
+    /// were declared in the internal subset. The text is parsed as
+    /// replacement text, so it may contain markup and references.
+    /// Declarations in the document take precedence.
+    pub fn add_entity(mut self, name: &str, text: &str) -> Self {
+        self.entities.insert(name.into(), text.into());
+        self
+    }
+
+    /// Declare the named character entities of XHTML 1.0, such as
+    /// `&nbsp;` and `&eacute;`, for every document that is parsed.
+    /// Declarations in the document and entities added with
+    /// `add_entity` take precedence. Defaults to `false`.
+    pub fn set_html_entities(mut self, html_entities: bool) -> Self {
+        self.html_entities = html_entities;
+        self
+    }
+
+    /// Set whether the parser records where each element, attribute,
+    /// text, comment and processing instruction was in the document,
+    /// as a `SourceSpan` returned by their `source_span` methods.
+    /// Defaults to `false`.
+    pub fn set_source_spans(mut self, source_spans: bool) -> Self {
+        self.source_spans = source_spans;
+        self
+    }
+
+    /// Set what loads the external DTD subset and external entities.
+    /// Defaults to `DenyAll`, which loads nothing: the external DTD
+    /// subset is skipped and references to external entities are an
+    /// error.
+    ///
+    /// The external DTD subset is read with the same rules as the
+    /// internal subset, so conditional sections are not supported.
+    pub fn set_resolver<R>(mut self, resolver: R) -> Self
+    where
+        R: Resolver + 'static,
+    {
+        self.resolver = Rc::new(resolver);
+        self
+    }
+
+    /// Set the limits on the documents that are accepted.
+    pub fn set_limits(mut self, limits: ParseLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Parses a string into a DOM, as `parse` does.
+    pub fn parse(&self, xml: &str) -> Result<super::Package, Error> {
+        self.build_dom(xml).map_err(|e| e.located_in(xml))
+    }
+
+    /// Parses a string into a DOM, repairing the document instead of
+    /// failing. Returns the DOM that could be built along with every
+    /// error that was found, in document order.
+    ///
+    /// To repair the document:
+    ///
+    /// - Elements left open are closed.
+    /// - An end tag closes every element opened since its matching
+    ///   start tag, and is ignored if there is none.
+    /// - Text that cannot be parsed, such as a stray `&` or `<`, is
+    ///   kept as text.
+    /// - The rest of a malformed start tag is skipped.
+    /// - Only the first of duplicate attributes is kept.
+    /// - Other nodes that cannot be added, such as unknown entity
+    ///   references, are left out.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use sxd_document::parser::{ErrorKind, Parser};
+    ///
+    /// let (package, errors) = Parser::new().parse_recovering("<a>1 & 2<b></a>");
+    ///
+    /// let doc = package.as_document();
+    /// let a = doc.root().children()[0].element().unwrap();
+    /// assert_eq!(a.children().len(), 3);
+    ///
+    /// let kinds: Vec<_> = errors.iter().map(|e| e.kind()).collect();
+    /// assert_eq!(kinds.len(), 2);
+    /// assert_eq!(kinds[1], ErrorKind::MismatchedElementEndName);
+    /// ```
+    pub fn parse_recovering(&self, xml: &str) -> (super::Package, Vec<Error>) {
+        let mut parser = PullParser::new(xml);
+        let package = super::Package::new();
+        let mut errors = Vec::new();
 
-impl<'a> DeferredAttributes<'a> {
-    fn new(attributes: Vec<DeferredAttribute<'a>>) -> DeferredAttributes<'a> {
-        let (mut namespaces, attributes): (Vec<_>, Vec<_>) = attributes
-            .into_iter()
-            .partition(|attr| attr.name.value.prefix == Some("xmlns"));
+        {
+            let doc = package.as_document();
+            let mut builder = DomBuilder::new(doc, self);
+
+            loop {
+                let offset = parser.offset();
+                match parser.next() {
+                    Some(Ok(token @ Token::ElementClose(..))) => {
+                        let span = SourceSpan::new(offset, parser.offset());
+                        builder.consume_recovering(token, span, &mut errors);
+
+                        // The end tag may have closed more than one element
+                        parser.resume_content(builder.elements.len());
+                    }
+                    Some(Ok(token)) => {
+                        let span = SourceSpan::new(offset, parser.offset());
+                        builder.consume_recovering(token, span, &mut errors);
+                    }
+                    Some(Err(e)) => {
+                        // Running out of input is reported as unclosed elements
+                        if offset == xml.len() && builder.has_unclosed_elements() {
+                            break;
+                        }
+                        errors.push(builder.in_current_element(e.into()));
+
+                        match parser.recover() {
+                            Some(tokens) => {
+                                let span = SourceSpan::new(offset, parser.offset());
+                                for token in tokens {
+                                    builder.consume_recovering(token, span, &mut errors);
+                                }
+                            }
+                            None => break,
+                        }
+                    }
+                    None => break,
+                }
+            }
 
-        let (mut default_namespaces, mut attributes): (Vec<_>, Vec<_>) = attributes
-            .into_iter()
-            .partition(|attr| attr.name.value.local_part == "xmlns");
+            if builder.has_unclosed_elements() {
+                let e = Error::new(xml.len(), ErrorKind::UnclosedElement);
+                errors.push(builder.in_current_element(e));
+            }
 
-        fn sort_by_name(
-            a: &DeferredAttribute<'_>,
-            b: &DeferredAttribute<'_>,
-        ) -> ::std::cmp::Ordering {
-            a.name.value.cmp(&b.name.value)
+            builder.finish();
         }
 
-        attributes.sort_by(sort_by_name);
-        namespaces.sort_by(sort_by_name);
-        default_namespaces.sort_by(sort_by_name);
+        (package, Error::locate_all(errors, xml))
+    }
+
+    /// Parses content, as found in the body of an element or an
+    /// external parsed entity, and appends it to `element`. The content
+    /// may mix text with any number of elements, and may start with a
+    /// text declaration. Prefixes are resolved against the namespaces
+    /// in scope on `element`.
+    ///
+    /// Nothing is appended if the content is not well-formed.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// use sxd_document::{parser::Parser, Package};
+    ///
+    /// let package = Package::new();
+    /// let doc = package.as_document();
+    /// let p = doc.create_element("p");
+    /// p.register_prefix("x", "urn:x");
+    ///
+    /// Parser::new()
+    ///     .parse_fragment_into(p, "text <b>bold</b> <x:i/> more")
+    ///     .expect("Failed to parse the fragment");
+    ///
+    /// let children = p.children();
+    /// assert_eq!(children.len(), 5);
+    /// assert_eq!(children[3].element().unwrap().name().namespace_uri(), Some("urn:x"));
+    /// ```
+    pub fn parse_fragment_into<'d>(
+        &self,
+        element: dom::Element<'d>,
+        xml: &str,
+    ) -> Result<(), Error> {
+        let existing = element.children().len();
+
+        self.build_fragment(element, xml).map_err(|e| {
+            for child in element.children().into_iter().skip(existing) {
+                element.remove_child(child);
+            }
+            e.located_in(xml)
+        })
+    }
 
-        DeferredAttributes {
-            attributes,
-            namespaces,
-            default_namespaces,
-        }
+    /// Parses bytes into a DOM, as `parse_bytes` does.
+    pub fn parse_bytes(&self, bytes: &[u8]) -> Result<super::Package, Error> {
+        let xml = decode(bytes)?;
+        self.parse(&xml)
     }
 
-    fn check_duplicates(&self) -> DomBuilderResult<()> {
-        for w in self.attributes.windows(2) {
-            if w[0].name.value == w[1].name.value {
-                return Err(w[1].name.map(|_| SpecificError::DuplicateAttribute));
+    /// Parses a document from a reader into a DOM, as `parse_reader`
+    /// does.
+    pub fn parse_reader<R: BufRead>(&self, reader: R) -> Result<super::Package, ReadError> {
+        let mut input = ChunkedInput::new(reader);
+        let package = super::Package::new();
+
+        {
+            let doc = package.as_document();
+            let mut builder = DomBuilder::new(doc, self);
+            let mut state = State::AtBeginning;
+
+            loop {
+                let len = input.fill_event(state)?;
+                if len == 0 {
+                    break;
+                }
+
+                let (xml, offset) = input.unconsumed();
+                let mut tokens = PullParser::resume(&xml[..len], offset, state);
+
+                while tokens.offset() < offset + len {
+                    let token_offset = tokens.offset();
+                    match tokens.next() {
+                        Some(token) => {
+                            // The input buffer is reused, so the DOM needs its own copy
+                            let span = SourceSpan::new(token_offset, tokens.offset());
+                            let result = token
+                                .map_err(Error::from)
+                                .and_then(|token| builder.consume(token.intern(doc), span));
+
+                            if let Err(e) = result {
+                                let e = builder.in_current_element(e);
+                                return Err(input.locate(e).into());
+                            }
+                        }
+                        None => break,
+                    }
+                }
+
+                let used = tokens.offset() - offset;
+                state = tokens.state;
+                input.consume(used);
+
+                if used == 0 {
+                    break;
+                }
+            }
+
+            if builder.has_unclosed_elements() {
+                let (_, offset) = input.unconsumed();
+                let e = Error::new(offset, ErrorKind::UnclosedElement);
+                return Err(input.locate(builder.in_current_element(e)).into());
             }
+
+            builder.finish();
         }
 
-        for w in self.namespaces.windows(2) {
-            if w[0].name.value == w[1].name.value {
-                return Err(w[1].name.map(|_| SpecificError::RedefinedNamespace));
+        Ok(package)
+    }
+
+    fn build_dom(&self, xml: &str) -> Result<super::Package, Error> {
+        let mut parser = PullParser::new(xml);
+        let package = super::Package::new();
+
+        {
+            let doc = package.as_document();
+            let mut builder = DomBuilder::new(doc, self);
+
+            loop {
+                let offset = parser.offset();
+                let token = match parser.next() {
+                    Some(token) => token.map_err(|e| builder.in_current_element(e.into()))?,
+                    None => break,
+                };
+                builder
+                    .consume(token, SourceSpan::new(offset, parser.offset()))
+                    .map_err(|e| builder.in_current_element(e))?;
             }
+
+            if builder.has_unclosed_elements() {
+                let e = Error::new(xml.len(), ErrorKind::UnclosedElement);
+                return Err(builder.in_current_element(e));
+            }
+
+            builder.finish();
+        }
+
+        Ok(package)
+    }
+
+    fn build_fragment<'d>(&self, element: dom::Element<'d>, xml: &str) -> Result<(), Error> {
+        let start = text_declaration(xml).map_or(0, |(len, _)| len);
+        let mut parser = PullParser {
+            fragment: true,
+            ..PullParser::resume(&xml[start..], start, State::Fragment)
+        };
+        let mut builder = DomBuilder::fragment(element, self);
+        let existing = element.children().len();
+
+        loop {
+            let offset = parser.offset();
+            let token = match parser.next() {
+                Some(token) => token.map_err(|e| builder.in_current_element(e.into()))?,
+                None => break,
+            };
+            // The DOM may outlive the string being parsed
+            let token = token.intern(builder.doc);
+            builder
+                .consume(token, SourceSpan::new(offset, parser.offset()))
+                .map_err(|e| builder.in_current_element(e))?;
+        }
+
+        if !builder.element_names.is_empty() {
+            let e = Error::new(xml.len(), ErrorKind::UnclosedElement);
+            return Err(builder.in_current_element(e));
         }
 
+        let added = element.children().into_iter().skip(existing);
+        builder.strip_whitespace(added.filter_map(|c| c.element()));
+
         Ok(())
     }
+}
 
-    fn attributes(&self) -> &[DeferredAttribute<'a>] {
-        &self.attributes
+/// Parses a string into a DOM. On failure, the location of the
+/// parsing failure and all possible failures will be returned.
+pub fn parse(xml: &str) -> Result<super::Package, Error> {
+    Parser::new().parse(xml)
+}
+
+/// Parses content with any number of top-level elements and text into
+/// an existing element. See `Parser::parse_fragment_into`.
+pub fn parse_fragment_into(element: dom::Element<'_>, xml: &str) -> Result<(), Error> {
+    Parser::new().parse_fragment_into(element, xml)
+}
+
+/// Parses bytes into a DOM. The encoding is determined as described
+/// in [Appendix F][F] of the XML specification, using the byte order
+/// mark, the first bytes of the document and the encoding declaration.
+/// UTF-8, UTF-16, ISO-8859-1 and US-ASCII are supported.
+///
+/// It is an error for the encoding declaration to contradict the
+/// encoding of the bytes. Error locations are offsets into the
+/// document after it has been decoded to UTF-8.
+///
+/// [F]: https://www.w3.org/TR/xml/#sec-guessing-no-ext-info
+///
+/// ### Example
+///
+/// ```
+/// use sxd_document::parser;
+///
+/// let bytes = b"<?xml version='1.0' encoding='ISO-8859-1'?><caf\xE9/>";
+/// let package = parser::parse_bytes(bytes).expect("Failed to parse");
+/// ```
+pub fn parse_bytes(bytes: &[u8]) -> Result<super::Package, Error> {
+    Parser::new().parse_bytes(bytes)
+}
+
+fn decode(bytes: &[u8]) -> Result<String, Error> {
+    let (detected, bom_len) = crate::encoding::detect(bytes)
+        .ok_or_else(|| Error::new(0, ErrorKind::UnsupportedEncoding).located_in(""))?;
+    let has_bom = bom_len > 0;
+    let bytes = &bytes[bom_len..];
+
+    let declaration = detected.decode_declaration(bytes);
+    let error = |offset, e| Error::new(offset, e).located_in(&declaration);
+    let declared = match PullParser::new(&declaration).next() {
+        Some(Ok(Token::XmlDeclaration(_, encoding, _))) => encoding,
+        _ => text_declaration(&declaration).map(|(_, encoding)| encoding),
+    };
+
+    let encoding = match declared {
+        Some(name) => {
+            let declared = Encoding::from_name(name.value, detected)
+                .ok_or_else(|| error(name.offset, ErrorKind::UnsupportedEncoding))?;
+
+            let compatible = if detected.is_utf16() {
+                declared == detected
+            } else if has_bom {
+                declared == Encoding::Utf8
+            } else {
+                !declared.is_utf16()
+            };
+
+            if !compatible {
+                return Err(error(name.offset, ErrorKind::MismatchedEncoding));
+            }
+
+            declared
+        }
+        // Without a byte order mark or an encoding declaration, a
+        // document has to be UTF-8
+        None if detected.is_utf16() && !has_bom => {
+            return Err(error(0, ErrorKind::MismatchedEncoding));
+        }
+        None => detected,
+    };
+
+    let mut xml = String::with_capacity(bytes.len());
+    match encoding.decode(bytes, &mut xml) {
+        Ok(()) => Ok(xml),
+        Err(offset) => Err(Error::new(offset, ErrorKind::InvalidEncodedData).located_in(&xml)),
     }
+}
 
-    fn namespaces(&self) -> &[DeferredAttribute<'a>] {
-        &self.namespaces
+/// Reads the text declaration at the start of an external entity,
+/// returning its length and the encoding it declares
+fn text_declaration(xml: &str) -> Option<(usize, Span<&str>)> {
+    let mut pm = XmlMaster::new();
+    let progress = parse_text_declaration(&mut pm, StringPoint::new(xml));
+
+    match pm.finish(progress) {
+        peresil::Progress {
+            status: peresil::Status::Success(encoding),
+            point,
+        } => Some((point.offset, encoding)),
+        _ => None,
     }
+}
 
-    fn default_namespace(&self) -> DomBuilderResult<Option<String>> {
-        match self.default_namespaces.len() {
-            0 => Ok(None),
-            1 => {
-                let ns = &self.default_namespaces[0];
-                let value = AttributeValueBuilder::convert(&ns.values)?;
-                Ok(Some(value))
+/// The smallest amount of input requested from a reader at once
+const MIN_READ_SIZE: usize = 8 * 1024;
+
+/// The most text before the current position that is kept to show
+/// the line of an error
+const MAX_LINE_CONTEXT: usize = 1024;
+
+/// Input from a reader, decoded as UTF-8. Only the part of the
+/// document that has not been consumed yet is kept in memory.
+struct ChunkedInput<R> {
+    reader: R,
+    text: String,
+    undecoded: Vec<u8>,
+    /// The offset in the document of the start of `text`
+    base: usize,
+    /// The length of the consumed prefix of `text`
+    consumed: usize,
+    /// The lines before the start of `text`
+    lines: LineCounter,
+    eof: bool,
+}
+
+impl<R: BufRead> ChunkedInput<R> {
+    fn new(reader: R) -> ChunkedInput<R> {
+        ChunkedInput {
+            reader,
+            text: String::new(),
+            undecoded: Vec::new(),
+            base: 0,
+            consumed: 0,
+            lines: LineCounter::default(),
+            eof: false,
+        }
+    }
+
+    /// The unconsumed input and its offset in the document
+    fn unconsumed(&self) -> (&str, usize) {
+        (&self.text[self.consumed..], self.base + self.consumed)
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.consumed += len;
+    }
+
+    /// Reads until the unconsumed input starts with a complete event
+    /// for the given parser state, returning the length of that
+    /// event. At the end of the input, all of the remaining input is
+    /// returned instead.
+    fn fill_event(&mut self, state: State) -> io::Result<usize> {
+        loop {
+            let (xml, _) = self.unconsumed();
+            if let Some(len) = end_of_event(xml, state) {
+                return Ok(len);
             }
-            _ => {
-                let last_namespace = self.default_namespaces.last().unwrap();
-                Err(last_namespace
-                    .name
-                    .map(|_| SpecificError::RedefinedDefaultNamespace))
+            if self.eof {
+                return Ok(xml.len());
+            }
+            self.read_more()?;
+        }
+    }
+
+    /// Adds the line and column to an error in the buffered input
+    fn locate(&self, error: Error) -> Error {
+        error.locate(&self.text, self.base, self.lines)
+    }
+
+    fn read_more(&mut self) -> io::Result<()> {
+        // Keep the start of the current line to show in errors, unless
+        // it is too long
+        let line_start = self.text[..self.consumed]
+            .rfind(is_line_end)
+            .map_or(0, |i| i + 1);
+        let discarded = if self.consumed - line_start <= MAX_LINE_CONTEXT {
+            line_start
+        } else {
+            self.consumed
+        };
+
+        self.lines.advance(&self.text[..discarded]);
+        self.text.drain(..discarded);
+        self.base += discarded;
+        self.consumed -= discarded;
+
+        // Reading at least as much as we already have keeps the cost
+        // of rescanning an incomplete event linear.
+        let wanted = cmp::max(self.text.len() - self.consumed, MIN_READ_SIZE);
+        let mut read = 0;
+
+        while read < wanted {
+            let len = match self.reader.fill_buf() {
+                Ok(bytes) => {
+                    self.undecoded.extend_from_slice(bytes);
+                    bytes.len()
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            if len == 0 {
+                self.eof = true;
+                break;
             }
+
+            self.reader.consume(len);
+            read += len;
         }
+
+        self.decode()
+    }
+
+    fn decode(&mut self) -> io::Result<()> {
+        let valid = match str::from_utf8(&self.undecoded) {
+            Ok(s) => s.len(),
+            // The last character may be split across reads
+            Err(ref e) if e.error_len().is_none() && !self.eof => e.valid_up_to(),
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stream did not contain valid UTF-8",
+                ))
+            }
+        };
+
+        let decoded = str::from_utf8(&self.undecoded[..valid]).expect("Input was validated");
+        self.text.push_str(decoded);
+        self.undecoded.drain(..valid);
+        Ok(())
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::{dom, Package, QName};
+/// Finds the end of the next event in the given parser state, if the
+/// event is completely contained in `xml`.
+fn end_of_event(xml: &str, state: State) -> Option<usize> {
+    match state {
+        State::Content(..) => {
+            if xml.starts_with('<') {
+                xml.end_of_markup()
+            } else {
+                // Text continues until the next markup
+                xml.find('<')
+            }
+        }
+        _ => {
+            let space = xml.end_of_space().unwrap_or(0);
+            (&xml[space..]).end_of_markup().map(|end| space + end)
+        }
+    }
+}
 
-    macro_rules! assert_qname_eq(
-        ($l:expr, $r:expr) => (assert_eq!(Into::<QName<'_>>::into($l), $r.into()));
-    );
+/// An error that occurred while parsing a document from a reader
+#[derive(Debug)]
+pub enum ReadError {
+    /// The reader failed or the input was not valid UTF-8
+    Io(io::Error),
+    /// The input was not well-formed XML
+    Parse(Error),
+}
 
-    fn full_parse(xml: &str) -> Result<Package, Error> {
-        super::parse(xml)
+impl From<io::Error> for ReadError {
+    fn from(other: io::Error) -> Self {
+        ReadError::Io(other)
     }
+}
 
-    fn quick_parse(xml: &str) -> Package {
-        full_parse(xml).expect("Failed to parse the XML string")
+impl From<Error> for ReadError {
+    fn from(other: Error) -> Self {
+        ReadError::Parse(other)
     }
+}
 
-    fn top<'d>(doc: &'d dom::Document<'d>) -> dom::Element<'d> {
-        doc.root().children()[0].element().unwrap()
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ReadError::Io(ref e) => write!(f, "Unable to read XML: {}", e),
+            ReadError::Parse(ref e) => e.fmt(f),
+        }
     }
+}
 
-    #[test]
-    fn a_document_with_a_prolog() {
-        let package = quick_parse("<?xml version='1.0' ?><hello />");
-        let doc = package.as_document();
-        let top = top(&doc);
+impl error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ReadError::Io(ref e) => Some(e),
+            ReadError::Parse(ref e) => Some(e),
+        }
+    }
+}
+
+/// Parses a document from a reader into a DOM. The input is read
+/// incrementally; only the part of the document needed for the
+/// current token is buffered, not the entire document.
+///
+/// ### Example
+///
+/// ```
+/// use sxd_document::parser;
+///
+/// let input = "<data>Science</data>".as_bytes();
+/// let package = parser::parse_reader(input).expect("Failed to parse");
+/// ```
+pub fn parse_reader<R: BufRead>(reader: R) -> Result<super::Package, ReadError> {
+    Parser::new().parse_reader(reader)
+}
+
+type DomBuilderResult<T> = Result<T, Span<ErrorKind>>;
+
+fn decode_reference<F>(ref_data: Reference<'_>, cb: F) -> DomBuilderResult<()>
+where
+    F: FnOnce(&str),
+{
+    // No version of XML allows these, even as a reference
+    let check_char = |c: char, span: Span<&str>| {
+        if c.is_char() || c.is_restricted_char() {
+            Ok(c)
+        } else {
+            Err(span.map(|_| ErrorKind::InvalidCharacter))
+        }
+    };
+
+    match ref_data {
+        DecimalChar(span) => u32::from_str_radix(span.value, 10)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| span.map(|_| ErrorKind::InvalidDecimalReference))
+            .and_then(|c| check_char(c, span))
+            .and_then(|c| {
+                let s: String = iter::repeat(c).take(1).collect();
+                cb(&s);
+                Ok(())
+            }),
+        HexChar(span) => u32::from_str_radix(span.value, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| span.map(|_| ErrorKind::InvalidHexReference))
+            .and_then(|c| check_char(c, span))
+            .and_then(|c| {
+                let s: String = iter::repeat(c).take(1).collect();
+                cb(&s);
+                Ok(())
+            }),
+        Entity(span) => {
+            let s = predefined_entity(span.value)
+                .ok_or_else(|| span.map(|_| ErrorKind::UnknownNamedReference))?;
+            cb(s);
+            Ok(())
+        }
+    }
+}
+
+/// Replaces each line end, `\r\n` or `\r`, with `\n`. XML 1.1 also
+/// ends lines with `\r\u{85}`, `\u{85}` and `\u{2028}`.
+fn normalize_line_ends(text: &str, xml_1_1: bool) -> Cow<'_, str> {
+    let is_line_end = |c| c == '\r' || (xml_1_1 && (c == '\u{85}' || c == '\u{2028}'));
+    if !text.contains(is_line_end) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut normalized = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\r' {
+            let next = chars.peek().cloned();
+            if next == Some('\n') || (xml_1_1 && next == Some('\u{85}')) {
+                chars.next();
+            }
+        }
+
+        if is_line_end(c) {
+            normalized.push('\n');
+        } else {
+            normalized.push(c);
+        }
+    }
+
+    Cow::Owned(normalized)
+}
+
+/// Replaces each whitespace character in an attribute value with a
+/// space
+fn normalize_attribute_spaces(text: &str) -> Cow<'_, str> {
+    let spaces = &['\t', '\n', '\r'][..];
+    if text.contains(spaces) {
+        Cow::Owned(text.replace(spaces, " "))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// Normalizes the line ends of a literal attribute value, then
+/// replaces each whitespace character with a space
+fn normalize_attribute_literal(text: &str, xml_1_1: bool) -> Cow<'_, str> {
+    match normalize_line_ends(text, xml_1_1) {
+        Cow::Borrowed(text) => normalize_attribute_spaces(text),
+        Cow::Owned(text) => Cow::Owned(normalize_attribute_spaces(&text).into_owned()),
+    }
+}
+
+/// Whether the attribute is declared with a type other than `CDATA`,
+/// whose value has its spaces collapsed
+fn is_tokenized(dtd: &Dtd, element: PrefixedName<'_>, attribute: PrefixedName<'_>) -> bool {
+    let mut element_name = String::new();
+    push_qualified_name(&mut element_name, element);
+    let mut attribute_name = String::new();
+    push_qualified_name(&mut attribute_name, attribute);
+
+    dtd.attributes(&element_name)
+        .iter()
+        .find(|a| a.name() == attribute_name)
+        .filter(|a| *a.attribute_type() != dtd::AttributeType::CData)
+        .is_some()
+}
+
+fn predefined_entity(name: &str) -> Option<&'static str> {
+    let s = match name {
+        "amp" => "&",
+        "lt" => "<",
+        "gt" => ">",
+        "apos" => "'",
+        "quot" => "\"",
+        _ => return None,
+    };
+    Some(s)
+}
+
+/// Finds the replacement text of an entity that is not predefined.
+/// `expanding` holds the entities whose replacement text is being
+/// used, which the entity may not refer back to.
+fn replacement_text<'a>(
+    dtd: &'a Dtd,
+    name: Span<&str>,
+    expanding: &[String],
+    in_attribute: bool,
+) -> DomBuilderResult<&'a str> {
+    if expanding.iter().any(|e| e == name.value) {
+        return Err(name.map(|_| ErrorKind::RecursiveEntityReference));
+    }
+
+    let kind = match dtd.entity(name.value) {
+        Some(dtd::Entity::Internal(text)) => return Ok(text),
+        Some(dtd::Entity::External {
+            notation: Some(_), ..
+        }) => ErrorKind::UnparsedEntityReference,
+        Some(_) if in_attribute => ErrorKind::ExternalEntityInAttributeValue,
+        Some(_) => ErrorKind::UnresolvedExternalEntity,
+        None => ErrorKind::UnknownNamedReference,
+    };
+
+    Err(name.map(|_| kind))
+}
+
+/// Adds the text that a reference in content stands for, expanding
+/// declared entities as long as they contain only text. Anything wrong
+/// with the replacement text is reported at the reference.
+fn expand_text_reference(
+    reference: Reference<'_>,
+    dtd: &Dtd,
+    expanding: &mut Vec<String>,
+    expansion: &Expansion,
+    out: &mut String,
+) -> DomBuilderResult<()> {
+    let name = match reference {
+        Entity(name) if predefined_entity(name.value).is_none() => name,
+        r => return decode_reference(r, |s| out.push_str(s)),
+    };
+
+    let mut text = replacement_text(dtd, name, expanding, false)?;
+    expansion.add(name, expanding.len(), text.len())?;
+    let mut pm = XmlMaster::new();
+
+    expanding.push(name.value.to_owned());
+    while let Some(i) = text.find(&['&', '<'][..]) {
+        out.push_str(&text[..i]);
+        text = &text[i..];
+
+        if text.starts_with('<') {
+            return Err(name.map(|_| ErrorKind::EntityWithMarkup));
+        }
+
+        let progress = parse_reference(&mut pm, StringPoint::new(text));
+        match pm.finish(progress) {
+            peresil::Progress {
+                status: peresil::Status::Success(r),
+                point,
+            } => {
+                expand_text_reference(r.at(name.offset), dtd, expanding, expansion, out)?;
+                text = point.s;
+            }
+            peresil::Progress {
+                status: peresil::Status::Failure(kinds),
+                ..
+            } => {
+                let kind = kinds.into_iter().min().expect("A failure without a kind");
+                return Err(name.map(|_| kind));
+            }
+        }
+    }
+    out.push_str(text);
+    expanding.pop();
+
+    Ok(())
+}
+
+/// Builds an entity from its declaration. Character references in the
+/// value are replaced now, while entity references are kept until the
+/// entity is used.
+fn build_entity(definition: EntityDefinition<'_>) -> DomBuilderResult<dtd::Entity> {
+    let (value, public_id, system_id, notation) = match definition {
+        EntityDefinition::Value(value) => (value, None, None, None),
+        EntityDefinition::External(public_id, system_id, notation) => {
+            (Vec::new(), public_id, Some(system_id), notation)
+        }
+    };
+
+    if let Some(system_id) = system_id {
+        return Ok(dtd::Entity::External {
+            public_id: public_id.map(Into::into),
+            system_id: system_id.into(),
+            notation: notation.map(Into::into),
+        });
+    }
+
+    let mut text = String::new();
+    for part in value {
+        match part {
+            EntityValue::Literal(s) => text.push_str(&normalize_line_ends(s, false)),
+            EntityValue::Reference(Entity(name)) => {
+                text.push('&');
+                text.push_str(name.value);
+                text.push(';');
+            }
+            EntityValue::Reference(r) => decode_reference(r, |s| text.push_str(s))?,
+            EntityValue::ParameterEntityReference(name) => {
+                return Err(name.map(|_| ErrorKind::ParameterEntityInInternalSubset));
+            }
+        }
+    }
+
+    Ok(dtd::Entity::Internal(text))
+}
+
+/// Reads the declarations of an internal subset into `dtd`.
+/// `expanding` holds the parameter entities whose replacement text is
+/// being read.
+fn read_declarations(
+    dtd: &mut Dtd,
+    xml: StringPoint<'_>,
+    expanding: &mut Vec<String>,
+    resolver: &dyn Resolver,
+    expansion: &Expansion,
+) -> Result<(), Error> {
+    let mut pm = XmlMaster::new();
+    let mut xml = xml;
+
+    while !xml.is_empty() {
+        let progress = parse_declaration(&mut pm, xml);
+        let declaration = match pm.finish(progress) {
+            peresil::Progress {
+                status: peresil::Status::Success(declaration),
+                point,
+            } => {
+                xml = point;
+                declaration
+            }
+            peresil::Progress {
+                status: peresil::Status::Failure(kinds),
+                point,
+            } => return Err((point.offset, kinds).into()),
+        };
+
+        match declaration {
+            Declaration::Entity(name, definition) => {
+                dtd.add_entity(name.value, build_entity(definition)?);
+            }
+            Declaration::ParameterEntity(name, definition) => {
+                dtd.add_parameter_entity(name.value, build_entity(definition)?);
+            }
+            Declaration::AttributeList(element, definitions) => {
+                for definition in definitions {
+                    let default_value = match definition.default_value {
+                        DefaultDeclaration::Required => dtd::DefaultValue::Required,
+                        DefaultDeclaration::Implied => dtd::DefaultValue::Implied,
+                        DefaultDeclaration::Fixed(v) => {
+                            let v = AttributeValueBuilder::convert_normalized(&v, dtd, expansion)?;
+                            dtd::DefaultValue::Fixed(v)
+                        }
+                        DefaultDeclaration::Default(v) => {
+                            let v = AttributeValueBuilder::convert_normalized(&v, dtd, expansion)?;
+                            dtd::DefaultValue::Default(v)
+                        }
+                    };
+                    let attribute = dtd::AttributeDeclaration::new(
+                        definition.name.value,
+                        definition.attribute_type,
+                        default_value,
+                    );
+                    dtd.add_attribute(element.value, attribute);
+                }
+            }
+            Declaration::Element(name, content) => {
+                dtd.add_element(name.value, content);
+            }
+            Declaration::ParameterEntityReference(name) => {
+                if expanding.iter().any(|e| e == name.value) {
+                    return Err(Error::new(name.offset, ErrorKind::RecursiveEntityReference));
+                }
+
+                let text = match dtd.parameter_entity(name.value) {
+                    Some(dtd::Entity::Internal(text)) => text.clone(),
+                    Some(dtd::Entity::External {
+                        public_id,
+                        system_id,
+                        ..
+                    }) => {
+                        let public_id = public_id.as_ref().map(|p| &p[..]);
+                        load_external(resolver, public_id, system_id, expansion).map_err(
+                            |kind| {
+                                let kind = kind.unwrap_or(ErrorKind::UnresolvedExternalEntity);
+                                Error::new(name.offset, kind)
+                            },
+                        )?
+                    }
+                    None => return Err(Error::new(name.offset, ErrorKind::UnknownNamedReference)),
+                };
+
+                expansion.add(name, expanding.len(), text.len())?;
+
+                // The replacement text must contain whole declarations
+                expanding.push(name.value.to_owned());
+                read_declarations(dtd, StringPoint::new(&text), expanding, resolver, expansion)
+                    .map_err(|e| Error::with_errors(name.offset, e.errors))?;
+                expanding.pop();
+            }
+            Declaration::Ignored => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads an external entity or DTD subset and removes its text
+/// declaration. Fails with `None` if the resolver refused to load it.
+fn load_external(
+    resolver: &dyn Resolver,
+    public_id: Option<&str>,
+    system_id: &str,
+    expansion: &Expansion,
+) -> Result<String, Option<ErrorKind>> {
+    let bytes = resolver
+        .resolve(public_id, system_id)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::PermissionDenied => None,
+            _ => Some(ErrorKind::UnresolvedExternalEntity),
+        })?;
+    expansion.load(bytes.len());
+    let mut text = decode(&bytes).map_err(|e| Some(e.kind()))?;
+
+    if let Some((len, _)) = text_declaration(&text) {
+        text.drain(..len);
+    }
+
+    Ok(normalize_line_ends(&text, false).into_owned())
+}
+
+/// Parses the declarations of an internal subset on its own
+pub(crate) fn parse_internal_subset(internal_subset: &str) -> Result<Dtd, Error> {
+    let mut dtd = Dtd::new();
+    read_declarations(
+        &mut dtd,
+        StringPoint::new(internal_subset),
+        &mut Vec::new(),
+        &DenyAll,
+        &Expansion::new(&ParseLimits::default()),
+    )
+    .map_err(|e| e.located_in(internal_subset))?;
+    Ok(dtd)
+}
+
+#[derive(Debug, Copy, Clone)]
+enum AttributeValue<'a> {
+    ReferenceAttributeValue(Reference<'a>),
+    LiteralAttributeValue(&'a str),
+}
+
+struct AttributeValueBuilder<'a> {
+    value: String,
+    dtd: &'a Dtd,
+    expansion: &'a Expansion,
+}
+
+impl<'a> AttributeValueBuilder<'a> {
+    fn convert(
+        values: &[AttributeValue<'_>],
+        dtd: &Dtd,
+        expansion: &Expansion,
+    ) -> DomBuilderResult<String> {
+        let mut builder = AttributeValueBuilder::new(dtd, expansion);
+        builder.ingest(values)?;
+        Ok(builder.implode())
+    }
+
+    /// As `convert`, but for a value that has not had its whitespace
+    /// normalized yet
+    fn convert_normalized(
+        values: &[AttributeValue<'_>],
+        dtd: &Dtd,
+        expansion: &Expansion,
+    ) -> DomBuilderResult<String> {
+        let mut builder = AttributeValueBuilder::new(dtd, expansion);
+        builder.ingest_normalized(values, false)?;
+        Ok(builder.implode())
+    }
+
+    fn new(dtd: &'a Dtd, expansion: &'a Expansion) -> AttributeValueBuilder<'a> {
+        AttributeValueBuilder {
+            value: String::new(),
+            dtd,
+            expansion,
+        }
+    }
+
+    fn ingest(&mut self, values: &[AttributeValue<'_>]) -> DomBuilderResult<()> {
+        use self::AttributeValue::*;
+
+        for value in values.iter() {
+            match *value {
+                LiteralAttributeValue(v) => self.value.push_str(v),
+                ReferenceAttributeValue(r) => self.ingest_reference(r, &mut Vec::new())?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn ingest_reference(
+        &mut self,
+        reference: Reference<'_>,
+        expanding: &mut Vec<String>,
+    ) -> DomBuilderResult<()> {
+        match reference {
+            Entity(name) if predefined_entity(name.value).is_none() => {
+                self.ingest_entity(name, expanding)
+            }
+            r => decode_reference(r, |s| self.value.push_str(s)),
+        }
+    }
+
+    /// Adds the replacement text of a declared entity, expanding the
+    /// references it contains. Anything wrong with the replacement text
+    /// is reported at the reference.
+    fn ingest_entity(
+        &mut self,
+        name: Span<&str>,
+        expanding: &mut Vec<String>,
+    ) -> DomBuilderResult<()> {
+        let dtd = self.dtd;
+        let mut text = replacement_text(dtd, name, expanding, true)?;
+        self.expansion.add(name, expanding.len(), text.len())?;
+        let mut pm = XmlMaster::new();
+
+        expanding.push(name.value.to_owned());
+        while let Some(i) = text.find(&['&', '<'][..]) {
+            self.value.push_str(&normalize_attribute_spaces(&text[..i]));
+            text = &text[i..];
+
+            if text.starts_with('<') {
+                return Err(name.map(|_| ErrorKind::LessThanInAttributeValue));
+            }
+
+            let progress = parse_reference(&mut pm, StringPoint::new(text));
+            match pm.finish(progress) {
+                peresil::Progress {
+                    status: peresil::Status::Success(r),
+                    point,
+                } => {
+                    self.ingest_reference(r.at(name.offset), expanding)?;
+                    text = point.s;
+                }
+                peresil::Progress {
+                    status: peresil::Status::Failure(kinds),
+                    ..
+                } => {
+                    let kind = kinds.into_iter().min().expect("A failure without a kind");
+                    return Err(name.map(|_| kind));
+                }
+            }
+        }
+        self.value.push_str(&normalize_attribute_spaces(text));
+        expanding.pop();
+
+        Ok(())
+    }
+
+    /// Normalizes the whitespace of the literal parts of the value as
+    /// they are added
+    fn ingest_normalized(
+        &mut self,
+        values: &[AttributeValue<'_>],
+        xml_1_1: bool,
+    ) -> DomBuilderResult<()> {
+        for value in values {
+            match *value {
+                AttributeValue::LiteralAttributeValue(v) => self
+                    .value
+                    .push_str(&normalize_attribute_literal(v, xml_1_1)),
+                AttributeValue::ReferenceAttributeValue(r) => {
+                    self.ingest_reference(r, &mut Vec::new())?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.value.clear();
+    }
+
+    /// Removes the spaces at the start and end of the value, and
+    /// replaces each run of spaces inside of it with one
+    fn collapse_spaces(&mut self) {
+        let tokens: Vec<_> = self.value.split(' ').filter(|t| !t.is_empty()).collect();
+        self.value = tokens.join(" ");
+    }
+
+    fn implode(self) -> String {
+        self.value
+    }
+}
+
+impl<'a> Deref for AttributeValueBuilder<'a> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.value
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DeferredAttribute<'d> {
+    name: Span<PrefixedName<'d>>,
+    values: Vec<AttributeValue<'d>>,
+    specified: bool,
+    source_span: Option<SourceSpan>,
+}
+
+struct DeferredAttributes<'a> {
+    attributes: Vec<DeferredAttribute<'a>>,
+    namespaces: Vec<DeferredAttribute<'a>>,
+    default_namespaces: Vec<DeferredAttribute<'a>>,
+}
+
+impl<'a> DeferredAttributes<'a> {
+    fn new(attributes: Vec<DeferredAttribute<'a>>) -> DeferredAttributes<'a> {
+        let (mut namespaces, attributes): (Vec<_>, Vec<_>) = attributes
+            .into_iter()
+            .partition(|attr| attr.name.value.prefix == Some("xmlns"));
+
+        let (mut default_namespaces, mut attributes): (Vec<_>, Vec<_>) = attributes
+            .into_iter()
+            .partition(|attr| attr.name.value.local_part == "xmlns");
+
+        fn sort_by_name(
+            a: &DeferredAttribute<'_>,
+            b: &DeferredAttribute<'_>,
+        ) -> ::std::cmp::Ordering {
+            a.name.value.cmp(&b.name.value)
+        }
+
+        attributes.sort_by(sort_by_name);
+        namespaces.sort_by(sort_by_name);
+        default_namespaces.sort_by(sort_by_name);
+
+        DeferredAttributes {
+            attributes,
+            namespaces,
+            default_namespaces,
+        }
+    }
+
+    fn check_duplicates(&self) -> DomBuilderResult<()> {
+        for w in self.attributes.windows(2) {
+            if w[0].name.value == w[1].name.value {
+                return Err(w[1].name.map(|_| ErrorKind::DuplicateAttribute));
+            }
+        }
+
+        for w in self.namespaces.windows(2) {
+            if w[0].name.value == w[1].name.value {
+                return Err(w[1].name.map(|_| ErrorKind::RedefinedNamespace));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn attributes(&self) -> &[DeferredAttribute<'a>] {
+        &self.attributes
+    }
+
+    fn namespaces(&self) -> &[DeferredAttribute<'a>] {
+        &self.namespaces
+    }
+
+    fn default_namespace(
+        &self,
+        dtd: &Dtd,
+        expansion: &Expansion,
+    ) -> DomBuilderResult<Option<String>> {
+        match self.default_namespaces.len() {
+            0 => Ok(None),
+            1 => {
+                let ns = &self.default_namespaces[0];
+                let value = AttributeValueBuilder::convert(&ns.values, dtd, expansion)?;
+                check_prefix_binding(None, &value).map_err(|kind| ns.name.map(|_| kind))?;
+                Ok(Some(value))
+            }
+            _ => {
+                let last_namespace = self.default_namespaces.last().unwrap();
+                Err(last_namespace
+                    .name
+                    .map(|_| ErrorKind::RedefinedDefaultNamespace))
+            }
+        }
+    }
+}
+
+/// Checks a namespace declaration against the prefixes and URIs that
+/// Namespaces in XML reserves. A `None` prefix is the default
+/// namespace.
+pub(crate) fn check_prefix_binding(prefix: Option<&str>, ns_uri: &str) -> Result<(), ErrorKind> {
+    let is_xml_prefix = prefix == Some(crate::XML_NS_PREFIX);
+
+    if prefix == Some(crate::XMLNS_NS_PREFIX) {
+        Err(ErrorKind::DeclaredXmlnsPrefix)
+    } else if is_xml_prefix && ns_uri != crate::XML_NS_URI {
+        Err(ErrorKind::RedefinedXmlPrefix)
+    } else if (!is_xml_prefix && ns_uri == crate::XML_NS_URI) || ns_uri == crate::XMLNS_NS_URI {
+        Err(ErrorKind::ReservedNamespace)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{dom, Package, QName};
+
+    macro_rules! assert_qname_eq(
+        ($l:expr, $r:expr) => (assert_eq!(Into::<QName<'_>>::into($l), $r.into()));
+    );
+
+    fn full_parse(xml: &str) -> Result<Package, Error> {
+        super::parse(xml)
+    }
+
+    fn quick_parse(xml: &str) -> Package {
+        full_parse(xml).expect("Failed to parse the XML string")
+    }
+
+    fn top<'d>(doc: &'d dom::Document<'d>) -> dom::Element<'d> {
+        doc.root()
+            .children()
+            .into_iter()
+            .find_map(|c| c.element())
+            .unwrap()
+    }
+
+    #[test]
+    fn a_document_with_a_prolog() {
+        let package = quick_parse("<?xml version='1.0' ?><hello />");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "hello");
+    }
+
+    #[test]
+    fn a_document_with_a_prolog_with_double_quotes() {
+        let package = quick_parse("<?xml version=\"1.0\" ?><hello />");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "hello");
+    }
+
+    #[test]
+    fn a_prolog_with_an_encoding() {
+        let package = quick_parse("<?xml version='1.0' encoding='UTF-8' ?><hello />");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "hello");
+    }
+
+    #[test]
+    fn a_non_standalone_prolog() {
+        let package = quick_parse("<?xml version='1.0' standalone='no'?><hello/>");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "hello");
+    }
+
+    #[test]
+    fn a_complete_prolog() {
+        let package =
+            quick_parse("<?xml version='1.0' encoding='UTF-8' standalone='yes'?><hello/>");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "hello");
+    }
+
+    #[test]
+    fn a_prolog_is_kept_on_the_document() {
+        let package =
+            quick_parse("<?xml version='1.0' encoding='ISO-8859-1' standalone='no'?><hello/>");
+        let doc = package.as_document();
+
+        assert_eq!(doc.xml_version(), "1.0");
+        assert_eq!(doc.xml_encoding(), Some("ISO-8859-1"));
+        assert_eq!(doc.xml_standalone(), Some(false));
+    }
+
+    #[test]
+    fn a_missing_prolog_leaves_the_defaults() {
+        let package = quick_parse("<hello/>");
+        let doc = package.as_document();
+
+        assert_eq!(doc.xml_version(), "1.0");
+        assert_eq!(doc.xml_encoding(), None);
+        assert_eq!(doc.xml_standalone(), None);
+    }
+
+    #[test]
+    fn a_prolog_with_a_doc_type_declaration_external_id() {
+        let package = quick_parse(
+            r#"<?xml version='1.0'?>
+        <!DOCTYPE doc SYSTEM "http://example.com/doc.dtd">
+        <hello/>"#,
+        );
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "hello");
+    }
+
+    #[test]
+    fn a_prolog_with_a_doc_type_declaration_int_subset() {
+        let package = quick_parse(
+            r#"<?xml version="1.0"?>
+            <!DOCTYPE note [
+            <!ELEMENT note (to,from,heading,body)>
+            <!ELEMENT to (#PCDATA)>
+            <!ELEMENT from (#PCDATA)>
+            <!ELEMENT heading (#PCDATA)>
+            <!ELEMENT body (#PCDATA)>
+            ]>
+            <note>
+            <to>Tove</to>
+            <from>Jani</from>
+            <heading>Reminder</heading>
+            <body>Don't forget me this weekend</body>
+            </note>
+        "#,
+        );
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "note");
+    }
+
+    #[test]
+    fn a_prolog_with_a_doc_type_declaration_int_subset_trailing_ws() {
+        let package = quick_parse(
+            r#"<?xml version="1.0"?>
+            <!DOCTYPE note
+                [
+                    <!ELEMENT note (to,from,heading,body)>
+                    <!ELEMENT to (#PCDATA)>
+                    <!ELEMENT from (#PCDATA)>
+                    <!ELEMENT heading (#PCDATA)>
+                    <!ELEMENT body (#PCDATA)>
+                ]
+
+            >
+            <note>
+            <to>Tove</to>
+            <from>Jani</from>
+            <heading>Reminder</heading>
+            <body>Don't forget me this weekend</body>
+            </note>
+        "#,
+        );
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "note");
+    }
+
+    #[test]
+    fn a_prolog_with_a_doc_type_declaration_zero_def() {
+        let package = quick_parse(
+            "<?xml version='1.0'?>
+        <!DOCTYPE doc>
+        <hello/>",
+        );
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "hello");
+    }
+
+    #[test]
+    fn a_prolog_with_a_doc_type_declaration_zero_def_trailing_ws() {
+        let package = quick_parse(
+            "<?xml version='1.0'?>
+        <!DOCTYPE doc  >
+        <hello/>",
+        );
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "hello");
+    }
+
+    #[test]
+    fn a_prolog_with_a_doc_type_declaration_both_int_subset_and_external_id() {
+        let package = quick_parse(
+            r#"<?xml version='1.0'?>
+        <!DOCTYPE doc SYSTEM "http://example.com/doc.dtd" [
+        <!ELEMENT hello (#PCDATA)>
+        ]>
+        <hello/>"#,
+        );
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "hello");
+    }
+
+    #[test]
+    fn a_prolog_with_a_doc_type_declaration_both_int_subset_and_external_id_trailing_ws() {
+        let package = quick_parse(
+            r#"<?xml version='1.0'?>
+        <!DOCTYPE doc SYSTEM "http://example.com/doc.dtd" [
+        <!ELEMENT hello (#PCDATA)>
+         ]  >
+        <hello/>"#,
+        );
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "hello");
+    }
+
+    fn document_type<'d>(doc: &'d dom::Document<'d>) -> dom::DocumentType<'d> {
+        doc.root()
+            .children()
+            .into_iter()
+            .find_map(|c| c.document_type())
+            .expect("No document type")
+    }
+
+    #[test]
+    fn a_doc_type_declaration_is_kept_before_the_top_element() {
+        let package =
+            quick_parse("<!DOCTYPE hello SYSTEM 'hello.dtd' [ <!ELEMENT hello EMPTY> ]><hello/>");
+        let doc = package.as_document();
+        let children = doc.root().children();
+
+        let doctype = children[0].document_type().unwrap();
+        assert_eq!(doctype.name(), "hello");
+        assert_eq!(doctype.public_id(), None);
+        assert_eq!(doctype.system_id(), Some("hello.dtd"));
+        assert_eq!(doctype.internal_subset(), Some("<!ELEMENT hello EMPTY>"));
+        assert_qname_eq!(children[1].element().unwrap().name(), "hello");
+    }
+
+    #[test]
+    fn a_doc_type_declaration_with_a_public_id() {
+        let package = quick_parse(
+            r#"<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Strict//EN"
+  "http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd"><html/>"#,
+        );
+        let doc = package.as_document();
+        let doctype = document_type(&doc);
+
+        assert_eq!(doctype.name(), "html");
+        assert_eq!(
+            doctype.public_id(),
+            Some("-//W3C//DTD XHTML 1.0 Strict//EN")
+        );
+        assert_eq!(
+            doctype.system_id(),
+            Some("http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd")
+        );
+        assert_eq!(doctype.internal_subset(), None);
+    }
+
+    #[test]
+    fn a_doc_type_declaration_with_only_a_name() {
+        let package = quick_parse("<!DOCTYPE hello><hello/>");
+        let doc = package.as_document();
+        let doctype = document_type(&doc);
+
+        assert_eq!(doctype.name(), "hello");
+        assert_eq!(doctype.system_id(), None);
+    }
+
+    #[test]
+    fn a_system_literal_may_contain_markup_characters() {
+        let package = quick_parse("<!DOCTYPE a SYSTEM 'a.dtd?x=<1>&y=2'><a/>");
+        let doc = package.as_document();
+
+        assert_eq!(document_type(&doc).system_id(), Some("a.dtd?x=<1>&y=2"));
+    }
+
+    #[test]
+    fn a_doc_type_declaration_survives_writing() {
+        let xml = "<?xml version='1.0' standalone='no'?><!DOCTYPE html PUBLIC '-//W3C//DTD XHTML 1.0 Strict//EN' 'xhtml1-strict.dtd'><html/>";
+
+        assert_eq!(serialize(&quick_parse(xml)), xml);
+    }
+
+    #[test]
+    fn only_one_doc_type_declaration_is_allowed() {
+        assert!(full_parse("<!DOCTYPE a><!DOCTYPE a><a/>").is_err());
+    }
+
+    #[test]
+    fn a_public_id_requires_a_system_literal() {
+        assert!(full_parse("<!DOCTYPE a PUBLIC 'pub'><a/>").is_err());
+    }
+
+    #[test]
+    fn a_public_id_cannot_contain_markup_characters() {
+        let err = full_parse("<!DOCTYPE a PUBLIC 'p<b' 'a.dtd'><a/>").unwrap_err();
+
+        assert_eq!(err.location(), 21);
+    }
+
+    #[test]
+    fn a_document_with_a_single_element() {
+        let package = quick_parse("<hello />");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "hello");
+    }
+
+    #[test]
+    fn an_element_with_a_namespace() {
+        let package = quick_parse("<ns:hello xmlns:ns='namespace'/>");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_eq!(top.preferred_prefix(), Some("ns"));
+        assert_qname_eq!(("namespace", "hello"), top.name());
+    }
+
+    #[test]
+    fn an_element_with_a_default_namespace() {
+        let package = quick_parse("<hello xmlns='namespace'/>");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_eq!(top.default_namespace_uri(), Some("namespace"));
+        assert_qname_eq!(("namespace", "hello"), top.name());
+    }
+
+    #[test]
+    fn an_element_with_an_attribute() {
+        let package = quick_parse("<hello scope='world'/>");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_eq!(top.attribute_value("scope"), Some("world"));
+    }
+
+    #[test]
+    fn an_element_with_an_attribute_using_double_quotes() {
+        let package = quick_parse("<hello scope=\"world\"/>");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_eq!(top.attribute_value("scope"), Some("world"));
+    }
+
+    #[test]
+    fn an_element_with_multiple_attributes() {
+        let package = quick_parse("<hello scope='world' happy='true'/>");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_eq!(top.attribute_value("scope"), Some("world"));
+        assert_eq!(top.attribute_value("happy"), Some("true"));
+    }
+
+    #[test]
+    fn an_attribute_with_a_namespace() {
+        let package = quick_parse("<hello ns:a='b' xmlns:ns='namespace'/>");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        let attr = top.attribute(("namespace", "a")).unwrap();
+
+        assert_eq!(attr.preferred_prefix(), Some("ns"));
+        assert_eq!(attr.value(), "b");
+    }
+
+    #[test]
+    fn an_attribute_with_xml_space_preserve() {
+        let package = quick_parse("<hello xml:space='preserve'> <a/> </hello>");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_eq!(
+            top.attribute((crate::XML_NS_URI, "space")).unwrap().value(),
+            "preserve"
+        );
+
+        let children = top.children();
+        assert_eq!(children.len(), 3);
+        assert_eq!(children[0].text().unwrap().text(), " ");
+        assert_qname_eq!(children[1].element().unwrap().name(), "a");
+        assert_eq!(children[2].text().unwrap().text(), " ");
+    }
+
+    #[test]
+    fn an_attribute_with_references() {
+        let package = quick_parse("<log msg='I &lt;3 math' />");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_eq!(top.attribute_value("msg"), Some("I <3 math"));
+    }
+
+    #[test]
+    fn an_element_that_is_not_self_closing() {
+        let package = quick_parse("<hello></hello>");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "hello");
+    }
+
+    #[test]
+    fn nested_elements() {
+        let package = quick_parse("<hello><world/></hello>");
+        let doc = package.as_document();
+        let hello = top(&doc);
+        let world = hello.children()[0].element().unwrap();
+
+        assert_qname_eq!(world.name(), "world");
+    }
+
+    #[test]
+    fn multiply_nested_elements() {
+        let package = quick_parse("<hello><awesome><world/></awesome></hello>");
+        let doc = package.as_document();
+        let hello = top(&doc);
+        let awesome = hello.children()[0].element().unwrap();
+        let world = awesome.children()[0].element().unwrap();
+
+        assert_qname_eq!(world.name(), "world");
+    }
+
+    #[test]
+    fn nested_elements_with_namespaces() {
+        let package =
+            quick_parse("<ns1:hello xmlns:ns1='outer'><ns2:world xmlns:ns2='inner'/></ns1:hello>");
+        let doc = package.as_document();
+        let hello = top(&doc);
+        let world = hello.children()[0].element().unwrap();
+
+        assert_qname_eq!(world.name(), ("inner", "world"));
+    }
+
+    #[test]
+    fn nested_elements_with_inherited_namespaces() {
+        let package = quick_parse("<ns1:hello xmlns:ns1='outer'><ns1:world/></ns1:hello>");
+        let doc = package.as_document();
+        let hello = top(&doc);
+        let world = hello.children()[0].element().unwrap();
+
+        assert_eq!(world.preferred_prefix(), Some("ns1"));
+        assert_qname_eq!(world.name(), ("outer", "world"));
+    }
+
+    #[test]
+    fn nested_elements_with_inherited_default_namespace() {
+        let package = quick_parse("<hello xmlns='outer'><world/></hello>");
+        let doc = package.as_document();
+        let hello = top(&doc);
+        let world = hello.children()[0].element().unwrap();
+
+        assert_qname_eq!(world.name(), ("outer", "world"));
+    }
+
+    #[test]
+    fn nested_elements_with_reset_default_namespace() {
+        let package = quick_parse("<hello xmlns='outer'><world xmlns=''/></hello>");
+        let doc = package.as_document();
+        let hello = top(&doc);
+        let world = hello.children()[0].element().unwrap();
+
+        assert_qname_eq!(world.name(), "world");
+    }
+
+    #[test]
+    fn nested_elements_with_attributes() {
+        let package = quick_parse("<hello><world name='Earth'/></hello>");
+        let doc = package.as_document();
+        let hello = top(&doc);
+        let world = hello.children()[0].element().unwrap();
+
+        assert_eq!(world.attribute_value("name"), Some("Earth"));
+    }
+
+    #[test]
+    fn nested_elements_with_attributes_with_inherited_namespaces() {
+        let package = quick_parse("<hello xmlns:ns1='outer'><world ns1:name='Earth'/></hello>");
+        let doc = package.as_document();
+        let hello = top(&doc);
+        let world = hello.children()[0].element().unwrap();
+
+        let attr = world.attribute(("outer", "name")).unwrap();
+
+        assert_eq!(attr.preferred_prefix(), Some("ns1"));
+        assert_eq!(attr.value(), "Earth");
+    }
+
+    #[test]
+    fn element_with_text() {
+        let package = quick_parse("<hello>world</hello>");
+        let doc = package.as_document();
+        let hello = top(&doc);
+        let text = hello.children()[0].text().unwrap();
+
+        assert_eq!(text.text(), "world");
+    }
+
+    #[test]
+    fn element_with_cdata() {
+        let package = quick_parse("<words><![CDATA[I have & and < !]]></words>");
+        let doc = package.as_document();
+        let words = top(&doc);
+        let text = words.children()[0].text().unwrap();
+
+        assert_eq!(text.text(), "I have & and < !");
+    }
+
+    #[test]
+    fn element_with_comment() {
+        let package = quick_parse("<hello><!-- A comment --></hello>");
+        let doc = package.as_document();
+        let words = top(&doc);
+        let comment = words.children()[0].comment().unwrap();
+
+        assert_eq!(comment.text(), " A comment ");
+    }
+
+    #[test]
+    fn comment_before_top_element() {
+        let package = quick_parse("<!-- A comment --><hello />");
+        let doc = package.as_document();
+        let comment = doc.root().children()[0].comment().unwrap();
+
+        assert_eq!(comment.text(), " A comment ");
+    }
+
+    #[test]
+    fn multiple_comments_before_top_element() {
+        let xml = r"
+<!--Comment 1-->
+<!--Comment 2-->
+<hello />";
+        let package = quick_parse(xml);
+        let doc = package.as_document();
+        let comment1 = doc.root().children()[0].comment().unwrap();
+        let comment2 = doc.root().children()[1].comment().unwrap();
+
+        assert_eq!(comment1.text(), "Comment 1");
+        assert_eq!(comment2.text(), "Comment 2");
+    }
+
+    #[test]
+    fn multiple_comments_after_top_element() {
+        let xml = r"
+<hello />
+<!--Comment 1-->
+<!--Comment 2-->";
+        let package = quick_parse(xml);
+        let doc = package.as_document();
+        let comment1 = doc.root().children()[1].comment().unwrap();
+        let comment2 = doc.root().children()[2].comment().unwrap();
+
+        assert_eq!(comment1.text(), "Comment 1");
+        assert_eq!(comment2.text(), "Comment 2");
+    }
+
+    #[test]
+    fn element_with_processing_instruction() {
+        let package = quick_parse("<hello><?device?></hello>");
+        let doc = package.as_document();
+        let hello = top(&doc);
+        let pi = hello.children()[0].processing_instruction().unwrap();
+
+        assert_eq!(pi.target(), "device");
+        assert_eq!(pi.value(), None);
+    }
+
+    #[test]
+    fn top_level_processing_instructions() {
+        let xml = r"
+<?output printer?>
+<hello />
+<?validated?>";
+
+        let package = quick_parse(xml);
+        let doc = package.as_document();
+        let pi1 = doc.root().children()[0].processing_instruction().unwrap();
+        let pi2 = doc.root().children()[2].processing_instruction().unwrap();
+
+        assert_eq!(pi1.target(), "output");
+        assert_eq!(pi1.value(), Some("printer"));
+
+        assert_eq!(pi2.target(), "validated");
+        assert_eq!(pi2.value(), None);
+    }
+
+    #[test]
+    fn element_with_decimal_char_reference() {
+        let package = quick_parse("<math>2 &#62; 1</math>");
+        let doc = package.as_document();
+        let math = top(&doc);
+        let text1 = math.children()[0].text().unwrap();
+        let text2 = math.children()[1].text().unwrap();
+        let text3 = math.children()[2].text().unwrap();
+
+        assert_eq!(text1.text(), "2 ");
+        assert_eq!(text2.text(), ">");
+        assert_eq!(text3.text(), " 1");
+    }
+
+    #[test]
+    fn element_with_hexidecimal_char_reference() {
+        let package = quick_parse("<math>1 &#x3c; 2</math>");
+        let doc = package.as_document();
+        let math = top(&doc);
+        let text1 = math.children()[0].text().unwrap();
+        let text2 = math.children()[1].text().unwrap();
+        let text3 = math.children()[2].text().unwrap();
+
+        assert_eq!(text1.text(), "1 ");
+        assert_eq!(text2.text(), "<");
+        assert_eq!(text3.text(), " 2");
+    }
+
+    #[test]
+    fn element_with_entity_reference() {
+        let package = quick_parse("<math>I &lt;3 math</math>");
+        let doc = package.as_document();
+        let math = top(&doc);
+        let text1 = math.children()[0].text().unwrap();
+        let text2 = math.children()[1].text().unwrap();
+        let text3 = math.children()[2].text().unwrap();
+
+        assert_eq!(text1.text(), "I ");
+        assert_eq!(text2.text(), "<");
+        assert_eq!(text3.text(), "3 math");
+    }
+
+    #[test]
+    fn element_with_mixed_children() {
+        let package = quick_parse("<hello>to <!--fixme--><a><![CDATA[the]]></a><?world?></hello>");
+        let doc = package.as_document();
+        let hello = top(&doc);
+
+        let text = hello.children()[0].text().unwrap();
+        let comment = hello.children()[1].comment().unwrap();
+        let element = hello.children()[2].element().unwrap();
+        let pi = hello.children()[3].processing_instruction().unwrap();
+
+        assert_eq!(text.text(), "to ");
+        assert_eq!(comment.text(), "fixme");
+        assert_qname_eq!(element.name(), "a");
+        assert_eq!(pi.target(), "world");
+    }
+
+    fn text_content(element: dom::Element<'_>) -> String {
+        element
+            .children()
+            .iter()
+            .filter_map(|c| c.text())
+            .map(|t| t.text())
+            .collect()
+    }
+
+    #[test]
+    fn element_with_declared_entity_reference() {
+        let package = quick_parse(r#"<!DOCTYPE a [<!ENTITY copy "&#169;">]><a>&copy; 2020</a>"#);
+        let doc = package.as_document();
+
+        assert_eq!(text_content(top(&doc)), "\u{A9} 2020");
+    }
+
+    #[test]
+    fn element_with_nested_entity_references() {
+        let package = quick_parse(
+            r#"<!DOCTYPE a [
+                 <!ENTITY inner "middle">
+                 <!ENTITY outer "start &inner; &amp; end">
+               ]><a>&outer;</a>"#,
+        );
+        let doc = package.as_document();
+
+        assert_eq!(text_content(top(&doc)), "start middle & end");
+    }
+
+    #[test]
+    fn element_with_entity_reference_containing_markup() {
+        let package = quick_parse(
+            r#"<!DOCTYPE a [<!ENTITY sig "<b x='1'>Bob</b><!--c-->">]><a>-- &sig;</a>"#,
+        );
+        let doc = package.as_document();
+        let a = top(&doc);
+
+        let b = a.children()[1].element().unwrap();
+        assert_qname_eq!(b.name(), "b");
+        assert_eq!(b.attribute_value("x"), Some("1"));
+        assert_eq!(text_content(b), "Bob");
+        assert_eq!(a.children()[2].comment().unwrap().text(), "c");
+    }
+
+    #[test]
+    fn element_with_escaped_markup_in_an_entity() {
+        let package = quick_parse(r#"<!DOCTYPE a [<!ENTITY lt2 "&#38;#60;">]><a>&lt2;</a>"#);
+        let doc = package.as_document();
+
+        assert_eq!(text_content(top(&doc)), "<");
+    }
+
+    #[test]
+    fn an_attribute_with_declared_entity_references() {
+        let package = quick_parse(
+            r#"<!DOCTYPE a [
+                 <!ENTITY inner "&#34;quoted&#34;">
+                 <!ENTITY outer "[&inner;]">
+               ]><a v='&outer; &lt;'/>"#,
+        );
+        let doc = package.as_document();
+
+        assert_eq!(top(&doc).attribute_value("v"), Some("[\"quoted\"] <"));
+    }
+
+    #[test]
+    fn predefined_entities_cannot_be_redeclared() {
+        let package = quick_parse(r#"<!DOCTYPE a [<!ENTITY lt "oops">]><a>&lt;</a>"#);
+        let doc = package.as_document();
+
+        assert_eq!(text_content(top(&doc)), "<");
+    }
+
+    fn parse_with_defaults(xml: &str) -> Package {
+        Parser::new()
+            .set_apply_attribute_defaults(true)
+            .parse(xml)
+            .expect("Failed to parse the XML string")
+    }
+
+    #[test]
+    fn attribute_defaults_are_not_applied_by_default() {
+        let package = quick_parse("<!DOCTYPE a [<!ATTLIST a b CDATA 'x'>]><a/>");
+        let doc = package.as_document();
+
+        assert_eq!(top(&doc).attribute("b"), None);
+    }
+
+    #[test]
+    fn attribute_defaults_are_applied() {
+        let package = parse_with_defaults(
+            "<!DOCTYPE a [\
+               <!ATTLIST a b CDATA 'x' c CDATA #FIXED 'y' d CDATA #IMPLIED>\
+             ]><a/>",
+        );
+        let doc = package.as_document();
+        let a = top(&doc);
+
+        assert_eq!(a.attribute_value("b"), Some("x"));
+        assert_eq!(a.attribute_value("c"), Some("y"));
+        assert_eq!(a.attribute("d"), None);
+        assert!(a.attributes().iter().all(|attr| !attr.is_specified()));
+    }
+
+    #[test]
+    fn attribute_defaults_have_their_whitespace_normalized() {
+        let package = parse_with_defaults(
+            "<!DOCTYPE a [\
+               <!ATTLIST a b CDATA 'x\ty\nz' c CDATA #FIXED '1\r\n&#9;2'>\
+             ]><a/>",
+        );
+        let doc = package.as_document();
+        let a = top(&doc);
+
+        assert_eq!(a.attribute_value("b"), Some("x y z"));
+        assert_eq!(a.attribute_value("c"), Some("1 \t2"));
+    }
+
+    #[test]
+    fn specified_attributes_are_not_replaced_by_defaults() {
+        let package =
+            parse_with_defaults("<!DOCTYPE a [<!ATTLIST a b CDATA 'default'>]><a b='given'/>");
+        let doc = package.as_document();
+        let b = top(&doc).attribute("b").unwrap();
+
+        assert_eq!(b.value(), "given");
+        assert!(b.is_specified());
+    }
+
+    #[test]
+    fn fixed_namespace_declarations_are_applied() {
+        let package = parse_with_defaults(
+            "<!DOCTYPE x:a [<!ATTLIST x:a xmlns:x CDATA #FIXED 'urn:x'>]><x:a/>",
+        );
+        let doc = package.as_document();
+
+        assert_qname_eq!(top(&doc).name(), ("urn:x", "a"));
+    }
+
+    #[test]
+    fn parsing_a_reader_applies_attribute_defaults() {
+        let xml = "<!DOCTYPE a [<!ATTLIST a b CDATA 'x'>]><a/>";
+        let package = Parser::new()
+            .set_apply_attribute_defaults(true)
+            .parse_reader(xml.as_bytes())
+            .expect("Failed to parse");
+        let doc = package.as_document();
+
+        assert_eq!(top(&doc).attribute_value("b"), Some("x"));
+    }
+
+    /// Resolves system identifiers to the text given for them
+    struct Files(Vec<(&'static str, &'static str)>);
+
+    impl Resolver for Files {
+        fn resolve(&self, _public_id: Option<&str>, system_id: &str) -> io::Result<Vec<u8>> {
+            self.0
+                .iter()
+                .find(|f| f.0 == system_id)
+                .map(|f| f.1.as_bytes().to_vec())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, system_id))
+        }
+    }
+
+    fn parse_with_files(
+        files: Vec<(&'static str, &'static str)>,
+        xml: &str,
+    ) -> Result<Package, Error> {
+        Parser::new().set_resolver(Files(files)).parse(xml)
+    }
+
+    #[test]
+    fn the_external_subset_is_skipped_by_default() {
+        let package = quick_parse("<!DOCTYPE a SYSTEM 'a.dtd'><a/>");
+        let doc = package.as_document();
+
+        assert_qname_eq!(top(&doc).name(), "a");
+    }
+
+    #[test]
+    fn declarations_in_the_external_subset_are_used() {
+        let package = parse_with_files(
+            vec![(
+                "a.dtd",
+                "<?xml encoding='UTF-8'?><!ENTITY e 'external'><!ENTITY i 'external'>",
+            )],
+            "<!DOCTYPE a SYSTEM 'a.dtd' [<!ENTITY i 'internal'>]><a>&e; &i;</a>",
+        )
+        .expect("Failed to parse");
+        let doc = package.as_document();
+
+        assert_eq!(text_content(top(&doc)), "external internal");
+    }
+
+    #[test]
+    fn external_entities_are_expanded() {
+        let package = parse_with_files(
+            vec![
+                (
+                    "chapter.xml",
+                    "<?xml version='1.0' encoding='UTF-8'?><b>&t;</b>",
+                ),
+                ("title.xml", "Title"),
+            ],
+            "<!DOCTYPE a [\
+               <!ENTITY chapter SYSTEM 'chapter.xml'>\
+               <!ENTITY t PUBLIC '-//T//EN' 'title.xml'>\
+             ]><a>&chapter;</a>",
+        )
+        .expect("Failed to parse");
+        let doc = package.as_document();
+        let b = top(&doc).children()[0].element().unwrap();
+
+        assert_qname_eq!(b.name(), "b");
+        assert_eq!(text_content(b), "Title");
+    }
+
+    #[test]
+    fn external_parameter_entities_are_expanded() {
+        let package = parse_with_files(
+            vec![("decls.ent", "<!ENTITY e 'declared'>")],
+            "<!DOCTYPE a [<!ENTITY % decls SYSTEM 'decls.ent'> %decls;]><a>&e;</a>",
+        )
+        .expect("Failed to parse");
+        let doc = package.as_document();
+
+        assert_eq!(text_content(top(&doc)), "declared");
+    }
+
+    #[test]
+    fn failure_to_load_an_external_subset_is_an_error() {
+        let r = parse_with_files(vec![], "<!DOCTYPE a SYSTEM 'a.dtd'><a/>");
+
+        assert_eq!(
+            r.map(|_| ()),
+            Err(Error::new(20, ErrorKind::UnresolvedExternalEntity))
+        );
+    }
+
+    #[test]
+    fn errors_in_an_external_subset_are_reported_at_its_system_id() {
+        let r = parse_with_files(
+            vec![("a.dtd", "<!ENTITY")],
+            "<!DOCTYPE a SYSTEM 'a.dtd'><a/>",
+        );
+
+        assert_eq!(r.unwrap_err().location(), 20);
+    }
+
+    #[test]
+    fn failure_to_load_an_external_entity_is_an_error() {
+        let r = parse_with_files(
+            vec![],
+            "<!DOCTYPE a [<!ENTITY e SYSTEM 'e.xml'>]><a>&e;</a>",
+        );
+
+        assert_eq!(
+            r.map(|_| ()),
+            Err(Error::new(45, ErrorKind::UnresolvedExternalEntity))
+        );
+    }
+
+    #[test]
+    fn recursive_external_entities_are_an_error() {
+        let r = parse_with_files(
+            vec![("e.xml", "&e;")],
+            "<!DOCTYPE a [<!ENTITY e SYSTEM 'e.xml'>]><a>&e;</a>",
+        );
+
+        assert_eq!(
+            r.map(|_| ()),
+            Err(Error::new(45, ErrorKind::RecursiveEntityReference))
+        );
+    }
+
+    #[test]
+    fn cdata_sections_are_plain_text_by_default() {
+        let package = quick_parse("<a><![CDATA[<b>]]></a>");
+        let doc = package.as_document();
+        let text = top(&doc).children()[0].text().unwrap();
+
+        assert_eq!(text.text(), "<b>");
+        assert!(!text.is_cdata());
+    }
+
+    #[test]
+    fn cdata_sections_can_be_preserved() {
+        let package = Parser::new()
+            .set_preserve_cdata(true)
+            .parse("<a>x<![CDATA[<b>]]></a>")
+            .expect("Failed to parse the XML string");
+        let doc = package.as_document();
+        let children = top(&doc).children();
+
+        assert!(!children[0].text().unwrap().is_cdata());
+        let text = children[1].text().unwrap();
+        assert_eq!(text.text(), "<b>");
+        assert!(text.is_cdata());
+    }
+
+    #[test]
+    fn namespaces_can_be_left_unprocessed() {
+        let package = Parser::new()
+            .set_namespace_aware(false)
+            .parse("<x:a xmlns:x='urn:x' y:b='1'/>")
+            .expect("Failed to parse the XML string");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_qname_eq!(top.name(), "x:a");
+        assert_eq!(top.attribute_value("xmlns:x"), Some("urn:x"));
+        assert_eq!(top.attribute_value("y:b"), Some("1"));
+    }
+
+    #[test]
+    fn duplicate_attributes_are_an_error_without_namespaces() {
+        let r = Parser::new()
+            .set_namespace_aware(false)
+            .parse("<a b='1' b='2'/>");
+
+        assert_eq!(
+            r.map(|_| ()),
+            Err(Error::new(9, ErrorKind::DuplicateAttribute))
+        );
+    }
+
+    #[test]
+    fn parser_entities_are_expanded() {
+        let package = Parser::new()
+            .add_entity("who", "<b>world</b>")
+            .parse("<a>hello &who;</a>")
+            .expect("Failed to parse the XML string");
+        let doc = package.as_document();
+        let top = top(&doc);
+        let b = top.children()[1].element().unwrap();
+
+        assert_eq!(text_content(top), "hello ");
+        assert_eq!(text_content(b), "world");
+    }
+
+    #[test]
+    fn entities_can_contain_long_cdata_sections() {
+        let package = Parser::new()
+            .add_entity("e", "<![CDATA[0123456789]]>")
+            .parse("<a>&e;</a>")
+            .expect("Failed to parse the XML string");
+        let doc = package.as_document();
+
+        assert_eq!(text_content(top(&doc)), "0123456789");
+    }
+
+    #[test]
+    fn entities_can_contain_long_comments() {
+        let package = Parser::new()
+            .add_entity("e", "<!--0123456789-->")
+            .parse("<a>&e;</a>")
+            .expect("Failed to parse the XML string");
+        let doc = package.as_document();
+        let comment = top(&doc).children()[0].comment().unwrap();
+
+        assert_eq!(comment.text(), "0123456789");
+    }
+
+    #[test]
+    fn entities_can_contain_long_processing_instructions() {
+        let package = Parser::new()
+            .add_entity("e", "<?pi 0123456789?>")
+            .parse("<a>&e;</a>")
+            .expect("Failed to parse the XML string");
+        let doc = package.as_document();
+        let pi = top(&doc).children()[0].processing_instruction().unwrap();
+
+        assert_eq!(pi.value(), Some("0123456789"));
+    }
+
+    #[test]
+    fn document_entities_take_precedence_over_parser_entities() {
+        let package = Parser::new()
+            .add_entity("who", "parser")
+            .add_entity("what", "parser")
+            .parse("<!DOCTYPE a [<!ENTITY who 'document'>]><a>&who; &what;</a>")
+            .expect("Failed to parse the XML string");
+        let doc = package.as_document();
+
+        assert_eq!(text_content(top(&doc)), "document parser");
+    }
+
+    #[test]
+    fn html_entities_are_unknown_by_default() {
+        let r = full_parse("<a>&nbsp;</a>");
+
+        assert_eq!(
+            r.map(|_| ()),
+            Err(Error::new(4, ErrorKind::UnknownNamedReference))
+        );
+    }
+
+    #[test]
+    fn html_entities_can_be_declared() {
+        let package = Parser::new()
+            .set_html_entities(true)
+            .parse("<a b='caf&eacute;'>x&nbsp;&ge;&nbsp;y</a>")
+            .expect("Failed to parse the XML string");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_eq!(top.attribute_value("b"), Some("caf\u{E9}"));
+        assert_eq!(text_content(top), "x\u{A0}\u{2265}\u{A0}y");
+    }
+
+    #[test]
+    fn parser_entities_take_precedence_over_html_entities() {
+        let package = Parser::new()
+            .set_html_entities(true)
+            .add_entity("nbsp", " ")
+            .parse("<!DOCTYPE a [<!ENTITY copy 'c'>]><a>&copy;&nbsp;&reg;</a>")
+            .expect("Failed to parse the XML string");
+        let doc = package.as_document();
+
+        assert_eq!(text_content(top(&doc)), "c \u{AE}");
+    }
+
+    #[test]
+    fn source_spans_are_not_recorded_by_default() {
+        let package = quick_parse("<a>text</a>");
+        let doc = package.as_document();
+        let a = top(&doc);
+
+        assert_eq!(a.source_span(), None);
+        assert_eq!(a.children()[0].text().unwrap().source_span(), None);
+    }
+
+    fn spans(xml: &str) -> Vec<(usize, usize)> {
+        let package = Parser::new()
+            .set_source_spans(true)
+            .parse(xml)
+            .expect("Failed to parse the XML string");
+        let doc = package.as_document();
+        let a = top(&doc);
+
+        let mut spans = vec![a.source_span()];
+        spans.extend(a.attributes().iter().map(|attr| attr.source_span()));
+        spans.extend(a.children().iter().map(|c| match *c {
+            dom::ChildOfElement::Element(e) => e.source_span(),
+            dom::ChildOfElement::Text(t) => t.source_span(),
+            dom::ChildOfElement::Comment(c) => c.source_span(),
+            dom::ChildOfElement::ProcessingInstruction(pi) => pi.source_span(),
+        }));
+        spans
+            .into_iter()
+            .map(|s| s.expect("No source span"))
+            .map(|s| (s.start(), s.end()))
+            .collect()
+    }
+
+    #[test]
+    fn source_spans_can_be_recorded() {
+        let spans = spans("<a x='1'>hi<!--c--><?p v?><b/></a>");
+
+        assert_eq!(
+            spans,
+            [(0, 34), (3, 8), (9, 11), (11, 19), (19, 26), (26, 30)]
+        );
+    }
+
+    #[test]
+    fn source_spans_of_entity_content_are_the_reference() {
+        let xml = "<!DOCTYPE a [<!ENTITY e '<b>x</b>'>]><a>&amp;&e;</a>";
+        let spans = spans(xml);
+        let amp = xml.find("&amp;").unwrap();
+        let e = xml.find("&e;").unwrap();
+
+        assert_eq!(spans[1..], [(amp, amp + 5), (e, e + 3)]);
+    }
+
+    #[test]
+    fn source_spans_are_recorded_when_parsing_a_reader() {
+        let xml = "<a x='1'>hi<!--c--><?p v?><b/></a>";
+        let package = Parser::new()
+            .set_source_spans(true)
+            .parse_reader(xml.as_bytes())
+            .expect("Failed to parse the XML");
+        let doc = package.as_document();
+        let a = top(&doc);
+        let x = a.attribute("x").unwrap();
+
+        assert_eq!(a.source_span(), Some(dom::SourceSpan::new(0, 34)));
+        assert_eq!(x.source_span(), Some(dom::SourceSpan::new(3, 8)));
+    }
+
+    fn parse_recovering(xml: &str) -> (Package, Vec<(usize, ErrorKind)>) {
+        let (package, errors) = Parser::new().parse_recovering(xml);
+        let errors = errors.iter().map(|e| (e.location(), e.kind())).collect();
+        (package, errors)
+    }
+
+    #[test]
+    fn recovering_a_well_formed_document_finds_no_errors() {
+        let (package, errors) = parse_recovering("<a><b>text</b></a>");
+        let doc = package.as_document();
+        let b = top(&doc).children()[0].element().unwrap();
+
+        assert_eq!(errors, []);
+        assert_eq!(text_content(b), "text");
+    }
+
+    #[test]
+    fn recovering_closes_unclosed_elements() {
+        let (package, errors) = parse_recovering("<a><b>text");
+        let doc = package.as_document();
+        let b = top(&doc).children()[0].element().unwrap();
+
+        assert_eq!(errors, [(10, ErrorKind::UnclosedElement)]);
+        assert_eq!(text_content(b), "text");
+    }
+
+    #[test]
+    fn recovering_closes_elements_up_to_a_matching_end_tag() {
+        let (package, errors) = parse_recovering("<a><b><c></b>text</a>");
+        let doc = package.as_document();
+        let a = top(&doc);
+        let b = a.children()[0].element().unwrap();
+
+        assert_eq!(errors, [(11, ErrorKind::MismatchedElementEndName)]);
+        assert_eq!(b.children().len(), 1);
+        assert_eq!(text_content(a), "text");
+    }
+
+    #[test]
+    fn recovering_ignores_end_tags_without_a_start_tag() {
+        let (package, errors) = parse_recovering("<a>x</c>y</a>");
+        let doc = package.as_document();
+
+        assert_eq!(errors, [(6, ErrorKind::MismatchedElementEndName)]);
+        assert_eq!(text_content(top(&doc)), "xy");
+    }
+
+    #[test]
+    fn recovering_keeps_a_stray_ampersand_as_text() {
+        let (package, errors) = parse_recovering("<a>fish & chips</a>");
+        let doc = package.as_document();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 9);
+        assert_eq!(text_content(top(&doc)), "fish & chips");
+    }
+
+    #[test]
+    fn recovering_keeps_the_first_of_duplicate_attributes() {
+        let (package, errors) = parse_recovering("<a x='1' x='2' y='3'/>");
+        let doc = package.as_document();
+        let a = top(&doc);
+
+        assert_eq!(errors, [(9, ErrorKind::DuplicateAttribute)]);
+        assert_eq!(a.attribute_value("x"), Some("1"));
+        assert_eq!(a.attribute_value("y"), Some("3"));
+    }
+
+    #[test]
+    fn recovering_skips_the_rest_of_a_malformed_start_tag() {
+        let (package, errors) = parse_recovering("<a><b c=1>text</b></a>");
+        let doc = package.as_document();
+        let b = top(&doc).children()[0].element().unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert!(b.attributes().is_empty());
+        assert_eq!(text_content(b), "text");
+    }
+
+    #[test]
+    fn recovering_keeps_elements_whose_attributes_are_wrong() {
+        let (package, errors) = parse_recovering("<a><b x:c='1'>text</b></a>");
+        let doc = package.as_document();
+        let b = top(&doc).children()[0].element().unwrap();
+
+        assert_eq!(errors, [(6, ErrorKind::UnknownNamespacePrefix)]);
+        assert_qname_eq!(b.name(), "b");
+        assert_eq!(text_content(b), "text");
+    }
+
+    #[test]
+    fn recovering_leaves_out_unknown_entities() {
+        let (package, errors) = parse_recovering("<a>x&y;z</a>");
+        let doc = package.as_document();
+
+        assert_eq!(errors, [(5, ErrorKind::UnknownNamedReference)]);
+        assert_eq!(text_content(top(&doc)), "xz");
+    }
+
+    #[test]
+    fn recovering_reports_every_error_with_its_line() {
+        let (_, errors) = Parser::new().parse_recovering("<a>\n&\n<b x='1' x='2'/>\n</c>");
+        let lines: Vec<_> = errors.iter().map(|e| e.line()).collect();
+
+        assert_eq!(lines, [2, 3, 4, 4]);
+    }
+
+    #[test]
+    fn recovering_locates_many_errors_on_one_long_line() {
+        let xml = format!("<a>{}</a>", "x & ".repeat(20_000));
+        let (_, errors) = Parser::new().parse_recovering(&xml);
+
+        assert_eq!(errors.len(), 20_000);
+        for (i, e) in errors.iter().enumerate() {
+            assert_eq!(e.line(), 1);
+            assert_eq!(e.column(), 4 + 4 * i + 3);
+            assert!(e.line_text().len() <= 2 * LINE_TEXT_CONTEXT);
+        }
+        assert_eq!(errors[0].line_text(), &xml[..6 + LINE_TEXT_CONTEXT]);
+        assert_eq!(errors[0].line_text_column(), 1);
+    }
+
+    #[test]
+    fn errors_on_long_lines_show_where_the_line_text_starts() {
+        let xml = format!("<a>{}&bogus;</a>", "x".repeat(300));
+        let err = full_parse(&xml).unwrap_err();
+
+        assert_eq!(err.column(), 305);
+        assert_eq!(err.line_text_column(), 305 - LINE_TEXT_CONTEXT);
+        let marked: String = err
+            .line_text()
+            .chars()
+            .skip(err.column() - err.line_text_column())
+            .collect();
+        assert!(marked.starts_with("bogus;"));
+    }
+
+    #[test]
+    fn a_fragment_is_appended_to_an_element() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let p = doc.create_element("p");
+        p.append_child(doc.create_comment("before"));
+
+        parse_fragment_into(p, "text <b>bold</b> more<!--c--><i/>").expect("Failed to parse");
+
+        let children = p.children();
+        assert_eq!(children.len(), 6);
+        assert_eq!(children[1].text().unwrap().text(), "text ");
+        assert_qname_eq!(children[2].element().unwrap().name(), "b");
+        assert_eq!(children[3].text().unwrap().text(), " more");
+        assert_eq!(children[4].comment().unwrap().text(), "c");
+        assert_qname_eq!(children[5].element().unwrap().name(), "i");
+    }
+
+    #[test]
+    fn a_fragment_may_be_only_text() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let p = doc.create_element("p");
+
+        parse_fragment_into(p, "a &amp; b").expect("Failed to parse");
+
+        assert_eq!(text_content(p), "a & b");
+    }
+
+    #[test]
+    fn a_fragment_uses_the_namespaces_in_scope() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let outer = doc.create_element("outer");
+        outer.register_prefix("x", "urn:x");
+        outer.set_default_namespace_uri(Some("urn:default"));
+        let p = doc.create_element(("urn:default", "p"));
+        outer.append_child(p);
+
+        parse_fragment_into(p, "<x:a x:attr='1'/><b/>").expect("Failed to parse");
+
+        let children = p.children();
+        let a = children[0].element().unwrap();
+        let b = children[1].element().unwrap();
+        assert_qname_eq!(a.name(), ("urn:x", "a"));
+        assert_eq!(a.attribute_value(("urn:x", "attr")), Some("1"));
+        assert_qname_eq!(b.name(), ("urn:default", "b"));
+    }
+
+    #[test]
+    fn a_fragment_can_declare_its_own_namespaces() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let p = doc.create_element("p");
+
+        parse_fragment_into(p, "<x:a xmlns:x='urn:x'/>").expect("Failed to parse");
+
+        let a = p.children()[0].element().unwrap();
+        assert_qname_eq!(a.name(), ("urn:x", "a"));
+    }
+
+    #[test]
+    fn a_fragment_may_start_with_a_text_declaration() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let p = doc.create_element("p");
+
+        parse_fragment_into(p, "<?xml encoding='UTF-8'?>text").expect("Failed to parse");
+
+        assert_eq!(p.children().len(), 1);
+        assert_eq!(text_content(p), "text");
+    }
+
+    #[test]
+    fn a_fragment_with_an_unknown_prefix_appends_nothing() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let p = doc.create_element("p");
+
+        let r = parse_fragment_into(p, "text <y:a/>");
+
+        assert_eq!(r, Err(Error::new(6, ErrorKind::UnknownNamespacePrefix)));
+        assert_eq!(p.children().len(), 0);
+    }
+
+    #[test]
+    fn a_fragment_with_an_unclosed_element_fails() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let p = doc.create_element("p");
+
+        let r = parse_fragment_into(p, "<a>text");
+
+        assert_eq!(r, Err(Error::new(7, ErrorKind::UnclosedElement)));
+        assert_eq!(p.children().len(), 0);
+    }
+
+    #[test]
+    fn a_fragment_cannot_close_the_element_it_is_parsed_into() {
+        let package = Package::new();
+        let doc = package.as_document();
+        let p = doc.create_element("p");
+
+        let r = parse_fragment_into(p, "text</p>");
+
+        assert!(r.is_err());
+        assert_eq!(p.children().len(), 0);
+    }
+
+    #[test]
+    fn line_ends_are_normalized() {
+        let package = quick_parse("<a>a\r\nb\rc<![CDATA[\r\n]]><!--\r\n--><?pi x\r\ny?></a>");
+        let doc = package.as_document();
+        let a = top(&doc);
+        let children = a.children();
+
+        assert_eq!(text_content(a), "a\nb\nc\n");
+        assert_eq!(children[2].comment().unwrap().text(), "\n");
+        assert_eq!(
+            children[3].processing_instruction().unwrap().value(),
+            Some("x\ny")
+        );
+    }
+
+    #[test]
+    fn line_ends_from_references_are_kept() {
+        let package =
+            quick_parse("<!DOCTYPE a [<!ENTITY e '&#13;\r\n'>]><a b='&#13;&#10;&#9;'>&#13;&e;</a>");
+        let doc = package.as_document();
+        let a = top(&doc);
+
+        assert_eq!(a.attribute_value("b"), Some("\r\n\t"));
+        assert_eq!(text_content(a), "\r\r\n");
+    }
+
+    #[test]
+    fn attribute_value_whitespace_becomes_spaces() {
+        let package = quick_parse("<a b='1\t2\r\n3\n4\r5'/>");
+        let doc = package.as_document();
+
+        assert_eq!(top(&doc).attribute_value("b"), Some("1 2 3 4 5"));
+    }
+
+    #[test]
+    fn attribute_value_whitespace_from_entities_becomes_spaces() {
+        let package = quick_parse("<!DOCTYPE a [<!ENTITY e 'x&#9;y\r\nz'>]><a b='&e;'/>");
+        let doc = package.as_document();
+
+        assert_eq!(top(&doc).attribute_value("b"), Some("x y z"));
+    }
+
+    #[test]
+    fn tokenized_attribute_values_have_their_spaces_collapsed() {
+        let package = quick_parse(
+            "<!DOCTYPE a [<!ATTLIST a id ID #IMPLIED refs IDREFS #IMPLIED>]>\
+             <a id=' x ' refs='\ty \n z ' other=' w '/>",
+        );
+        let doc = package.as_document();
+        let a = top(&doc);
+
+        assert_eq!(a.attribute_value("id"), Some("x"));
+        assert_eq!(a.attribute_value("refs"), Some("y z"));
+        assert_eq!(a.attribute_value("other"), Some(" w "));
+    }
+
+    #[test]
+    fn xml_1_1_line_ends_are_normalized() {
+        let package = quick_parse(
+            "<?xml version='1.1'?><a x='1\u{85}2'>a\r\nb\rc\r\u{85}d\u{85}e\u{2028}f<!--\u{85}--></a>",
+        );
+        let doc = package.as_document();
+        let a = top(&doc);
+
+        assert_eq!(text_content(a), "a\nb\nc\nd\ne\nf");
+        assert_eq!(a.attribute_value("x"), Some("1 2"));
+        assert_eq!(a.children()[1].comment().unwrap().text(), "\n");
+    }
+
+    #[test]
+    fn xml_1_0_does_not_treat_nel_and_ls_as_line_ends() {
+        let package = quick_parse("<a>a\u{85}b\u{2028}c</a>");
+        let doc = package.as_document();
+
+        assert_eq!(text_content(top(&doc)), "a\u{85}b\u{2028}c");
+    }
+
+    #[test]
+    fn xml_1_1_line_ends_from_references_are_kept() {
+        let package = quick_parse("<?xml version='1.1'?><a>&#xD;&#x85;&#x2028;</a>");
+        let doc = package.as_document();
+
+        assert_eq!(text_content(top(&doc)), "\r\u{85}\u{2028}");
+    }
+
+    #[test]
+    fn xml_1_1_allows_references_to_restricted_characters() {
+        let package = quick_parse("<?xml version='1.1'?><a b='&#x1;'>&#x7F;</a>");
+        let doc = package.as_document();
+        let a = top(&doc);
+
+        assert_eq!(a.attribute_value("b"), Some("\u{1}"));
+        assert_eq!(text_content(a), "\u{7F}");
+    }
+
+    #[test]
+    fn xml_1_0_allows_literal_delete_characters() {
+        let package = quick_parse("<a>\u{7F}</a>");
+        let doc = package.as_document();
+
+        assert_eq!(text_content(top(&doc)), "\u{7F}");
+    }
+
+    #[test]
+    fn the_xml_prefix_may_be_declared() {
+        let package =
+            quick_parse("<a xmlns:xml='http://www.w3.org/XML/1998/namespace' xml:lang='en'/>");
+        let doc = package.as_document();
+        let top = top(&doc);
+
+        assert_eq!(top.attribute_value((crate::XML_NS_URI, "lang")), Some("en"));
+    }
+
+    #[test]
+    fn xml_1_1_prefixes_can_be_undeclared() {
+        let package = quick_parse("<?xml version='1.1'?><a xmlns:p='urn:p'><b xmlns:p=''/></a>");
+        let doc = package.as_document();
+        let b = top(&doc).children()[0].element().unwrap();
+
+        assert_eq!(b.namespace_uri_for_prefix("p"), None);
+    }
+
+    fn parse_with_whitespace(whitespace: WhitespaceMode, xml: &str) -> Package {
+        Parser::new()
+            .set_whitespace(whitespace)
+            .parse(xml)
+            .expect("Failed to parse the XML string")
+    }
+
+    fn texts<'d>(element: dom::Element<'d>) -> Vec<&'d str> {
+        element
+            .children()
+            .iter()
+            .filter_map(|c| c.text())
+            .map(|t| t.text())
+            .collect()
+    }
+
+    #[test]
+    fn whitespace_is_kept_by_default() {
+        let package = quick_parse("<a>\n <b> x </b>\n</a>");
+        let doc = package.as_document();
+
+        assert_eq!(texts(top(&doc)), ["\n ", "\n"]);
+    }
+
+    #[test]
+    fn blank_text_can_be_removed() {
+        let package = parse_with_whitespace(
+            WhitespaceMode::RemoveBlank,
+            "<a>\n <b> x </b>\n <c> </c>&#32;</a>",
+        );
+        let doc = package.as_document();
+        let a = top(&doc);
+
+        assert_eq!(a.children().len(), 2);
+        let b = a.children()[0].element().unwrap();
+        assert_eq!(texts(b), [" x "]);
+    }
+
+    #[test]
+    fn text_can_be_trimmed() {
+        let package = Parser::new()
+            .set_whitespace(WhitespaceMode::Trim)
+            .set_preserve_cdata(true)
+            .parse("<a>\n <b> x &amp; y </b><![CDATA[ z ]]></a>")
+            .expect("Failed to parse the XML string");
+        let doc = package.as_document();
+        let a = top(&doc);
+        let b = a.children()[0].element().unwrap();
+
+        assert_eq!(texts(b), ["x ", "&", " y"]);
+        assert_eq!(texts(a), [" z "]);
+    }
+
+    #[test]
+    fn whitespace_modes_respect_xml_space() {
+        let package = parse_with_whitespace(
+            WhitespaceMode::Trim,
+            "<a xml:space='preserve'> <b> x </b> \
+             <c xml:space='default'> <d> y </d> </c></a>",
+        );
+        let doc = package.as_document();
+        let a = top(&doc);
+        let children = a.children();
+
+        assert_eq!(texts(a), [" ", " "]);
+        assert_eq!(texts(children[1].element().unwrap()), [" x "]);
+        let c = children[3].element().unwrap();
+        assert_eq!(c.children().len(), 1);
+        assert_eq!(texts(c.children()[0].element().unwrap()), ["y"]);
+    }
+
+    fn parse_with_limits(limits: ParseLimits, xml: &str) -> Result<(), Error> {
+        Parser::new().set_limits(limits).parse(xml).map(|_| ())
+    }
+
+    fn limit_exceeded(offset: usize, limit: Limit) -> Result<(), Error> {
+        Err(Error::new(offset, ErrorKind::LimitExceeded(limit)))
+    }
+
+    #[test]
+    fn documents_within_the_limits_are_parsed() {
+        let limits = ParseLimits::new()
+            .set_max_depth(2)
+            .set_max_attributes(2)
+            .set_max_name_length(3)
+            .set_max_text_length(5)
+            .set_max_nodes(4);
+        let r = parse_with_limits(limits, "<a x='12345' y=''><bcd>hello</bcd><!--c--></a>");
+
+        assert_eq!(r, Ok(()));
+    }
+
+    #[test]
+    fn nesting_depth_is_limited() {
+        let limits = ParseLimits::new().set_max_depth(2);
+        let r = parse_with_limits(limits, "<a><b><c/></b></a>");
+
+        assert_eq!(r, limit_exceeded(7, Limit::Depth));
+    }
+
+    #[test]
+    fn attribute_count_is_limited() {
+        let limits = ParseLimits::new().set_max_attributes(2);
+        let r = parse_with_limits(limits, "<a xmlns:x='u' b='1' c='2'/>");
+
+        assert_eq!(r, limit_exceeded(21, Limit::Attributes));
+    }
+
+    #[test]
+    fn name_length_is_limited() {
+        let limits = ParseLimits::new().set_max_name_length(3);
+
+        assert_eq!(
+            parse_with_limits(limits, "<abcd/>"),
+            limit_exceeded(1, Limit::NameLength)
+        );
+        assert_eq!(
+            parse_with_limits(limits, "<a b:cd='1' xmlns:b='u'/>"),
+            limit_exceeded(3, Limit::NameLength)
+        );
+        assert_eq!(
+            parse_with_limits(limits, "<a><?abcd?></a>"),
+            limit_exceeded(3, Limit::NameLength)
+        );
+    }
+
+    #[test]
+    fn text_length_is_limited() {
+        let limits = ParseLimits::new().set_max_text_length(5);
+
+        assert_eq!(
+            parse_with_limits(limits, "<a>123456</a>"),
+            limit_exceeded(3, Limit::TextLength)
+        );
+        assert_eq!(
+            parse_with_limits(limits, "<a b='12&amp;456'/>"),
+            limit_exceeded(3, Limit::TextLength)
+        );
+    }
+
+    #[test]
+    fn node_count_is_limited() {
+        let limits = ParseLimits::new().set_max_nodes(3);
+        let r = parse_with_limits(limits, "<a><b/>text<!--comment--></a>");
 
-        assert_qname_eq!(top.name(), "hello");
+        assert_eq!(r, limit_exceeded(11, Limit::Nodes));
     }
 
     #[test]
-    fn a_document_with_a_prolog_with_double_quotes() {
-        let package = quick_parse("<?xml version=\"1.0\" ?><hello />");
-        let doc = package.as_document();
-        let top = top(&doc);
+    fn entity_nesting_depth_is_limited() {
+        let limits = ParseLimits::new().set_max_entity_depth(2);
+        let r = parse_with_limits(
+            limits,
+            "<!DOCTYPE a [ \
+             <!ENTITY a 'a'> \
+             <!ENTITY b '&a;'> \
+             <!ENTITY c '&b;'> \
+             ]><a>&b;&c;</a>",
+        );
 
-        assert_qname_eq!(top.name(), "hello");
+        assert_eq!(r, limit_exceeded(75, Limit::EntityDepth));
     }
 
+    const LAUGHS: &str = "<!DOCTYPE a [ \
+                          <!ENTITY l0 'lol'> \
+                          <!ENTITY l1 '&l0;&l0;&l0;&l0;&l0;&l0;&l0;&l0;&l0;&l0;'> \
+                          <!ENTITY l2 '&l1;&l1;&l1;&l1;&l1;&l1;&l1;&l1;&l1;&l1;'> \
+                          <!ENTITY l3 '&l2;&l2;&l2;&l2;&l2;&l2;&l2;&l2;&l2;&l2;'> \
+                          <!ENTITY l4 '&l3;&l3;&l3;&l3;&l3;&l3;&l3;&l3;&l3;&l3;'> \
+                          <!ENTITY l5 '&l4;&l4;&l4;&l4;&l4;&l4;&l4;&l4;&l4;&l4;'> \
+                          <!ENTITY l6 '&l5;&l5;&l5;&l5;&l5;&l5;&l5;&l5;&l5;&l5;'> \
+                          <!ENTITY l7 '&l6;&l6;&l6;&l6;&l6;&l6;&l6;&l6;&l6;&l6;'> \
+                          <!ENTITY l8 '&l7;&l7;&l7;&l7;&l7;&l7;&l7;&l7;&l7;&l7;'> \
+                          <!ENTITY l9 '&l8;&l8;&l8;&l8;&l8;&l8;&l8;&l8;&l8;&l8;'> \
+                          ]>";
+
     #[test]
-    fn a_prolog_with_an_encoding() {
-        let package = quick_parse("<?xml version='1.0' encoding='UTF-8' ?><hello />");
-        let doc = package.as_document();
-        let top = top(&doc);
+    fn entity_expansion_in_content_is_limited() {
+        let xml = format!("{}<a>&l9;</a>", LAUGHS);
+        let r = parse_with_limits(ParseLimits::new(), &xml);
 
-        assert_qname_eq!(top.name(), "hello");
+        assert_eq!(
+            r.unwrap_err().kind(),
+            ErrorKind::LimitExceeded(Limit::EntityAmplification)
+        );
     }
 
     #[test]
-    fn a_non_standalone_prolog() {
-        let package = quick_parse("<?xml version='1.0' standalone='no'?><hello/>");
-        let doc = package.as_document();
-        let top = top(&doc);
+    fn entity_expansion_in_attribute_values_is_limited() {
+        let xml = format!("{}<a b='&l9;'/>", LAUGHS);
+        let r = parse_with_limits(ParseLimits::new(), &xml);
 
-        assert_qname_eq!(top.name(), "hello");
+        assert_eq!(
+            r.unwrap_err().kind(),
+            ErrorKind::LimitExceeded(Limit::EntityAmplification)
+        );
     }
 
     #[test]
-    fn a_complete_prolog() {
-        let package =
-            quick_parse("<?xml version='1.0' encoding='UTF-8' standalone='yes'?><hello/>");
-        let doc = package.as_document();
-        let top = top(&doc);
+    fn moderate_entity_expansion_is_allowed() {
+        let xml = format!("{}<a>&l4;</a>", LAUGHS);
+        let r = parse_with_limits(ParseLimits::new(), &xml);
 
-        assert_qname_eq!(top.name(), "hello");
+        assert_eq!(r, Ok(()));
     }
 
     #[test]
-    fn a_prolog_with_a_doc_type_declaration_external_id() {
-        let package = quick_parse(
-            r#"<?xml version='1.0'?>
-        <!DOCTYPE doc SYSTEM "http://example.com/doc.dtd">
-        <hello/>"#,
+    fn exceeded_limits_are_displayed() {
+        let kind = ErrorKind::LimitExceeded(Limit::Depth);
+
+        assert_eq!(
+            kind.to_string(),
+            "the element nesting depth is over the limit"
         );
-        let doc = package.as_document();
-        let top = top(&doc);
+    }
 
-        assert_qname_eq!(top.name(), "hello");
+    fn events(xml: &str) -> Vec<Event<'_>> {
+        EventReader::new(xml)
+            .collect::<Result<_, _>>()
+            .expect("Failed to read events")
     }
 
     #[test]
-    fn a_prolog_with_a_doc_type_declaration_int_subset() {
-        let package = quick_parse(
-            r#"<?xml version="1.0"?>
-            <!DOCTYPE note [
-            <!ELEMENT note (to,from,heading,body)>
-            <!ELEMENT to (#PCDATA)>
-            <!ELEMENT from (#PCDATA)>
-            <!ELEMENT heading (#PCDATA)>
-            <!ELEMENT body (#PCDATA)>
-            ]>
-            <note>
-            <to>Tove</to>
-            <from>Jani</from>
-            <heading>Reminder</heading>
-            <body>Don't forget me this weekend</body>
-            </note>
-        "#,
+    fn events_for_a_prolog() {
+        let events = events(
+            "<?xml version='1.0' encoding='UTF-8' standalone='yes'?>\
+             <!DOCTYPE hello SYSTEM 'hello.dtd'><hello/>",
         );
-        let doc = package.as_document();
-        let top = top(&doc);
 
-        assert_qname_eq!(top.name(), "note");
+        assert_eq!(
+            events[..2],
+            [
+                Event::XmlDeclaration {
+                    version: "1.0",
+                    encoding: Some("UTF-8"),
+                    standalone: Some(true),
+                },
+                Event::DocumentType {
+                    name: "hello",
+                    public_id: None,
+                    system_id: Some("hello.dtd"),
+                    internal_subset: None,
+                },
+            ]
+        );
     }
 
     #[test]
-    fn a_prolog_with_a_doc_type_declaration_int_subset_trailing_ws() {
-        let package = quick_parse(
-            r#"<?xml version="1.0"?>
-            <!DOCTYPE note
-                [
-                    <!ELEMENT note (to,from,heading,body)>
-                    <!ELEMENT to (#PCDATA)>
-                    <!ELEMENT from (#PCDATA)>
-                    <!ELEMENT heading (#PCDATA)>
-                    <!ELEMENT body (#PCDATA)>
-                ]
+    fn events_for_a_self_closing_element_with_attributes() {
+        let events = events("<ns:hello a='1' b='x &amp; y'/>");
 
-            >
-            <note>
-            <to>Tove</to>
-            <from>Jani</from>
-            <heading>Reminder</heading>
-            <body>Don't forget me this weekend</body>
-            </note>
-        "#,
+        match events[0] {
+            Event::StartElement {
+                name,
+                ref attributes,
+            } => {
+                assert_eq!(name, PrefixedName::with_prefix(Some("ns"), "hello"));
+                assert_eq!(attributes.len(), 2);
+                assert_eq!(attributes[0].name(), PrefixedName::new("a"));
+                assert_eq!(attributes[0].value(), "1");
+                assert_eq!(attributes[1].value(), "x & y");
+            }
+            ref e => panic!("Unexpected event {:?}", e),
+        }
+        assert_eq!(
+            events[1],
+            Event::EndElement {
+                name: PrefixedName::with_prefix(Some("ns"), "hello")
+            }
         );
-        let doc = package.as_document();
-        let top = top(&doc);
+        assert_eq!(events.len(), 2);
+    }
 
-        assert_qname_eq!(top.name(), "note");
+    #[test]
+    fn events_combine_text_and_references() {
+        let events = events("<a>1 &lt; 2 &#38; 3<![CDATA[<]]><!--c--><?pi v?></a>");
+
+        assert_eq!(
+            events[1..5],
+            [
+                Event::Text("1 < 2 & 3".into()),
+                Event::CData("<".into()),
+                Event::Comment("c".into()),
+                Event::ProcessingInstruction {
+                    target: "pi",
+                    value: Some("v".into()),
+                },
+            ]
+        );
     }
 
     #[test]
-    fn a_prolog_with_a_doc_type_declaration_zero_def() {
-        let package = quick_parse(
-            "<?xml version='1.0'?>
-        <!DOCTYPE doc>
-        <hello/>",
+    fn events_normalize_line_ends() {
+        let events = events("<a>\r\nx\ry<![CDATA[\r\n]]><!--\r\n--><?pi a\r\nb?></a>");
+
+        assert_eq!(
+            events[1..5],
+            [
+                Event::Text("\nx\ny".into()),
+                Event::CData("\n".into()),
+                Event::Comment("\n".into()),
+                Event::ProcessingInstruction {
+                    target: "pi",
+                    value: Some("a\nb".into()),
+                },
+            ]
         );
-        let doc = package.as_document();
-        let top = top(&doc);
+    }
 
-        assert_qname_eq!(top.name(), "hello");
+    #[test]
+    fn events_keep_line_ends_from_references() {
+        let events = events("<a>x\r\n&#13;&#10;</a>");
+
+        assert_eq!(events[1], Event::Text("x\n\r\n".into()));
     }
 
     #[test]
-    fn a_prolog_with_a_doc_type_declaration_zero_def_trailing_ws() {
-        let package = quick_parse(
-            "<?xml version='1.0'?>
-        <!DOCTYPE doc  >
-        <hello/>",
-        );
-        let doc = package.as_document();
-        let top = top(&doc);
+    fn events_normalize_xml_1_1_line_ends() {
+        let events = events("<?xml version='1.1'?><a>x\u{85}y\u{2028}z</a>");
 
-        assert_qname_eq!(top.name(), "hello");
+        assert_eq!(events[2], Event::Text("x\ny\nz".into()));
     }
 
     #[test]
-    fn a_prolog_with_a_doc_type_declaration_both_int_subset_and_external_id() {
-        let package = quick_parse(
-            r#"<?xml version='1.0'?>
-        <!DOCTYPE doc SYSTEM "http://example.com/doc.dtd" [
-        <!ELEMENT hello (#PCDATA)>
-        ]>
-        <hello/>"#,
+    fn events_normalize_attribute_values() {
+        let events = events(
+            "<!DOCTYPE a [<!ATTLIST a t NMTOKENS #IMPLIED>]>\
+             <a b='1\t2\r\n3' c='&#9;x\ty' t=' x\t &#32;y '/>",
         );
-        let doc = package.as_document();
-        let top = top(&doc);
 
-        assert_qname_eq!(top.name(), "hello");
+        match events[1] {
+            Event::StartElement { ref attributes, .. } => {
+                let values: Vec<_> = attributes.iter().map(|a| a.value()).collect();
+                assert_eq!(values, ["1 2 3", "\tx y", "x y"]);
+            }
+            ref e => panic!("Unexpected event {:?}", e),
+        }
     }
 
     #[test]
-    fn a_prolog_with_a_doc_type_declaration_both_int_subset_and_external_id_trailing_ws() {
-        let package = quick_parse(
-            r#"<?xml version='1.0'?>
-        <!DOCTYPE doc SYSTEM "http://example.com/doc.dtd" [
-        <!ELEMENT hello (#PCDATA)>
-         ]  >
-        <hello/>"#,
+    fn events_expand_entities_declared_in_the_internal_subset() {
+        let events = events(
+            "<!DOCTYPE a [<!ENTITY e 'x&f;'><!ENTITY f '&#38;#60;y'>]><a b='&e;'>1 &e; 2</a>",
         );
-        let doc = package.as_document();
-        let top = top(&doc);
 
-        assert_qname_eq!(top.name(), "hello");
+        assert_eq!(
+            events[1..3],
+            [
+                Event::StartElement {
+                    name: PrefixedName::new("a"),
+                    attributes: vec![Attribute {
+                        name: PrefixedName::new("b"),
+                        value: "x<y".into(),
+                    }],
+                },
+                Event::Text("1 x<y 2".into()),
+            ]
+        );
     }
 
     #[test]
-    fn a_document_with_a_single_element() {
-        let package = quick_parse("<hello />");
-        let doc = package.as_document();
-        let top = top(&doc);
+    fn events_cannot_expand_entities_with_markup() {
+        let mut reader = EventReader::new("<!DOCTYPE a [<!ENTITY e '<b/>'>]><a>&e;</a>");
 
-        assert_qname_eq!(top.name(), "hello");
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.location(), 37);
+        assert_eq!(err.kind(), ErrorKind::EntityWithMarkup);
     }
 
     #[test]
-    fn an_element_with_a_namespace() {
-        let package = quick_parse("<ns:hello xmlns:ns='namespace'/>");
-        let doc = package.as_document();
-        let top = top(&doc);
+    fn events_stop_after_an_error() {
+        let mut reader = EventReader::new("<a></b>");
 
-        assert_eq!(top.preferred_prefix(), Some("ns"));
-        assert_qname_eq!(("namespace", "hello"), top.name());
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.location(), 5);
+        assert!(reader.next().is_none());
     }
 
     #[test]
-    fn an_element_with_a_default_namespace() {
-        let package = quick_parse("<hello xmlns='namespace'/>");
-        let doc = package.as_document();
-        let top = top(&doc);
+    fn events_report_unclosed_elements() {
+        let r: Result<Vec<_>, _> = EventReader::new("<a><b></b>").collect();
 
-        assert_eq!(top.default_namespace_uri(), Some("namespace"));
-        assert_qname_eq!(("namespace", "hello"), top.name());
+        assert_eq!(r, Err(Error::new(10, ErrorKind::UnclosedElement)));
     }
 
     #[test]
-    fn an_element_with_an_attribute() {
-        let package = quick_parse("<hello scope='world'/>");
-        let doc = package.as_document();
-        let top = top(&doc);
+    fn events_report_duplicate_attributes() {
+        let r: Result<Vec<_>, _> = EventReader::new("<a b='c' b='d'/>").collect();
 
-        assert_eq!(top.attribute_value("scope"), Some("world"));
+        assert_eq!(r, Err(Error::new(9, ErrorKind::DuplicateAttribute)));
+    }
+
+    fn bytes_parse_failure(bytes: &[u8]) -> Error {
+        match parse_bytes(bytes) {
+            Ok(_) => panic!("Parsing should have failed"),
+            Err(e) => e,
+        }
+    }
+
+    fn utf_16(xml: &str, big_endian: bool) -> Vec<u8> {
+        xml.encode_utf16()
+            .flat_map(|u| {
+                let (high, low) = ((u >> 8) as u8, u as u8);
+                if big_endian {
+                    vec![high, low]
+                } else {
+                    vec![low, high]
+                }
+            })
+            .collect()
     }
 
     #[test]
-    fn an_element_with_an_attribute_using_double_quotes() {
-        let package = quick_parse("<hello scope=\"world\"/>");
+    fn bytes_in_utf_8_with_a_byte_order_mark() {
+        let package = parse_bytes(b"\xEF\xBB\xBF<caf\xC3\xA9/>").expect("Failed to parse");
         let doc = package.as_document();
-        let top = top(&doc);
 
-        assert_eq!(top.attribute_value("scope"), Some("world"));
+        assert_qname_eq!(top(&doc).name(), "caf\u{e9}");
     }
 
     #[test]
-    fn an_element_with_multiple_attributes() {
-        let package = quick_parse("<hello scope='world' happy='true'/>");
+    fn bytes_in_utf_16_with_a_byte_order_mark() {
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(utf_16("<caf\u{e9}>\u{1F600}</caf\u{e9}>", false));
+
+        let package = parse_bytes(&bytes).expect("Failed to parse");
         let doc = package.as_document();
         let top = top(&doc);
 
-        assert_eq!(top.attribute_value("scope"), Some("world"));
-        assert_eq!(top.attribute_value("happy"), Some("true"));
+        assert_qname_eq!(top.name(), "caf\u{e9}");
+        assert_eq!(top.children()[0].text().unwrap().text(), "\u{1F600}");
     }
 
     #[test]
-    fn an_attribute_with_a_namespace() {
-        let package = quick_parse("<hello ns:a='b' xmlns:ns='namespace'/>");
-        let doc = package.as_document();
-        let top = top(&doc);
+    fn bytes_in_utf_16_without_a_byte_order_mark() {
+        let bytes = utf_16("<?xml version='1.0' encoding='UTF-16'?><hello/>", true);
 
-        let attr = top.attribute(("namespace", "a")).unwrap();
+        let package = parse_bytes(&bytes).expect("Failed to parse");
+        let doc = package.as_document();
 
-        assert_eq!(attr.preferred_prefix(), Some("ns"));
-        assert_eq!(attr.value(), "b");
+        assert_qname_eq!(top(&doc).name(), "hello");
     }
 
     #[test]
-    fn an_attribute_with_xml_space_preserve() {
-        let package = quick_parse("<hello xml:space='preserve'> <a/> </hello>");
-        let doc = package.as_document();
-        let top = top(&doc);
+    fn bytes_in_iso_8859_1() {
+        let bytes = b"<?xml version='1.0' encoding='iso-8859-1'?><caf\xE9/>";
 
-        assert_eq!(
-            top.attribute((crate::XML_NS_URI, "space")).unwrap().value(),
-            "preserve"
-        );
+        let package = parse_bytes(bytes).expect("Failed to parse");
+        let doc = package.as_document();
 
-        let children = top.children();
-        assert_eq!(children.len(), 3);
-        assert_eq!(children[0].text().unwrap().text(), " ");
-        assert_qname_eq!(children[1].element().unwrap().name(), "a");
-        assert_eq!(children[2].text().unwrap().text(), " ");
+        assert_qname_eq!(top(&doc).name(), "caf\u{e9}");
     }
 
     #[test]
-    fn an_attribute_with_references() {
-        let package = quick_parse("<log msg='I &lt;3 math' />");
-        let doc = package.as_document();
-        let top = top(&doc);
+    fn bytes_declared_as_utf_16_but_in_utf_8() {
+        let err = bytes_parse_failure(b"<?xml version='1.0' encoding='UTF-16'?><a/>");
 
-        assert_eq!(top.attribute_value("msg"), Some("I <3 math"));
+        assert_eq!(err, Error::new(30, ErrorKind::MismatchedEncoding));
     }
 
     #[test]
-    fn an_element_that_is_not_self_closing() {
-        let package = quick_parse("<hello></hello>");
-        let doc = package.as_document();
-        let top = top(&doc);
+    fn bytes_with_a_utf_8_byte_order_mark_declared_as_iso_8859_1() {
+        let err =
+            bytes_parse_failure(b"\xEF\xBB\xBF<?xml version='1.0' encoding='ISO-8859-1'?><a/>");
 
-        assert_qname_eq!(top.name(), "hello");
+        assert_eq!(err, Error::new(30, ErrorKind::MismatchedEncoding));
     }
 
     #[test]
-    fn nested_elements() {
-        let package = quick_parse("<hello><world/></hello>");
-        let doc = package.as_document();
-        let hello = top(&doc);
-        let world = hello.children()[0].element().unwrap();
+    fn bytes_in_utf_16_without_a_byte_order_mark_or_declared_encoding() {
+        let err = bytes_parse_failure(&utf_16("<?xml version='1.0'?><a/>", false));
 
-        assert_qname_eq!(world.name(), "world");
+        assert_eq!(err, Error::new(0, ErrorKind::MismatchedEncoding));
     }
 
     #[test]
-    fn multiply_nested_elements() {
-        let package = quick_parse("<hello><awesome><world/></awesome></hello>");
-        let doc = package.as_document();
-        let hello = top(&doc);
-        let awesome = hello.children()[0].element().unwrap();
-        let world = awesome.children()[0].element().unwrap();
+    fn bytes_with_an_unsupported_encoding() {
+        let err = bytes_parse_failure(b"<?xml version='1.0' encoding='EBCDIC-US'?><a/>");
 
-        assert_qname_eq!(world.name(), "world");
+        assert_eq!(err, Error::new(30, ErrorKind::UnsupportedEncoding));
     }
 
     #[test]
-    fn nested_elements_with_namespaces() {
-        let package =
-            quick_parse("<ns1:hello xmlns:ns1='outer'><ns2:world xmlns:ns2='inner'/></ns1:hello>");
-        let doc = package.as_document();
-        let hello = top(&doc);
-        let world = hello.children()[0].element().unwrap();
+    fn bytes_that_are_invalid_for_the_encoding() {
+        let err = bytes_parse_failure(b"<?xml version='1.0' encoding='US-ASCII'?><a>\xE9</a>");
 
-        assert_qname_eq!(world.name(), ("inner", "world"));
+        assert_eq!(err, Error::new(44, ErrorKind::InvalidEncodedData));
     }
 
     #[test]
-    fn nested_elements_with_inherited_namespaces() {
-        let package = quick_parse("<ns1:hello xmlns:ns1='outer'><ns1:world/></ns1:hello>");
-        let doc = package.as_document();
-        let hello = top(&doc);
-        let world = hello.children()[0].element().unwrap();
+    fn bytes_without_a_declaration_must_be_utf_8() {
+        let err = bytes_parse_failure(b"<a>caf\xE9</a>");
 
-        assert_eq!(world.preferred_prefix(), Some("ns1"));
-        assert_qname_eq!(world.name(), ("outer", "world"));
+        assert_eq!(err, Error::new(6, ErrorKind::InvalidEncodedData));
     }
 
     #[test]
-    fn nested_elements_with_inherited_default_namespace() {
-        let package = quick_parse("<hello xmlns='outer'><world/></hello>");
-        let doc = package.as_document();
-        let hello = top(&doc);
-        let world = hello.children()[0].element().unwrap();
+    fn errors_report_the_line_and_column() {
+        let xml = "<a>\r\n  <caf\u{e9}>\n    <\u{e9}t\u{e9}></b>\n  </caf\u{e9}>\n</a>";
+        let err = full_parse(xml).unwrap_err();
 
-        assert_qname_eq!(world.name(), ("outer", "world"));
+        assert_eq!(err.line(), 3);
+        assert_eq!(err.column(), 12);
+        assert_eq!(err.line_text(), "    <\u{e9}t\u{e9}></b>");
     }
 
     #[test]
-    fn nested_elements_with_reset_default_namespace() {
-        let package = quick_parse("<hello xmlns='outer'><world xmlns=''/></hello>");
-        let doc = package.as_document();
-        let hello = top(&doc);
-        let world = hello.children()[0].element().unwrap();
+    fn errors_count_a_carriage_return_as_a_line_end() {
+        let err = full_parse("<a>\r\r</b>").unwrap_err();
 
-        assert_qname_eq!(world.name(), "world");
+        assert_eq!((err.line(), err.column()), (3, 3));
+        assert_eq!(err.line_text(), "</b>");
     }
 
     #[test]
-    fn nested_elements_with_attributes() {
-        let package = quick_parse("<hello><world name='Earth'/></hello>");
-        let doc = package.as_document();
-        let hello = top(&doc);
-        let world = hello.children()[0].element().unwrap();
+    fn errors_at_the_end_of_the_document() {
+        let err = full_parse("<a>\n  <b></b>").unwrap_err();
+
+        assert_eq!((err.line(), err.column()), (2, 10));
+        assert_eq!(err.line_text(), "  <b></b>");
+    }
+
+    #[test]
+    fn errors_expose_their_kind() {
+        let err = full_parse("<a>&bogus;</a>").unwrap_err();
 
-        assert_eq!(world.attribute_value("name"), Some("Earth"));
+        assert_eq!(err.kind(), ErrorKind::UnknownNamedReference);
     }
 
     #[test]
-    fn nested_elements_with_attributes_with_inherited_namespaces() {
-        let package = quick_parse("<hello xmlns:ns1='outer'><world ns1:name='Earth'/></hello>");
-        let doc = package.as_document();
-        let hello = top(&doc);
-        let world = hello.children()[0].element().unwrap();
+    fn errors_prefer_a_problem_over_an_expectation() {
+        let kinds = [ErrorKind::ExpectedElement, ErrorKind::UnknownNamedReference];
+        let err = Error::with_errors(0, kinds.iter().cloned().collect());
 
-        let attr = world.attribute(("outer", "name")).unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnknownNamedReference);
+    }
 
-        assert_eq!(attr.preferred_prefix(), Some("ns1"));
-        assert_eq!(attr.value(), "Earth");
+    #[test]
+    fn error_kinds_always_display() {
+        assert_eq!(ErrorKind::__Nonexhaustive.to_string(), "malformed XML");
+        assert_eq!(Limit::__Nonexhaustive.to_string(), "limit");
     }
 
     #[test]
-    fn element_with_text() {
-        let package = quick_parse("<hello>world</hello>");
-        let doc = package.as_document();
-        let hello = top(&doc);
-        let text = hello.children()[0].text().unwrap();
+    fn errors_expose_all_acceptable_alternatives() {
+        let err = full_parse("<a b='1' &>").unwrap_err();
+        let kinds: Vec<_> = err.kinds().collect();
 
-        assert_eq!(text.text(), "world");
+        assert_eq!(
+            kinds,
+            [
+                ErrorKind::ExpectedAttribute,
+                ErrorKind::ExpectedElementEnd,
+                ErrorKind::ExpectedElementSelfClosed,
+            ]
+        );
     }
 
     #[test]
-    fn element_with_cdata() {
-        let package = quick_parse("<words><![CDATA[I have & and < !]]></words>");
-        let doc = package.as_document();
-        let words = top(&doc);
-        let text = words.children()[0].text().unwrap();
+    fn errors_display_the_expected_alternatives() {
+        let err = full_parse("<a>\n<foo b='1' &>").unwrap_err();
 
-        assert_eq!(text.text(), "I have & and < !");
+        assert_eq!(
+            err.to_string(),
+            "XML parsing error at line 2, column 12: expected an attribute, '>' or '/>' \
+             to close element <foo> opened at line 2"
+        );
     }
 
     #[test]
-    fn element_with_comment() {
-        let package = quick_parse("<hello><!-- A comment --></hello>");
-        let doc = package.as_document();
-        let words = top(&doc);
-        let comment = words.children()[0].comment().unwrap();
+    fn errors_display_the_unclosed_element() {
+        let err = full_parse("<a>\n  <foo>\n</a>").unwrap_err();
 
-        assert_eq!(comment.text(), " A comment ");
+        assert_eq!(
+            err.to_string(),
+            "XML parsing error at line 3, column 3: expected '</foo>' \
+             to close element <foo> opened at line 2"
+        );
     }
 
     #[test]
-    fn comment_before_top_element() {
-        let package = quick_parse("<!-- A comment --><hello />");
-        let doc = package.as_document();
-        let comment = doc.root().children()[0].comment().unwrap();
+    fn errors_display_the_enclosing_element() {
+        let err = full_parse("<a>&bogus;</a>").unwrap_err();
 
-        assert_eq!(comment.text(), " A comment ");
+        assert_eq!(
+            err.to_string(),
+            "XML parsing error at line 1, column 5: unknown entity in element <a> opened at line 1"
+        );
     }
 
     #[test]
-    fn multiple_comments_before_top_element() {
-        let xml = r"
-<!--Comment 1-->
-<!--Comment 2-->
-<hello />";
-        let package = quick_parse(xml);
-        let doc = package.as_document();
-        let comment1 = doc.root().children()[0].comment().unwrap();
-        let comment2 = doc.root().children()[1].comment().unwrap();
+    fn events_report_the_line_and_column() {
+        let err = EventReader::new("<a>\n<b/>\n</c>")
+            .find(|e| e.is_err())
+            .unwrap()
+            .unwrap_err();
 
-        assert_eq!(comment1.text(), "Comment 1");
-        assert_eq!(comment2.text(), "Comment 2");
+        assert_eq!((err.line(), err.column()), (3, 3));
+        assert_eq!(err.line_text(), "</c>");
     }
 
     #[test]
-    fn multiple_comments_after_top_element() {
-        let xml = r"
-<hello />
-<!--Comment 1-->
-<!--Comment 2-->";
-        let package = quick_parse(xml);
-        let doc = package.as_document();
-        let comment1 = doc.root().children()[1].comment().unwrap();
-        let comment2 = doc.root().children()[2].comment().unwrap();
+    fn reader_errors_report_the_line_and_column() {
+        let xml = "<a>\n  <b>text</b>\n  <c></d>\n</a>";
 
-        assert_eq!(comment1.text(), "Comment 1");
-        assert_eq!(comment2.text(), "Comment 2");
+        let err = match parse_reader(one_byte_at_a_time(xml)) {
+            Err(ReadError::Parse(e)) => e,
+            r => panic!("Unexpected result: {:?}", r.map(|_| ())),
+        };
+
+        assert_eq!((err.line(), err.column()), (3, 8));
+        assert_eq!(err.line_text(), "  <c></d>");
     }
 
     #[test]
-    fn element_with_processing_instruction() {
-        let package = quick_parse("<hello><?device?></hello>");
-        let doc = package.as_document();
-        let hello = top(&doc);
-        let pi = hello.children()[0].processing_instruction().unwrap();
+    fn streaming_errors_report_the_line_and_column() {
+        let xml = format!("<a>{}\n  <c></d>\n</a>", "<b/>".repeat(10_000));
+        let mut reader = StreamingEventReader::new(xml.as_bytes());
+
+        let err = loop {
+            match reader.next_event() {
+                Some(Ok(_)) => {}
+                Some(Err(ReadError::Parse(e))) => break e,
+                r => panic!("Unexpected result: {:?}", r),
+            }
+        };
 
-        assert_eq!(pi.target(), "device");
-        assert_eq!(pi.value(), None);
+        assert_eq!((err.line(), err.column()), (2, 8));
+        assert_eq!(err.line_text(), "  <c></d>");
     }
 
     #[test]
-    fn top_level_processing_instructions() {
-        let xml = r"
-<?output printer?>
-<hello />
-<?validated?>";
+    fn reader_errors_on_long_lines_show_where_the_line_text_starts() {
+        let xml = format!("<a>{}</b>", "<c/>".repeat(5_000));
 
-        let package = quick_parse(xml);
-        let doc = package.as_document();
-        let pi1 = doc.root().children()[0].processing_instruction().unwrap();
-        let pi2 = doc.root().children()[2].processing_instruction().unwrap();
+        let err = match parse_reader(one_byte_at_a_time(&xml)) {
+            Err(ReadError::Parse(e)) => e,
+            r => panic!("Unexpected result: {:?}", r.map(|_| ())),
+        };
 
-        assert_eq!(pi1.target(), "output");
-        assert_eq!(pi1.value(), Some("printer"));
+        assert_eq!(err.column(), 20_006);
+        assert!(err.line_text_column() > 1);
+        let marked: String = err
+            .line_text()
+            .chars()
+            .skip(err.column() - err.line_text_column())
+            .collect();
+        assert!(marked.starts_with("b>"));
+    }
 
-        assert_eq!(pi2.target(), "validated");
-        assert_eq!(pi2.value(), None);
+    const STREAMED: &str = "<?xml version='1.0'?>\n\
+                            <!DOCTYPE a [<!ELEMENT a ANY><!ENTITY e 'e&#38;#38;e'>]>\n\
+                            <!-- before -->\n\
+                            <a xmlns:x='urn:x' b='1&amp;2&e;'>\
+                            caf\u{e9}\r\n&lt;&#x263a;&gt; &e; <x:b c='\t'/><![CDATA[<raw>]]><?pi v?>\
+                            </a>\n<!-- after -->\n";
+
+    fn one_byte_at_a_time(xml: &str) -> io::BufReader<&[u8]> {
+        io::BufReader::with_capacity(1, xml.as_bytes())
+    }
+
+    fn serialize(package: &Package) -> String {
+        let mut out = Vec::new();
+        crate::writer::format_document(&package.as_document(), &mut out).expect("Failed to write");
+        String::from_utf8(out).expect("Not UTF-8")
     }
 
     #[test]
-    fn element_with_decimal_char_reference() {
-        let package = quick_parse("<math>2 &#62; 1</math>");
-        let doc = package.as_document();
-        let math = top(&doc);
-        let text1 = math.children()[0].text().unwrap();
-        let text2 = math.children()[1].text().unwrap();
-        let text3 = math.children()[2].text().unwrap();
+    fn parsing_a_reader_matches_parsing_a_string() {
+        let expected = serialize(&quick_parse(STREAMED));
+        let package = parse_reader(one_byte_at_a_time(STREAMED)).expect("Failed to parse");
 
-        assert_eq!(text1.text(), "2 ");
-        assert_eq!(text2.text(), ">");
-        assert_eq!(text3.text(), " 1");
+        assert_eq!(serialize(&package), expected);
     }
 
     #[test]
-    fn element_with_hexidecimal_char_reference() {
-        let package = quick_parse("<math>1 &#x3c; 2</math>");
-        let doc = package.as_document();
-        let math = top(&doc);
-        let text1 = math.children()[0].text().unwrap();
-        let text2 = math.children()[1].text().unwrap();
-        let text3 = math.children()[2].text().unwrap();
+    fn parsing_a_reader_expands_declared_entities() {
+        let xml = "<!DOCTYPE a [<!ENTITY e '<b>&#169;</b>'>]><a>&e;</a>";
+        let package = parse_reader(one_byte_at_a_time(xml)).expect("Failed to parse");
 
-        assert_eq!(text1.text(), "1 ");
-        assert_eq!(text2.text(), "<");
-        assert_eq!(text3.text(), " 2");
+        assert_eq!(serialize(&package), serialize(&quick_parse(xml)));
     }
 
     #[test]
-    fn element_with_entity_reference() {
-        let package = quick_parse("<math>I &lt;3 math</math>");
-        let doc = package.as_document();
-        let math = top(&doc);
-        let text1 = math.children()[0].text().unwrap();
-        let text2 = math.children()[1].text().unwrap();
-        let text3 = math.children()[2].text().unwrap();
+    fn parsing_a_reader_reports_errors_at_document_offsets() {
+        let xml = "<a><b></c></a>";
 
-        assert_eq!(text1.text(), "I ");
-        assert_eq!(text2.text(), "<");
-        assert_eq!(text3.text(), "3 math");
+        match parse_reader(one_byte_at_a_time(xml)) {
+            Err(ReadError::Parse(e)) => assert_eq!(e, full_parse(xml).unwrap_err()),
+            r => panic!("Unexpected result: {:?}", r.map(|_| ())),
+        }
     }
 
     #[test]
-    fn element_with_mixed_children() {
-        let package = quick_parse("<hello>to <!--fixme--><a><![CDATA[the]]></a><?world?></hello>");
-        let doc = package.as_document();
-        let hello = top(&doc);
+    fn parsing_a_reader_reports_unclosed_elements() {
+        match parse_reader(one_byte_at_a_time("<a><b></b>")) {
+            Err(ReadError::Parse(e)) => {
+                assert_eq!(e, Error::new(10, ErrorKind::UnclosedElement))
+            }
+            r => panic!("Unexpected result: {:?}", r.map(|_| ())),
+        }
+    }
 
-        let text = hello.children()[0].text().unwrap();
-        let comment = hello.children()[1].comment().unwrap();
-        let element = hello.children()[2].element().unwrap();
-        let pi = hello.children()[3].processing_instruction().unwrap();
+    #[test]
+    fn parsing_a_reader_rejects_invalid_utf_8() {
+        match parse_reader(&b"<a>\xff</a>"[..]) {
+            Err(ReadError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            r => panic!("Unexpected result: {:?}", r.map(|_| ())),
+        }
+    }
 
-        assert_eq!(text.text(), "to ");
-        assert_eq!(comment.text(), "fixme");
-        assert_qname_eq!(element.name(), "a");
-        assert_eq!(pi.target(), "world");
+    #[test]
+    fn streaming_events_match_string_events() {
+        let expected = events(STREAMED);
+
+        let mut reader = StreamingEventReader::new(one_byte_at_a_time(STREAMED));
+        let mut actual = Vec::new();
+        while let Some(event) = reader.next_event() {
+            actual.push(format!("{:?}", event.expect("Failed to read events")));
+        }
+
+        let expected: Vec<_> = expected.iter().map(|e| format!("{:?}", e)).collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn streaming_events_stop_after_an_error() {
+        let mut reader = StreamingEventReader::new(one_byte_at_a_time("<a></b>"));
+
+        assert!(reader.next_event().unwrap().is_ok());
+        match reader.next_event() {
+            Some(Err(ReadError::Parse(e))) => assert_eq!(e.location(), 5),
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert!(reader.next_event().is_none());
     }
 
     // TODO: untested errors
@@ -1911,7 +7303,7 @@ mod test {
 
     #[test]
     fn failure_invalid_encoding() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<?xml version='1.0' encoding='8BIT' ?><hi/>");
 
@@ -1920,7 +7312,7 @@ mod test {
 
     #[test]
     fn failure_invalid_standalone() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<?xml version='1.0' standalone='invalid'?><hello/>");
 
@@ -1929,7 +7321,7 @@ mod test {
 
     #[test]
     fn failure_no_open_brace() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("hi />");
 
@@ -1937,6 +7329,7 @@ mod test {
             r,
             0,
             Expected("<?xml"),
+            Expected("<!DOCTYPE"),
             ExpectedComment,
             ExpectedProcessingInstruction,
             ExpectedWhitespace,
@@ -1946,7 +7339,7 @@ mod test {
 
     #[test]
     fn failure_unclosed_tag() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<hi");
 
@@ -1961,7 +7354,7 @@ mod test {
 
     #[test]
     fn failure_unexpected_space() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<hi / >");
 
@@ -1976,7 +7369,7 @@ mod test {
 
     #[test]
     fn failure_attribute_without_open_quote() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<hi oops=value' />");
 
@@ -1985,7 +7378,7 @@ mod test {
 
     #[test]
     fn failure_attribute_without_close_quote() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<hi oops='value />");
 
@@ -2002,7 +7395,7 @@ mod test {
 
     #[test]
     fn failure_unclosed_attribute_and_tag() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<hi oops='value");
 
@@ -2019,7 +7412,7 @@ mod test {
 
     #[test]
     fn failure_nested_unclosed_tag() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<hi><oops</hi>");
 
@@ -2034,7 +7427,7 @@ mod test {
 
     #[test]
     fn failure_missing_close_tag() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<hi>wow");
 
@@ -2043,7 +7436,7 @@ mod test {
 
     #[test]
     fn failure_nested_unexpected_space() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<hi><oops / ></hi>");
 
@@ -2058,7 +7451,7 @@ mod test {
 
     #[test]
     fn failure_malformed_entity_reference() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<hi>Entity: &;</hi>");
 
@@ -2067,7 +7460,7 @@ mod test {
 
     #[test]
     fn failure_nested_malformed_entity_reference() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<hi><bye>Entity: &;</bye></hi>");
 
@@ -2076,7 +7469,7 @@ mod test {
 
     #[test]
     fn failure_nested_attribute_without_open_quote() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<hi><bye oops=value' /></hi>");
 
@@ -2090,7 +7483,7 @@ mod test {
 
     #[test]
     fn failure_nested_attribute_without_close_quote() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<hi><bye oops='value /></hi>");
 
@@ -2107,7 +7500,7 @@ mod test {
 
     #[test]
     fn failure_nested_unclosed_attribute_and_tag() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<hi><bye oops='value</hi>");
 
@@ -2124,7 +7517,7 @@ mod test {
 
     #[test]
     fn failure_pi_target_as_xml() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<a><?xml?></a>");
 
@@ -2133,7 +7526,7 @@ mod test {
 
     #[test]
     fn failure_end_tag_does_not_match() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<a></b>");
 
@@ -2142,7 +7535,7 @@ mod test {
 
     #[test]
     fn failure_invalid_decimal_reference() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<a>&#99999999;</a>");
 
@@ -2151,25 +7544,155 @@ mod test {
 
     #[test]
     fn failure_invalid_hex_reference() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<a>&#x99999999;</a>");
 
         assert_parse_failure!(r, 6, InvalidHexReference);
     }
 
+    #[test]
+    fn failure_reference_to_nul() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<a>&#0;</a>");
+
+        assert_parse_failure!(r, 5, InvalidCharacter);
+    }
+
+    #[test]
+    fn failure_reference_to_restricted_character_in_xml_1_0() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<a>&#x1;</a>");
+
+        assert_parse_failure!(r, 3, InvalidCharacter);
+    }
+
+    #[test]
+    fn failure_control_character_in_text() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<a>hello\u{1}</a>");
+
+        assert_parse_failure!(r, 8, InvalidCharacter);
+    }
+
+    #[test]
+    fn failure_control_character_in_attribute() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<a b='\u{8}'/>");
+
+        assert_parse_failure!(r, 6, InvalidCharacter);
+    }
+
+    #[test]
+    fn failure_control_character_in_comment() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<a><!--x\u{1F}--></a>");
+
+        assert_parse_failure!(r, 8, InvalidCharacter);
+    }
+
+    #[test]
+    fn failure_literal_restricted_character_in_xml_1_1() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<?xml version='1.1'?><a>\u{7F}</a>");
+
+        assert_parse_failure!(r, 24, InvalidCharacter);
+    }
+
     #[test]
     fn failure_unknown_named_reference() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<a>&fake;</a>");
 
         assert_parse_failure!(r, 4, UnknownNamedReference);
     }
 
+    #[test]
+    fn failure_recursive_entity_reference() {
+        use super::ErrorKind::*;
+
+        let xml = "<!DOCTYPE a [<!ENTITY a '&b;'><!ENTITY b '<b>&a;</b>'>]><a>&a;</a>";
+        let r = full_parse(xml);
+
+        assert_parse_failure!(r, 60, RecursiveEntityReference);
+    }
+
+    #[test]
+    fn failure_recursive_entity_reference_in_attribute() {
+        use super::ErrorKind::*;
+
+        let xml = "<!DOCTYPE a [<!ENTITY a 'x&a;'>]><a v='&a;'/>";
+        let r = full_parse(xml);
+
+        assert_parse_failure!(r, 40, RecursiveEntityReference);
+    }
+
+    #[test]
+    fn failure_entity_with_unclosed_element() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<!DOCTYPE a [<!ENTITY open '<b>'>]><a>&open;</b></a>");
+
+        assert_parse_failure!(r, 39, UnclosedElement);
+    }
+
+    #[test]
+    fn failure_entity_closing_an_element_it_did_not_open() {
+        let r = full_parse("<!DOCTYPE a [<!ENTITY close '</b>'>]><a><b>&close;</a>");
+
+        assert_eq!(r.unwrap_err().location(), 44);
+    }
+
+    #[test]
+    fn failure_less_than_in_attribute_entity() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<!DOCTYPE a [<!ENTITY b '<b/>'>]><a v='&b;'/>");
+
+        assert_parse_failure!(r, 40, LessThanInAttributeValue);
+    }
+
+    #[test]
+    fn failure_external_entity_reference() {
+        use super::ErrorKind::*;
+
+        let xml = "<!DOCTYPE a [<!ENTITY e SYSTEM 'e.xml'>]>";
+
+        let r = full_parse(&format!("{}<a>&e;</a>", xml));
+        assert_parse_failure!(r, 45, UnresolvedExternalEntity);
+
+        let r = full_parse(&format!("{}<a v='&e;'/>", xml));
+        assert_parse_failure!(r, 48, ExternalEntityInAttributeValue);
+    }
+
+    #[test]
+    fn failure_unparsed_entity_reference() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<!DOCTYPE a [<!ENTITY e SYSTEM 'e.png' NDATA png>]><a>&e;</a>");
+
+        assert_parse_failure!(r, 55, UnparsedEntityReference);
+    }
+
+    #[test]
+    fn failure_malformed_entity_declaration() {
+        let r = full_parse("<!DOCTYPE a [\n<!ENTITY a 'x' junk>]><a/>");
+        let e = r.unwrap_err();
+
+        assert_eq!(e.location(), 29);
+        assert_eq!(e.line(), 2);
+    }
+
     #[test]
     fn failure_duplicate_attribute() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<a b='c' b='d'/>");
 
@@ -2178,7 +7701,7 @@ mod test {
 
     #[test]
     fn failure_redefined_namespace() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<a xmlns:b='c' xmlns:b='d'/>");
 
@@ -2187,7 +7710,7 @@ mod test {
 
     #[test]
     fn failure_redefined_default_namespace() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<a xmlns='a' xmlns='b'/>");
 
@@ -2196,16 +7719,79 @@ mod test {
 
     #[test]
     fn failure_empty_namespace() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<a xmlns:b=''/>");
 
         assert_parse_failure!(r, 3, EmptyNamespace);
     }
 
+    #[test]
+    fn failure_duplicate_attribute_by_expanded_name() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<a xmlns:b='u' xmlns:c='u' b:x='1' c:x='2'/>");
+
+        assert_parse_failure!(r, 35, DuplicateExpandedAttribute);
+    }
+
+    #[test]
+    fn failure_xml_prefix_bound_to_another_namespace() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<a xmlns:xml='urn:x'/>");
+
+        assert_parse_failure!(r, 3, RedefinedXmlPrefix);
+    }
+
+    #[test]
+    fn failure_xmlns_prefix_declared() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<a xmlns:xmlns='http://www.w3.org/2000/xmlns/'/>");
+
+        assert_parse_failure!(r, 3, DeclaredXmlnsPrefix);
+    }
+
+    #[test]
+    fn failure_prefix_bound_to_the_xml_namespace() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<a xmlns:x='http://www.w3.org/XML/1998/namespace'/>");
+
+        assert_parse_failure!(r, 3, ReservedNamespace);
+    }
+
+    #[test]
+    fn failure_prefix_bound_to_the_xmlns_namespace() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<a xmlns:x='http://www.w3.org/2000/xmlns/'/>");
+
+        assert_parse_failure!(r, 3, ReservedNamespace);
+    }
+
+    #[test]
+    fn failure_default_namespace_is_the_xmlns_namespace() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<a xmlns='http://www.w3.org/2000/xmlns/'/>");
+
+        assert_parse_failure!(r, 3, ReservedNamespace);
+    }
+
+    #[test]
+    fn failure_undeclared_prefix_in_xml_1_1() {
+        use super::ErrorKind::*;
+
+        let r = full_parse("<?xml version='1.1'?><a xmlns:p='urn:p'><b xmlns:p=''><p:c/></b></a>");
+
+        assert_parse_failure!(r, 55, UnknownNamespacePrefix);
+    }
+
     #[test]
     fn failure_unknown_attribute_namespace_prefix() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<a b:foo='a'/>");
 
@@ -2214,7 +7800,7 @@ mod test {
 
     #[test]
     fn failure_unknown_element_namespace_prefix() {
-        use super::SpecificError::*;
+        use super::ErrorKind::*;
 
         let r = full_parse("<b:a/>");
 
@@ -1,4 +1,4 @@
-use super::{lazy_hash_map::LazyHashMap, QName};
+use super::{dom::SourceSpan, lazy_hash_map::LazyHashMap, QName};
 
 use crate::string_pool::{InternedString, StringPool};
 use std::{marker::PhantomData, slice};
@@ -20,6 +20,21 @@ impl InternedQName {
 
 pub struct Root {
     children: Vec<ChildOfRoot>,
+    xml_version: InternedString,
+    xml_encoding: Option<InternedString>,
+    xml_standalone: Option<bool>,
+}
+
+impl Root {
+    pub fn xml_version(&self) -> &str {
+        self.xml_version.as_slice()
+    }
+    pub fn xml_encoding(&self) -> Option<&str> {
+        self.xml_encoding.map(|e| e.as_slice())
+    }
+    pub fn xml_standalone(&self) -> Option<bool> {
+        self.xml_standalone
+    }
 }
 
 pub struct Element {
@@ -30,6 +45,7 @@ pub struct Element {
     parent: Option<ParentOfChild>,
     attributes: Vec<*mut Attribute>,
     prefix_to_namespace: LazyHashMap<InternedString, InternedString>,
+    source_span: Option<SourceSpan>,
 }
 
 impl Element {
@@ -42,12 +58,17 @@ impl Element {
     pub fn preferred_prefix(&self) -> Option<&str> {
         self.preferred_prefix.map(|p| p.as_slice())
     }
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        self.source_span
+    }
 }
 
 pub struct Attribute {
     name: InternedQName,
     preferred_prefix: Option<InternedString>,
     value: InternedString,
+    specified: bool,
+    source_span: Option<SourceSpan>,
     parent: Option<*mut Element>,
 }
 
@@ -61,10 +82,18 @@ impl Attribute {
     pub fn preferred_prefix(&self) -> Option<&str> {
         self.preferred_prefix.map(|p| p.as_slice())
     }
+    pub fn is_specified(&self) -> bool {
+        self.specified
+    }
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        self.source_span
+    }
 }
 
 pub struct Text {
     text: InternedString,
+    cdata: bool,
+    source_span: Option<SourceSpan>,
     parent: Option<*mut Element>,
 }
 
@@ -72,10 +101,17 @@ impl Text {
     pub fn text(&self) -> &str {
         &self.text
     }
+    pub fn is_cdata(&self) -> bool {
+        self.cdata
+    }
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        self.source_span
+    }
 }
 
 pub struct Comment {
     text: InternedString,
+    source_span: Option<SourceSpan>,
     parent: Option<ParentOfChild>,
 }
 
@@ -83,11 +119,15 @@ impl Comment {
     pub fn text(&self) -> &str {
         &self.text
     }
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        self.source_span
+    }
 }
 
 pub struct ProcessingInstruction {
     target: InternedString,
     value: Option<InternedString>,
+    source_span: Option<SourceSpan>,
     parent: Option<ParentOfChild>,
 }
 
@@ -98,11 +138,38 @@ impl ProcessingInstruction {
     pub fn value(&self) -> Option<&str> {
         self.value.map(|v| v.as_slice())
     }
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        self.source_span
+    }
+}
+
+pub struct DocumentType {
+    name: InternedString,
+    public_id: Option<InternedString>,
+    system_id: Option<InternedString>,
+    internal_subset: Option<InternedString>,
+    parent: Option<*mut Root>,
+}
+
+impl DocumentType {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn public_id(&self) -> Option<&str> {
+        self.public_id.map(|v| v.as_slice())
+    }
+    pub fn system_id(&self) -> Option<&str> {
+        self.system_id.map(|v| v.as_slice())
+    }
+    pub fn internal_subset(&self) -> Option<&str> {
+        self.internal_subset.map(|v| v.as_slice())
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ChildOfRoot {
     Element(*mut Element),
+    DocumentType(*mut DocumentType),
     Comment(*mut Comment),
     ProcessingInstruction(*mut ProcessingInstruction),
 }
@@ -115,6 +182,24 @@ impl ChildOfRoot {
         }
     }
 
+    fn is_document_type(&self) -> bool {
+        match *self {
+            ChildOfRoot::DocumentType(_) => true,
+            _ => false,
+        }
+    }
+
+    /// The same node as a child of an element. A document type can
+    /// only be the child of the root.
+    fn as_child_of_element(&self) -> Option<ChildOfElement> {
+        match *self {
+            ChildOfRoot::Element(n) => Some(ChildOfElement::Element(n)),
+            ChildOfRoot::DocumentType(_) => None,
+            ChildOfRoot::Comment(n) => Some(ChildOfElement::Comment(n)),
+            ChildOfRoot::ProcessingInstruction(n) => Some(ChildOfElement::ProcessingInstruction(n)),
+        }
+    }
+
     fn replace_parent(&self, parent: *mut Root) {
         match *self {
             ChildOfRoot::Element(n) => {
@@ -123,6 +208,21 @@ impl ChildOfRoot {
                 parent_r.children.retain(|c| !c.is_element());
                 replace_parent(*self, ParentOfChild::Root(parent), &mut n.parent);
             }
+            ChildOfRoot::DocumentType(n) => {
+                let parent_r = unsafe { &mut *parent };
+                let n = unsafe { &mut *n };
+                if let Some(prev_parent) = n.parent {
+                    let prev_parent_r = unsafe { &mut *prev_parent };
+                    prev_parent_r.children.retain(|c| c != self);
+                }
+                for c in &parent_r.children {
+                    if c.is_document_type() {
+                        c.remove_parent();
+                    }
+                }
+                parent_r.children.retain(|c| !c.is_document_type());
+                n.parent = Some(parent);
+            }
             ChildOfRoot::Comment(n) => {
                 let n = unsafe { &mut *n };
                 replace_parent(*self, ParentOfChild::Root(parent), &mut n.parent);
@@ -140,6 +240,10 @@ impl ChildOfRoot {
                 let n = unsafe { &mut *n };
                 n.parent = None;
             }
+            ChildOfRoot::DocumentType(n) => {
+                let n = unsafe { &mut *n };
+                n.parent = None;
+            }
             ChildOfRoot::Comment(n) => {
                 let n = unsafe { &mut *n };
                 n.parent = None;
@@ -173,8 +277,8 @@ fn replace_parent(
             }
             ParentOfChild::Element(e) => {
                 let e_r = unsafe { &mut *e };
-                let as_element_child = child.into();
-                e_r.children.retain(|n| *n != as_element_child);
+                let as_element_child = child.as_child_of_element();
+                e_r.children.retain(|n| Some(*n) != as_element_child);
             }
         }
     }
@@ -274,24 +378,16 @@ conversion_trait!(
 conversion_trait!(
     ChildOfRoot, {
         Element               => ChildOfRoot::Element,
+        DocumentType          => ChildOfRoot::DocumentType,
         Comment               => ChildOfRoot::Comment,
         ProcessingInstruction => ChildOfRoot::ProcessingInstruction
     }
 );
 
-impl From<ChildOfRoot> for ChildOfElement {
-    fn from(v: ChildOfRoot) -> ChildOfElement {
-        match v {
-            ChildOfRoot::Element(n) => ChildOfElement::Element(n),
-            ChildOfRoot::Comment(n) => ChildOfElement::Comment(n),
-            ChildOfRoot::ProcessingInstruction(n) => ChildOfElement::ProcessingInstruction(n),
-        }
-    }
-}
-
 pub struct Storage {
     strings: StringPool,
     roots: Arena<Root>,
+    document_types: Arena<DocumentType>,
     elements: Arena<Element>,
     attributes: Arena<Attribute>,
     texts: Arena<Text>,
@@ -304,6 +400,7 @@ impl Default for Storage {
         Storage {
             strings: StringPool::new(),
             roots: Arena::new(),
+            document_types: Arena::new(),
             elements: Arena::new(),
             attributes: Arena::new(),
             texts: Arena::new(),
@@ -323,6 +420,11 @@ impl Storage {
         InternedString::from_str(interned)
     }
 
+    /// Copies the string into storage that lives as long as the document
+    pub fn intern_str(&self, s: &str) -> &str {
+        self.strings.intern(s)
+    }
+
     fn intern_qname(&self, q: QName<'_>) -> InternedQName {
         InternedQName {
             namespace_uri: q.namespace_uri.map(|p| self.intern(p)),
@@ -333,6 +435,28 @@ impl Storage {
     pub fn create_root(&self) -> *mut Root {
         self.roots.alloc(Root {
             children: Vec::new(),
+            xml_version: InternedString::from_str("1.0"),
+            xml_encoding: None,
+            xml_standalone: None,
+        })
+    }
+
+    pub fn create_document_type(
+        &self,
+        name: &str,
+        public_id: Option<&str>,
+        system_id: Option<&str>,
+    ) -> *mut DocumentType {
+        let name = self.intern(name);
+        let public_id = public_id.map(|v| self.intern(v));
+        let system_id = system_id.map(|v| self.intern(v));
+
+        self.document_types.alloc(DocumentType {
+            name,
+            public_id,
+            system_id,
+            internal_subset: None,
+            parent: None,
         })
     }
 
@@ -351,6 +475,7 @@ impl Storage {
             parent: None,
             attributes: Vec::new(),
             prefix_to_namespace: LazyHashMap::new(),
+            source_span: None,
         })
     }
 
@@ -366,6 +491,8 @@ impl Storage {
             name,
             preferred_prefix: None,
             value,
+            specified: true,
+            source_span: None,
             parent: None,
         })
     }
@@ -373,13 +500,22 @@ impl Storage {
     pub fn create_text(&self, text: &str) -> *mut Text {
         let text = self.intern(text);
 
-        self.texts.alloc(Text { text, parent: None })
+        self.texts.alloc(Text {
+            text,
+            cdata: false,
+            source_span: None,
+            parent: None,
+        })
     }
 
     pub fn create_comment(&self, text: &str) -> *mut Comment {
         let text = self.intern(text);
 
-        self.comments.alloc(Comment { text, parent: None })
+        self.comments.alloc(Comment {
+            text,
+            source_span: None,
+            parent: None,
+        })
     }
 
     pub fn create_processing_instruction(
@@ -393,6 +529,7 @@ impl Storage {
         self.processing_instructions.alloc(ProcessingInstruction {
             target,
             value,
+            source_span: None,
             parent: None,
         })
     }
@@ -429,6 +566,59 @@ impl Storage {
         element_r.default_namespace_uri = namespace_uri;
     }
 
+    pub fn root_set_xml_version(&self, root: *mut Root, version: &str) {
+        let version = self.intern(version);
+        let root_r = unsafe { &mut *root };
+        root_r.xml_version = version;
+    }
+
+    pub fn root_set_xml_encoding(&self, root: *mut Root, encoding: Option<&str>) {
+        let encoding = encoding.map(|e| self.intern(e));
+        let root_r = unsafe { &mut *root };
+        root_r.xml_encoding = encoding;
+    }
+
+    pub fn root_set_xml_standalone(&self, root: *mut Root, standalone: Option<bool>) {
+        let root_r = unsafe { &mut *root };
+        root_r.xml_standalone = standalone;
+    }
+
+    pub fn document_type_set_name(&self, document_type: *mut DocumentType, name: &str) {
+        let name = self.intern(name);
+        let document_type_r = unsafe { &mut *document_type };
+        document_type_r.name = name;
+    }
+
+    pub fn document_type_set_public_id(
+        &self,
+        document_type: *mut DocumentType,
+        public_id: Option<&str>,
+    ) {
+        let public_id = public_id.map(|v| self.intern(v));
+        let document_type_r = unsafe { &mut *document_type };
+        document_type_r.public_id = public_id;
+    }
+
+    pub fn document_type_set_system_id(
+        &self,
+        document_type: *mut DocumentType,
+        system_id: Option<&str>,
+    ) {
+        let system_id = system_id.map(|v| self.intern(v));
+        let document_type_r = unsafe { &mut *document_type };
+        document_type_r.system_id = system_id;
+    }
+
+    pub fn document_type_set_internal_subset(
+        &self,
+        document_type: *mut DocumentType,
+        internal_subset: Option<&str>,
+    ) {
+        let internal_subset = internal_subset.map(|v| self.intern(v));
+        let document_type_r = unsafe { &mut *document_type };
+        document_type_r.internal_subset = internal_subset;
+    }
+
     pub fn element_set_preferred_prefix(&self, element: *mut Element, prefix: Option<&str>) {
         let prefix = prefix.map(|p| self.intern(p));
         let element_r = unsafe { &mut *element };
@@ -441,12 +631,22 @@ impl Storage {
         attribute_r.preferred_prefix = prefix;
     }
 
+    pub fn attribute_set_specified(&self, attribute: *mut Attribute, specified: bool) {
+        let attribute_r = unsafe { &mut *attribute };
+        attribute_r.specified = specified;
+    }
+
     pub fn text_set_text(&self, text: *mut Text, new_text: &str) {
         let new_text = self.intern(new_text);
         let text_r = unsafe { &mut *text };
         text_r.text = new_text;
     }
 
+    pub fn text_set_cdata(&self, text: *mut Text, cdata: bool) {
+        let text_r = unsafe { &mut *text };
+        text_r.cdata = cdata;
+    }
+
     pub fn comment_set_text(&self, comment: *mut Comment, new_text: &str) {
         let new_text = self.intern(new_text);
         let comment_r = unsafe { &mut *comment };
@@ -472,6 +672,35 @@ impl Storage {
         let pi_r = unsafe { &mut *pi };
         pi_r.value = new_value;
     }
+
+    pub fn element_set_source_span(&self, element: *mut Element, span: Option<SourceSpan>) {
+        let element_r = unsafe { &mut *element };
+        element_r.source_span = span;
+    }
+
+    pub fn attribute_set_source_span(&self, attribute: *mut Attribute, span: Option<SourceSpan>) {
+        let attribute_r = unsafe { &mut *attribute };
+        attribute_r.source_span = span;
+    }
+
+    pub fn text_set_source_span(&self, text: *mut Text, span: Option<SourceSpan>) {
+        let text_r = unsafe { &mut *text };
+        text_r.source_span = span;
+    }
+
+    pub fn comment_set_source_span(&self, comment: *mut Comment, span: Option<SourceSpan>) {
+        let comment_r = unsafe { &mut *comment };
+        comment_r.source_span = span;
+    }
+
+    pub fn processing_instruction_set_source_span(
+        &self,
+        pi: *mut ProcessingInstruction,
+        span: Option<SourceSpan>,
+    ) {
+        let pi_r = unsafe { &mut *pi };
+        pi_r.source_span = span;
+    }
 }
 
 pub struct Connections {
@@ -492,6 +721,11 @@ impl Connections {
         child_r.parent
     }
 
+    pub fn document_type_parent(&self, child: *mut DocumentType) -> Option<*mut Root> {
+        let child_r = unsafe { &*child };
+        child_r.parent
+    }
+
     pub fn text_parent(&self, child: *mut Text) -> Option<*mut Element> {
         let child_r = unsafe { &*child };
         child_r.parent
@@ -518,7 +752,13 @@ impl Connections {
         let parent_r = unsafe { &mut *self.root };
 
         child.replace_parent(self.root);
-        parent_r.children.push(child);
+
+        // The document type must come before the document element
+        let element_position = parent_r.children.iter().position(|c| c.is_element());
+        match element_position {
+            Some(position) if child.is_document_type() => parent_r.children.insert(position, child),
+            _ => parent_r.children.push(child),
+        }
     }
 
     pub fn append_element_child<C>(&self, parent: *mut Element, child: C)
@@ -577,6 +817,13 @@ impl Connections {
         }
     }
 
+    pub fn remove_document_type_from_parent(&self, child: *mut DocumentType) {
+        let child_r = unsafe { &mut *child };
+        if child_r.parent.is_some() {
+            self.remove_root_child(child);
+        }
+    }
+
     pub fn remove_attribute_from_parent(&self, child: *mut Attribute) {
         let child_r = unsafe { &mut *child };
         if let Some(parent) = child_r.parent {
@@ -850,6 +1097,7 @@ impl Connections {
             .filter_map(|e| e.prefix_to_namespace.get(prefix))
             .next()
             .map(|s| s.as_slice())
+            .filter(|ns_uri| !ns_uri.is_empty())
     }
 
     pub fn element_prefix_for_namespace_uri(
@@ -900,6 +1148,9 @@ impl Connections {
             }
         }
 
+        // An empty URI undeclares the prefix
+        namespaces.retain(|ns| !ns.1.is_empty());
+
         NamespacesInScope {
             iter: namespaces.into_iter(),
         }
@@ -1015,7 +1266,9 @@ impl<'d> Iterator for SiblingIter<'d> {
 
     fn next(&mut self) -> Option<ChildOfElement> {
         match self.data {
-            SiblingData::FromRoot(ref mut children) => children.next().map(|&sib| sib.into()),
+            SiblingData::FromRoot(ref mut children) => {
+                children.find_map(|sib| sib.as_child_of_element())
+            }
             SiblingData::FromElement(ref mut children) => children.next().cloned(),
             SiblingData::Dead => None,
         }
@@ -0,0 +1,631 @@
+//! Loading of the external resources a document refers to, such as
+//! an external DTD subset or an external parsed entity.
+//!
+//! Loading whatever a document asks for is a security risk, so the
+//! parser refuses by default. A `Resolver` decides which resources are
+//! loaded and where they come from.
+//!
+//! ### Example
+//!
+//! ```no_run
+//! use sxd_document::{parser::Parser, resolver::FileResolver};
+//!
+//! let xml = r#"<!DOCTYPE book SYSTEM "book.dtd"><book>&chapter;</book>"#;
+//! let package = Parser::new()
+//!     .set_resolver(FileResolver::new("/srv/documents"))
+//!     .parse(xml)
+//!     .expect("Failed to parse");
+//! ```
+
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use super::{dom, parser};
+
+/// Provides the content of external resources to the parser
+pub trait Resolver {
+    /// Loads the resource with the given identifiers, as written in
+    /// the document. The bytes are decoded like a document: a byte
+    /// order mark or the encoding given in a text declaration is
+    /// used.
+    ///
+    /// To refuse to load a resource, return an error of kind
+    /// `io::ErrorKind::PermissionDenied`. The parser carries on
+    /// without a refused external DTD subset, but a reference to a
+    /// refused entity is an error. Every other error is reported.
+    fn resolve(&self, public_id: Option<&str>, system_id: &str) -> io::Result<Vec<u8>>;
+}
+
+/// Refuses to load anything. This is the default for the parser.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DenyAll;
+
+impl Resolver for DenyAll {
+    fn resolve(&self, _public_id: Option<&str>, _system_id: &str) -> io::Result<Vec<u8>> {
+        Err(refused())
+    }
+}
+
+fn refused() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "external resources are not loaded",
+    )
+}
+
+/// Loads files from a base directory and the directories below it.
+/// System identifiers are paths relative to the base directory, or
+/// `file:` URIs. Anything else, including files reached through a
+/// symbolic link that leads out of the base directory, is refused.
+#[derive(Debug, Clone)]
+pub struct FileResolver {
+    base: PathBuf,
+}
+
+impl FileResolver {
+    pub fn new<P>(base: P) -> FileResolver
+    where
+        P: Into<PathBuf>,
+    {
+        FileResolver { base: base.into() }
+    }
+
+    /// The file a system identifier refers to
+    fn path(&self, system_id: &str) -> io::Result<PathBuf> {
+        let path = match file_uri_path(system_id) {
+            Some(path) => path,
+            None if has_scheme(system_id) => return Err(refused()),
+            None => system_id,
+        };
+
+        // Absolute paths replace the base, and are checked like the rest
+        let base = self.base.canonicalize()?;
+        let path = base.join(path).canonicalize()?;
+
+        if path.starts_with(&base) {
+            Ok(path)
+        } else {
+            Err(refused())
+        }
+    }
+}
+
+impl Resolver for FileResolver {
+    fn resolve(&self, _public_id: Option<&str>, system_id: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(system_id)?)
+    }
+}
+
+static CATALOG_NS_URI: &str = "urn:oasis:names:tc:entity:xmlns:xml:catalog";
+
+#[derive(Debug, Clone)]
+enum CatalogEntry {
+    Public {
+        public_id: String,
+        path: PathBuf,
+        prefer_public: bool,
+    },
+    System {
+        system_id: String,
+        path: PathBuf,
+    },
+    RewriteSystem {
+        start: String,
+        prefix: PathBuf,
+    },
+    SystemSuffix {
+        suffix: String,
+        path: PathBuf,
+    },
+}
+
+/// Loads the files that an [OASIS XML Catalog][catalog] maps public
+/// and system identifiers to. Identifiers that the catalog does not
+/// map are refused, so nothing is fetched from the network.
+///
+/// The `public`, `system`, `rewriteSystem` and `systemSuffix` entries
+/// are used, along with `group` elements and the `prefer` and
+/// `xml:base` attributes. Other entries, such as `nextCatalog` and
+/// the delegate entries, are ignored. A `rewriteSystem` entry does not
+/// rewrite identifiers whose remainder is absolute or contains `..`,
+/// so that a document cannot reach files outside of the rewrite prefix.
+///
+/// [catalog]: https://www.oasis-open.org/committees/download.php/14809/xml-catalogs.html
+///
+/// ### Example
+///
+/// ```
+/// use sxd_document::resolver::CatalogResolver;
+/// use std::path::Path;
+///
+/// let catalog = r#"
+///   <catalog xmlns="urn:oasis:names:tc:entity:xmlns:xml:catalog">
+///     <public publicId="-//OASIS//DTD DocBook XML V4.5//EN" uri="docbook/docbookx.dtd"/>
+///     <rewriteSystem systemIdStartString="http://example.com/dtd/" rewritePrefix="dtd/"/>
+///   </catalog>"#;
+/// let resolver = CatalogResolver::parse(catalog, Path::new("/etc/xml")).unwrap();
+///
+/// assert_eq!(
+///     resolver.local_path(Some("-//OASIS//DTD DocBook XML V4.5//EN"), "docbookx.dtd"),
+///     Some(Path::new("/etc/xml/docbook/docbookx.dtd").to_path_buf())
+/// );
+/// assert_eq!(
+///     resolver.local_path(None, "http://example.com/dtd/a.dtd"),
+///     Some(Path::new("/etc/xml/dtd/a.dtd").to_path_buf())
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct CatalogResolver {
+    entries: Vec<CatalogEntry>,
+}
+
+impl CatalogResolver {
+    /// Reads a catalog file. Relative URIs in the catalog are resolved
+    /// against the directory that contains it.
+    pub fn from_file<P>(path: P) -> Result<CatalogResolver, parser::ReadError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let bytes = fs::read(path)?;
+        let package = parser::parse_bytes(&bytes)?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+        Ok(CatalogResolver::from_document(&package.as_document(), base))
+    }
+
+    /// Reads a catalog from a string. Relative URIs in the catalog are
+    /// resolved against `base`.
+    pub fn parse(xml: &str, base: &Path) -> Result<CatalogResolver, parser::Error> {
+        let package = parser::parse(xml)?;
+        Ok(CatalogResolver::from_document(&package.as_document(), base))
+    }
+
+    fn from_document(doc: &dom::Document<'_>, base: &Path) -> CatalogResolver {
+        let mut entries = Vec::new();
+
+        for child in doc.root().children() {
+            if let Some(catalog) = child.element() {
+                if is_catalog_element(catalog, "catalog") {
+                    read_entries(catalog, base, true, &mut entries);
+                }
+            }
+        }
+
+        CatalogResolver { entries }
+    }
+
+    /// The file that the catalog maps the identifiers to, if any.
+    /// The system identifier is looked up first, then the public
+    /// identifier.
+    pub fn local_path(&self, public_id: Option<&str>, system_id: &str) -> Option<PathBuf> {
+        self.system_path(system_id)
+            .or_else(|| public_id.and_then(|id| self.public_path(id)))
+    }
+
+    fn system_path(&self, system_id: &str) -> Option<PathBuf> {
+        let exact = self.entries.iter().find_map(|e| match *e {
+            CatalogEntry::System {
+                system_id: ref id,
+                ref path,
+            } if id == system_id => Some(path.clone()),
+            _ => None,
+        });
+
+        // The longest matching start or suffix wins. The rest of the
+        // identifier may not lead out of the rewritten directory.
+        let rewrite = || {
+            self.entries
+                .iter()
+                .filter_map(|e| match *e {
+                    CatalogEntry::RewriteSystem {
+                        ref start,
+                        ref prefix,
+                    } if system_id.starts_with(&start[..]) => Some((start.len(), prefix)),
+                    _ => None,
+                })
+                .max_by_key(|&(len, _)| len)
+                .and_then(|(len, prefix)| {
+                    confined_path(system_id[len..].trim_start_matches('/'))
+                        .map(|rest| prefix.join(rest))
+                })
+        };
+
+        let suffix = || {
+            self.entries
+                .iter()
+                .filter_map(|e| match *e {
+                    CatalogEntry::SystemSuffix {
+                        ref suffix,
+                        ref path,
+                    } if system_id.ends_with(&suffix[..]) => Some((suffix.len(), path)),
+                    _ => None,
+                })
+                .max_by_key(|&(len, _)| len)
+                .map(|(_, path)| path.clone())
+        };
+
+        exact.or_else(rewrite).or_else(suffix)
+    }
+
+    /// A document always gives a system identifier along with a
+    /// public one, so public entries are only used where the catalog
+    /// prefers them
+    fn public_path(&self, public_id: &str) -> Option<PathBuf> {
+        let public_id = normalize_public_id(public_id);
+
+        self.entries.iter().find_map(|e| match *e {
+            CatalogEntry::Public {
+                public_id: ref id,
+                ref path,
+                prefer_public: true,
+            } if *id == public_id => Some(path.clone()),
+            _ => None,
+        })
+    }
+}
+
+impl Resolver for CatalogResolver {
+    fn resolve(&self, public_id: Option<&str>, system_id: &str) -> io::Result<Vec<u8>> {
+        match self.local_path(public_id, system_id) {
+            Some(path) => fs::read(path),
+            None => Err(refused()),
+        }
+    }
+}
+
+fn is_catalog_element(element: dom::Element<'_>, local_part: &str) -> bool {
+    let name = element.name();
+    name.namespace_uri() == Some(CATALOG_NS_URI) && name.local_part() == local_part
+}
+
+/// The path that a URI in the catalog refers to
+fn catalog_path(base: &Path, uri: &str) -> PathBuf {
+    base.join(file_uri_path(uri).unwrap_or(uri))
+}
+
+/// A relative path that cannot lead out of the directory it is
+/// joined to
+fn confined_path(path: &str) -> Option<&Path> {
+    let path = Path::new(path);
+    let confined = path.components().all(|c| {
+        if let Component::Normal(_) = c {
+            true
+        } else {
+            c == Component::CurDir
+        }
+    });
+
+    if confined {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Whitespace in public identifiers is not significant
+fn normalize_public_id(id: &str) -> String {
+    id.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Reads the entries of a `catalog` or `group` element
+fn read_entries(
+    parent: dom::Element<'_>,
+    base: &Path,
+    prefer_public: bool,
+    entries: &mut Vec<CatalogEntry>,
+) {
+    let (base, prefer_public) = entry_settings(parent, base, prefer_public);
+
+    for child in parent.children() {
+        let element = match child.element() {
+            Some(element) if element.name().namespace_uri() == Some(CATALOG_NS_URI) => element,
+            _ => continue,
+        };
+
+        if is_catalog_element(element, "group") {
+            read_entries(element, &base, prefer_public, entries);
+            continue;
+        }
+
+        let (base, prefer_public) = entry_settings(element, &base, prefer_public);
+        let attribute = |name| element.attribute_value(name);
+        let path = |name| attribute(name).map(|uri| catalog_path(&base, uri));
+
+        let entry = match element.name().local_part() {
+            "public" => attribute("publicId")
+                .and_then(|id| Some((id, path("uri")?)))
+                .map(|(id, path)| CatalogEntry::Public {
+                    public_id: normalize_public_id(id),
+                    path,
+                    prefer_public,
+                }),
+            "system" => attribute("systemId")
+                .and_then(|id| Some((id, path("uri")?)))
+                .map(|(id, path)| CatalogEntry::System {
+                    system_id: id.into(),
+                    path,
+                }),
+            "rewriteSystem" => attribute("systemIdStartString")
+                .and_then(|start| Some((start, path("rewritePrefix")?)))
+                .map(|(start, prefix)| CatalogEntry::RewriteSystem {
+                    start: start.into(),
+                    prefix,
+                }),
+            "systemSuffix" => attribute("systemIdSuffix")
+                .and_then(|suffix| Some((suffix, path("uri")?)))
+                .map(|(suffix, path)| CatalogEntry::SystemSuffix {
+                    suffix: suffix.into(),
+                    path,
+                }),
+            _ => None,
+        };
+
+        entries.extend(entry);
+    }
+}
+
+/// Applies the `xml:base` and `prefer` attributes of an element
+fn entry_settings(element: dom::Element<'_>, base: &Path, prefer_public: bool) -> (PathBuf, bool) {
+    let base = match element.attribute_value((crate::XML_NS_URI, "base")) {
+        Some(uri) => catalog_path(base, uri),
+        None => base.to_path_buf(),
+    };
+
+    let prefer_public = match element.attribute_value("prefer") {
+        Some("public") => true,
+        Some("system") => false,
+        _ => prefer_public,
+    };
+
+    (base, prefer_public)
+}
+
+/// The path of a `file:` URI
+fn file_uri_path(uri: &str) -> Option<&str> {
+    ["file://", "file:"]
+        .iter()
+        .find(|scheme| uri.starts_with(*scheme))
+        .map(|scheme| &uri[scheme.len()..])
+}
+
+/// Whether the identifier starts with a URI scheme, such as `http:`
+fn has_scheme(id: &str) -> bool {
+    match id.find(':') {
+        // A single letter is more likely to be a Windows drive
+        Some(i) if i > 1 => id[..i]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+
+    /// A fresh directory containing `files`, with a sibling file
+    /// outside of it
+    fn directory(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = env::temp_dir().join("sxd-document-resolver").join(name);
+        let _ = fs::remove_dir_all(&root);
+
+        let base = root.join("base");
+        fs::create_dir_all(&base).unwrap();
+        for &(file, content) in files {
+            let path = base.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        fs::write(root.join("outside.dtd"), "secret").unwrap();
+
+        base
+    }
+
+    #[test]
+    fn deny_all_refuses_everything() {
+        let e = DenyAll.resolve(None, "a.dtd").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn files_are_loaded_relative_to_the_base() {
+        let base = directory("relative", &[("a.dtd", "a"), ("sub/b.ent", "b")]);
+        let resolver = FileResolver::new(&base);
+
+        assert_eq!(resolver.resolve(None, "a.dtd").unwrap(), b"a");
+        assert_eq!(resolver.resolve(None, "./sub/b.ent").unwrap(), b"b");
+        assert_eq!(resolver.resolve(None, "sub/../a.dtd").unwrap(), b"a");
+    }
+
+    #[test]
+    fn file_uris_are_loaded() {
+        let base = directory("uris", &[("a.dtd", "a")]);
+        let resolver = FileResolver::new(&base);
+        let absolute = base.canonicalize().unwrap().join("a.dtd");
+
+        let uri = format!("file://{}", absolute.display());
+        assert_eq!(resolver.resolve(None, &uri).unwrap(), b"a");
+        assert_eq!(resolver.resolve(None, "file:a.dtd").unwrap(), b"a");
+    }
+
+    #[test]
+    fn files_outside_the_base_are_refused() {
+        let base = directory("outside", &[("a.dtd", "a")]);
+        let resolver = FileResolver::new(&base);
+        let outside = base.join("../outside.dtd").canonicalize().unwrap();
+
+        for id in &["../outside.dtd", &outside.display().to_string()] {
+            let e = resolver.resolve(None, id).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::PermissionDenied, "{}", id);
+        }
+    }
+
+    #[test]
+    fn other_schemes_are_refused() {
+        let base = directory("schemes", &[]);
+        let resolver = FileResolver::new(&base);
+
+        let e = resolver
+            .resolve(None, "http://example.com/a.dtd")
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    fn catalog(entries: &str) -> CatalogResolver {
+        let xml = format!(
+            "<catalog xmlns='urn:oasis:names:tc:entity:xmlns:xml:catalog'>{}</catalog>",
+            entries
+        );
+        CatalogResolver::parse(&xml, Path::new("/catalog")).expect("Failed to parse")
+    }
+
+    fn local(path: &str) -> Option<PathBuf> {
+        Some(PathBuf::from(path))
+    }
+
+    #[test]
+    fn catalogs_map_system_identifiers_before_public_ones() {
+        let catalog = catalog(
+            "<public publicId='-//A//DTD A//EN' uri='public.dtd'/>\
+             <system systemId='http://example.com/a.dtd' uri='system.dtd'/>",
+        );
+
+        assert_eq!(
+            catalog.local_path(Some("-//A//DTD A//EN"), "http://example.com/a.dtd"),
+            local("/catalog/system.dtd")
+        );
+        assert_eq!(
+            catalog.local_path(Some("-//A//DTD  A//EN"), "a.dtd"),
+            local("/catalog/public.dtd")
+        );
+        assert_eq!(catalog.local_path(None, "a.dtd"), None);
+    }
+
+    #[test]
+    fn catalogs_can_prefer_system_identifiers() {
+        let catalog = catalog(
+            "<group prefer='system'>\
+               <public publicId='-//A//DTD A//EN' uri='a.dtd'/>\
+             </group>\
+             <public publicId='-//B//DTD B//EN' uri='b.dtd'/>",
+        );
+
+        assert_eq!(catalog.local_path(Some("-//A//DTD A//EN"), "x.dtd"), None);
+        assert_eq!(
+            catalog.local_path(Some("-//B//DTD B//EN"), "x.dtd"),
+            local("/catalog/b.dtd")
+        );
+    }
+
+    #[test]
+    fn catalogs_rewrite_the_longest_matching_prefix() {
+        let catalog = catalog(
+            "<rewriteSystem systemIdStartString='http://example.com/' rewritePrefix='all'/>\
+             <rewriteSystem systemIdStartString='http://example.com/dtd/' rewritePrefix='dtd/'/>\
+             <systemSuffix systemIdSuffix='/b.dtd' uri='suffix/b.dtd'/>",
+        );
+
+        assert_eq!(
+            catalog.local_path(None, "http://example.com/dtd/a.dtd"),
+            local("/catalog/dtd/a.dtd")
+        );
+        assert_eq!(
+            catalog.local_path(None, "http://example.com/x/a.dtd"),
+            local("/catalog/all/x/a.dtd")
+        );
+        assert_eq!(
+            catalog.local_path(None, "http://other.com/b.dtd"),
+            local("/catalog/suffix/b.dtd")
+        );
+    }
+
+    #[test]
+    fn catalogs_refuse_rewritten_paths_that_leave_the_prefix() {
+        let catalog = catalog(
+            "<rewriteSystem systemIdStartString='http://example.com/dtd/' rewritePrefix='dtd/'/>",
+        );
+
+        assert_eq!(
+            catalog.local_path(None, "http://example.com/dtd/../secret.ent"),
+            None
+        );
+        assert_eq!(
+            catalog.local_path(None, "http://example.com/dtd/a/../../secret.ent"),
+            None
+        );
+        assert_eq!(
+            catalog.local_path(None, "http://example.com/dtd/./a.dtd"),
+            local("/catalog/dtd/a.dtd")
+        );
+        assert_eq!(
+            catalog
+                .resolve(None, "http://example.com/dtd/../secret.ent")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn catalogs_apply_xml_base() {
+        let catalog = catalog(
+            "<group xml:base='dtds/'>\
+               <system systemId='a' uri='a.dtd'/>\
+               <system systemId='b' uri='b.dtd' xml:base='file:///abs/'/>\
+             </group>\
+             <system systemId='c' uri='c.dtd'/>\
+             <other:system xmlns:other='urn:other' systemId='d' uri='d.dtd'/>",
+        );
+
+        assert_eq!(catalog.local_path(None, "a"), local("/catalog/dtds/a.dtd"));
+        assert_eq!(catalog.local_path(None, "b"), local("/abs/b.dtd"));
+        assert_eq!(catalog.local_path(None, "c"), local("/catalog/c.dtd"));
+        assert_eq!(catalog.local_path(None, "d"), None);
+    }
+
+    #[test]
+    fn catalogs_are_used_to_load_the_external_subset() {
+        let base = directory(
+            "catalog",
+            &[
+                (
+                    "catalog.xml",
+                    "<catalog xmlns='urn:oasis:names:tc:entity:xmlns:xml:catalog'>\
+                       <public publicId='-//A//DTD A//EN' uri='dtd/a.dtd'/>\
+                     </catalog>",
+                ),
+                ("dtd/a.dtd", "<!ENTITY greeting 'hello'>"),
+            ],
+        );
+        let resolver = CatalogResolver::from_file(base.join("catalog.xml")).unwrap();
+
+        let xml = "<!DOCTYPE a PUBLIC '-//A//DTD A//EN' 'http://example.com/a.dtd'>\
+                   <a>&greeting;</a>";
+        let package = parser::Parser::new()
+            .set_resolver(resolver.clone())
+            .parse(xml)
+            .expect("Failed to parse");
+        let doc = package.as_document();
+        let a = doc.root().children()[1].element().unwrap();
+        assert_eq!(a.children()[0].text().unwrap().text(), "hello");
+
+        let e = resolver
+            .resolve(None, "http://example.com/b.dtd")
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn missing_files_are_not_found() {
+        let base = directory("missing", &[]);
+        let e = FileResolver::new(&base)
+            .resolve(None, "missing.dtd")
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,587 @@
+//! Reading a document by calling a `ContentHandler` for each part of
+//! it, without building a DOM.
+//!
+//! The document is read with the same rules as `parser::EventReader`:
+//! entities declared in the internal subset are expanded, line ends are
+//! normalized to `\n` and whitespace in attribute values becomes
+//! spaces. Referring to an entity that contains markup is an
+//! `EntityWithMarkup` error. Names are reported with their namespaces
+//! resolved. Namespace
+//! declarations are reported with `start_prefix_mapping` and
+//! `end_prefix_mapping` instead of as attributes. Any callback can
+//! return `Control::Stop` to stop reading the rest of the document.
+//!
+//! ### Example
+//!
+//! ```
+//! use sxd_document::{
+//!     sax::{self, ContentHandler, Control},
+//!     QName,
+//! };
+//!
+//! struct FirstTitle(Option<String>, bool);
+//!
+//! impl ContentHandler for FirstTitle {
+//!     fn start_element(&mut self, name: QName<'_>, _: &[sax::Attribute<'_>]) -> Control {
+//!         self.1 = name == QName::with_namespace_uri(Some("urn:book"), "title");
+//!         Control::Continue
+//!     }
+//!
+//!     fn characters(&mut self, text: &str) -> Control {
+//!         if !self.1 {
+//!             return Control::Continue;
+//!         }
+//!         self.0 = Some(text.to_owned());
+//!         Control::Stop
+//!     }
+//! }
+//!
+//! let xml = "<b:book xmlns:b='urn:book'><b:title>Dune</b:title><b:title>Emma</b:title></b:book>";
+//! let mut handler = FirstTitle(None, false);
+//! sax::parse(xml, &mut handler).expect("Failed to parse");
+//!
+//! assert_eq!(handler.0, Some("Dune".to_owned()));
+//! ```
+
+use std::io::BufRead;
+
+use super::{
+    parser::{self, Error, ErrorKind, Event, EventReader, ReadError, StreamingEventReader},
+    PrefixedName, QName,
+};
+
+/// Whether reading continues after a callback
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Control {
+    Continue,
+    /// Stop reading without reporting the rest of the document.
+    /// Whatever follows is not checked for errors.
+    Stop,
+}
+
+/// An attribute of an element, as given to
+/// `ContentHandler::start_element`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Attribute<'a> {
+    name: QName<'a>,
+    prefix: Option<&'a str>,
+    value: &'a str,
+}
+
+impl<'a> Attribute<'a> {
+    pub fn name(&self) -> QName<'a> {
+        self.name
+    }
+
+    /// The prefix the name was written with
+    pub fn prefix(&self) -> Option<&'a str> {
+        self.prefix
+    }
+
+    /// The value with all references decoded
+    pub fn value(&self) -> &'a str {
+        self.value
+    }
+}
+
+/// Receives the parts of a document in order. Every method does
+/// nothing by default, so only the interesting ones need to be
+/// implemented.
+pub trait ContentHandler {
+    fn start_document(&mut self) -> Control {
+        Control::Continue
+    }
+
+    /// Called when the whole document has been read without errors
+    fn end_document(&mut self) -> Control {
+        Control::Continue
+    }
+
+    /// A namespace declaration on the element whose `start_element`
+    /// follows. A `None` prefix is the default namespace, and an
+    /// empty URI undeclares the prefix.
+    fn start_prefix_mapping(&mut self, _prefix: Option<&str>, _namespace_uri: &str) -> Control {
+        Control::Continue
+    }
+
+    /// The declaration goes out of scope, after the `end_element` of
+    /// the element it was on
+    fn end_prefix_mapping(&mut self, _prefix: Option<&str>) -> Control {
+        Control::Continue
+    }
+
+    /// The attributes do not include namespace declarations. A
+    /// self-closing element is followed immediately by its
+    /// `end_element`.
+    fn start_element(&mut self, _name: QName<'_>, _attributes: &[Attribute<'_>]) -> Control {
+        Control::Continue
+    }
+
+    fn end_element(&mut self, _name: QName<'_>) -> Control {
+        Control::Continue
+    }
+
+    /// A run of text, including the content of CDATA sections
+    fn characters(&mut self, _text: &str) -> Control {
+        Control::Continue
+    }
+
+    fn comment(&mut self, _text: &str) -> Control {
+        Control::Continue
+    }
+
+    fn processing_instruction(&mut self, _target: &str, _value: Option<&str>) -> Control {
+        Control::Continue
+    }
+}
+
+/// Reads a string, calling the handler for each part of it. Returns
+/// early without an error when the handler stops reading.
+pub fn parse<H>(xml: &str, handler: &mut H) -> Result<(), Error>
+where
+    H: ?Sized + ContentHandler,
+{
+    let mut events = EventReader::new(xml);
+    let mut namespaces = Namespaces::new();
+
+    if handler.start_document() == Control::Stop {
+        return Ok(());
+    }
+
+    while let Some(event) = events.next() {
+        match namespaces.handle(&event?, handler) {
+            Ok(Control::Continue) => {}
+            Ok(Control::Stop) => return Ok(()),
+            Err((index, kind)) => return Err(events.start_tag_error(index, kind)),
+        }
+    }
+
+    handler.end_document();
+    Ok(())
+}
+
+/// Reads a document from a reader as `parse` does. Only the part of
+/// the document needed for the current callback is kept in memory.
+pub fn parse_reader<R, H>(reader: R, handler: &mut H) -> Result<(), ReadError>
+where
+    R: BufRead,
+    H: ?Sized + ContentHandler,
+{
+    let mut events = StreamingEventReader::new(reader);
+    let mut namespaces = Namespaces::new();
+
+    if handler.start_document() == Control::Stop {
+        return Ok(());
+    }
+
+    while let Some(event) = events.next_event() {
+        let handled = namespaces.handle(&event?, handler);
+
+        match handled {
+            Ok(Control::Continue) => {}
+            Ok(Control::Stop) => return Ok(()),
+            Err((index, kind)) => return Err(events.start_tag_error(index, kind)),
+        }
+    }
+
+    handler.end_document();
+    Ok(())
+}
+
+/// An error in a start tag: where in the tag it is, counting the
+/// element name as 0 and then each attribute, and what it is.
+type StartTagError = (usize, ErrorKind);
+
+/// The namespace declarations in scope
+struct Namespaces {
+    /// Each declared prefix, or an empty one for the default
+    /// namespace, with its URI. Later declarations hide earlier ones.
+    bindings: Vec<(String, String)>,
+    /// How many declarations each open element added
+    declared: Vec<usize>,
+    xml_1_1: bool,
+}
+
+impl Namespaces {
+    fn new() -> Self {
+        Namespaces {
+            bindings: vec![(crate::XML_NS_PREFIX.into(), crate::XML_NS_URI.into())],
+            declared: Vec::new(),
+            xml_1_1: false,
+        }
+    }
+
+    fn namespace_uri(&self, prefix: Option<&str>) -> Option<&str> {
+        self.bindings
+            .iter()
+            .rev()
+            .find(|b| b.0 == prefix.unwrap_or(""))
+            .map(|b| &b.1[..])
+            .filter(|ns_uri| !ns_uri.is_empty())
+    }
+
+    fn handle<H>(&mut self, event: &Event<'_>, handler: &mut H) -> Result<Control, StartTagError>
+    where
+        H: ?Sized + ContentHandler,
+    {
+        match *event {
+            Event::XmlDeclaration { version, .. } => {
+                self.xml_1_1 = version == "1.1";
+                Ok(Control::Continue)
+            }
+            Event::DocumentType { .. } => Ok(Control::Continue),
+            Event::StartElement {
+                name,
+                ref attributes,
+            } => self.start_element(name, attributes, handler),
+            Event::EndElement { name } => Ok(self.end_element(name, handler)),
+            Event::Text(ref text) => Ok(handler.characters(text)),
+            Event::CData(ref text) => Ok(handler.characters(text)),
+            Event::Comment(ref text) => Ok(handler.comment(text)),
+            Event::ProcessingInstruction { target, ref value } => {
+                let value = value.as_ref().map(|v| &v[..]);
+                Ok(handler.processing_instruction(target, value))
+            }
+        }
+    }
+
+    fn start_element<H>(
+        &mut self,
+        name: PrefixedName<'_>,
+        attributes: &[parser::Attribute<'_>],
+        handler: &mut H,
+    ) -> Result<Control, StartTagError>
+    where
+        H: ?Sized + ContentHandler,
+    {
+        let scope = self.bindings.len();
+
+        for (i, attribute) in attributes.iter().enumerate() {
+            let prefix = match (attribute.name().prefix, attribute.name().local_part) {
+                (Some("xmlns"), prefix) => Some(prefix),
+                (None, "xmlns") => None,
+                _ => continue,
+            };
+            let ns_uri = attribute.value();
+
+            // XML 1.1 allows a prefix to be undeclared
+            if prefix.is_some() && ns_uri.is_empty() && !self.xml_1_1 {
+                return Err((i + 1, ErrorKind::EmptyNamespace));
+            }
+            parser::check_prefix_binding(prefix, ns_uri).map_err(|kind| (i + 1, kind))?;
+
+            self.bindings
+                .push((prefix.unwrap_or("").into(), ns_uri.into()));
+        }
+        self.declared.push(self.bindings.len() - scope);
+
+        let element_ns_uri = match name.prefix {
+            Some(prefix) => Some(
+                self.namespace_uri(Some(prefix))
+                    .ok_or((0, ErrorKind::UnknownNamespacePrefix))?,
+            ),
+            None => self.namespace_uri(None),
+        };
+
+        let mut resolved: Vec<Attribute<'_>> = Vec::with_capacity(attributes.len());
+        for (i, attribute) in attributes.iter().enumerate() {
+            let prefixed_name = attribute.name();
+            let ns_uri = match (prefixed_name.prefix, prefixed_name.local_part) {
+                (Some("xmlns"), _) | (None, "xmlns") => continue,
+                (Some(prefix), _) => Some(
+                    self.namespace_uri(Some(prefix))
+                        .ok_or((i + 1, ErrorKind::UnknownNamespacePrefix))?,
+                ),
+                (None, _) => None,
+            };
+
+            let name = QName::with_namespace_uri(ns_uri, prefixed_name.local_part);
+            if resolved.iter().any(|a| a.name == name) {
+                return Err((i + 1, ErrorKind::DuplicateExpandedAttribute));
+            }
+
+            resolved.push(Attribute {
+                name,
+                prefix: prefixed_name.prefix,
+                value: attribute.value(),
+            });
+        }
+
+        for (prefix, ns_uri) in &self.bindings[scope..] {
+            if handler.start_prefix_mapping(declared_prefix(prefix), ns_uri) == Control::Stop {
+                return Ok(Control::Stop);
+            }
+        }
+
+        let name = QName::with_namespace_uri(element_ns_uri, name.local_part);
+        Ok(handler.start_element(name, &resolved))
+    }
+
+    fn end_element<H>(&mut self, name: PrefixedName<'_>, handler: &mut H) -> Control
+    where
+        H: ?Sized + ContentHandler,
+    {
+        let ns_uri = self.namespace_uri(name.prefix);
+        let name = QName::with_namespace_uri(ns_uri, name.local_part);
+        if handler.end_element(name) == Control::Stop {
+            return Control::Stop;
+        }
+
+        let declared = self.declared.pop().expect("No open element");
+        for _ in 0..declared {
+            let (prefix, _) = self.bindings.pop().expect("No namespace declaration");
+            if handler.end_prefix_mapping(declared_prefix(&prefix)) == Control::Stop {
+                return Control::Stop;
+            }
+        }
+
+        Control::Continue
+    }
+}
+
+fn declared_prefix(prefix: &str) -> Option<&str> {
+    Some(prefix).filter(|p| !p.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Records each callback as a line of text
+    #[derive(Default)]
+    struct Recorder {
+        calls: Vec<String>,
+        stop_at: Option<usize>,
+    }
+
+    impl Recorder {
+        fn record(&mut self, call: String) -> Control {
+            self.calls.push(call);
+            if Some(self.calls.len()) == self.stop_at {
+                Control::Stop
+            } else {
+                Control::Continue
+            }
+        }
+    }
+
+    fn show(name: QName<'_>) -> String {
+        match name.namespace_uri() {
+            Some(ns_uri) => format!("{{{}}}{}", ns_uri, name.local_part()),
+            None => name.local_part().to_owned(),
+        }
+    }
+
+    impl ContentHandler for Recorder {
+        fn start_document(&mut self) -> Control {
+            self.record("start document".into())
+        }
+
+        fn end_document(&mut self) -> Control {
+            self.record("end document".into())
+        }
+
+        fn start_prefix_mapping(&mut self, prefix: Option<&str>, namespace_uri: &str) -> Control {
+            self.record(format!("start prefix {:?}={}", prefix, namespace_uri))
+        }
+
+        fn end_prefix_mapping(&mut self, prefix: Option<&str>) -> Control {
+            self.record(format!("end prefix {:?}", prefix))
+        }
+
+        fn start_element(&mut self, name: QName<'_>, attributes: &[Attribute<'_>]) -> Control {
+            let mut call = format!("start {}", show(name));
+            for a in attributes {
+                call.push_str(&format!(" {}={}", show(a.name()), a.value()));
+            }
+            self.record(call)
+        }
+
+        fn end_element(&mut self, name: QName<'_>) -> Control {
+            self.record(format!("end {}", show(name)))
+        }
+
+        fn characters(&mut self, text: &str) -> Control {
+            self.record(format!("text {}", text))
+        }
+
+        fn comment(&mut self, text: &str) -> Control {
+            self.record(format!("comment {}", text))
+        }
+
+        fn processing_instruction(&mut self, target: &str, value: Option<&str>) -> Control {
+            self.record(format!("pi {} {:?}", target, value))
+        }
+    }
+
+    fn calls(xml: &str) -> Vec<String> {
+        let mut recorder = Recorder::default();
+        parse(xml, &mut recorder).expect("Failed to parse");
+        recorder.calls
+    }
+
+    #[test]
+    fn names_are_reported_with_their_namespaces() {
+        let calls = calls("<a xmlns='urn:d' xmlns:p='urn:p' p:x='1' y='2'><p:b/></a>");
+
+        assert_eq!(
+            calls,
+            [
+                "start document",
+                "start prefix None=urn:d",
+                "start prefix Some(\"p\")=urn:p",
+                "start {urn:d}a {urn:p}x=1 y=2",
+                "start {urn:p}b",
+                "end {urn:p}b",
+                "end {urn:d}a",
+                "end prefix Some(\"p\")",
+                "end prefix None",
+                "end document",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_default_namespace_can_be_undeclared() {
+        let calls = calls("<a xmlns='urn:d'><b xmlns=''/></a>");
+
+        assert!(calls.contains(&"start b".to_owned()));
+    }
+
+    #[test]
+    fn the_xml_prefix_is_always_declared() {
+        let calls = calls("<a xml:lang='en'/>");
+
+        assert_eq!(
+            calls[1],
+            "start a {http://www.w3.org/XML/1998/namespace}lang=en"
+        );
+    }
+
+    #[test]
+    fn text_comments_and_processing_instructions_are_reported() {
+        let calls = calls("<a>x &amp; y<![CDATA[<z>]]><!--c--><?pi v?></a>");
+
+        assert_eq!(
+            &calls[2..6],
+            ["text x & y", "text <z>", "comment c", "pi pi Some(\"v\")"]
+        );
+    }
+
+    #[test]
+    fn entities_declared_in_the_internal_subset_are_expanded() {
+        let calls = calls("<!DOCTYPE a [<!ENTITY e 'x&#38;#38;y'>]><a b='&e;'>1 &e; 2</a>");
+
+        assert_eq!(&calls[1..3], ["start a b=x&y", "text 1 x&y 2"]);
+    }
+
+    #[test]
+    fn line_ends_are_normalized() {
+        let calls = calls("<a>x\r\ny\rz<!--\r\n--></a>");
+
+        assert_eq!(&calls[2..4], ["text x\ny\nz", "comment \n"]);
+    }
+
+    #[test]
+    fn whitespace_in_attribute_values_becomes_spaces() {
+        let calls = calls("<a b='1\t2\r\n3\n4'/>");
+
+        assert_eq!(calls[1], "start a b=1 2 3 4");
+    }
+
+    #[test]
+    fn a_handler_can_stop_reading() {
+        let mut recorder = Recorder {
+            stop_at: Some(2),
+            ..Recorder::default()
+        };
+
+        // The rest of the document is not checked
+        let r = parse("<a><b></a>", &mut recorder);
+
+        assert!(r.is_ok());
+        assert_eq!(recorder.calls, ["start document", "start a"]);
+    }
+
+    #[test]
+    fn reading_from_a_reader_reports_the_same_calls() {
+        let xml = "<!DOCTYPE a [<!ENTITY e 'x'>]>\
+                   <a xmlns:p='urn:p'><p:b p:c='d\te&e;'>text\r\n&e;</p:b></a>";
+        let mut recorder = Recorder::default();
+        parse_reader(xml.as_bytes(), &mut recorder).expect("Failed to parse");
+
+        assert_eq!(recorder.calls, calls(xml));
+    }
+
+    fn parse_failure(xml: &str) -> (usize, ErrorKind) {
+        let err = parse(xml, &mut Recorder::default()).unwrap_err();
+        (err.location(), err.kind())
+    }
+
+    #[test]
+    fn failure_unknown_namespace_prefix() {
+        assert_eq!(
+            parse_failure("<a><p:b/></a>"),
+            (4, ErrorKind::UnknownNamespacePrefix)
+        );
+        assert_eq!(
+            parse_failure("<a b='1' p:c='2'/>"),
+            (9, ErrorKind::UnknownNamespacePrefix)
+        );
+    }
+
+    #[test]
+    fn failure_reserved_namespace() {
+        assert_eq!(
+            parse_failure("<a xmlns:p='http://www.w3.org/2000/xmlns/'/>"),
+            (3, ErrorKind::ReservedNamespace)
+        );
+    }
+
+    #[test]
+    fn failure_empty_namespace() {
+        assert_eq!(
+            parse_failure("<a xmlns:p=''/>"),
+            (3, ErrorKind::EmptyNamespace)
+        );
+    }
+
+    #[test]
+    fn failure_duplicate_attribute_by_expanded_name() {
+        assert_eq!(
+            parse_failure("<a xmlns:p='u' xmlns:q='u' p:x='1' q:x='2'/>"),
+            (35, ErrorKind::DuplicateExpandedAttribute)
+        );
+    }
+
+    #[test]
+    fn failure_entity_with_markup() {
+        assert_eq!(
+            parse_failure("<!DOCTYPE a [<!ENTITY e '<b/>'>]><a>&e;</a>"),
+            (37, ErrorKind::EntityWithMarkup)
+        );
+    }
+
+    #[test]
+    fn failure_in_the_document_is_reported() {
+        assert_eq!(
+            parse_failure("<a></b>"),
+            (5, ErrorKind::MismatchedElementEndName)
+        );
+    }
+
+    #[test]
+    fn failure_from_a_reader_is_reported_at_its_offset() {
+        let xml = "<a>\n<p:b/></a>";
+        let err = parse_reader(xml.as_bytes(), &mut Recorder::default()).unwrap_err();
+
+        match err {
+            ReadError::Parse(e) => {
+                assert_eq!(e.location(), 5);
+                assert_eq!(e.line(), 2);
+                assert_eq!(e.kind(), ErrorKind::UnknownNamespacePrefix);
+            }
+            e => panic!("Expected a parse error, got {:?}", e),
+        }
+    }
+}
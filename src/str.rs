@@ -30,6 +30,12 @@ impl<'a> StrParseExt for &'a str {
 pub trait XmlStr {
     /// Find the end of the quoted attribute value, not including the quote
     fn end_of_attribute(&self, quote: &str) -> Option<usize>;
+    /// Find the end of a [SystemLiteral](http://www.w3.org/TR/xml/#NT-SystemLiteral), not including the quote
+    fn end_of_system_literal(&self, quote: &str) -> Option<usize>;
+    /// Find the end of a [PubidLiteral](http://www.w3.org/TR/xml/#NT-PubidLiteral), not including the quote
+    fn end_of_pubid_literal(&self, quote: &str) -> Option<usize>;
+    /// Find the end of the literal text in an [EntityValue](http://www.w3.org/TR/xml/#NT-EntityValue), before any reference or the quote
+    fn end_of_entity_value(&self, quote: &str) -> Option<usize>;
     /// Find the end of the direct character data
     fn end_of_char_data(&self) -> Option<usize>;
     /// Find the end of the CData section, not including the ]]>
@@ -46,6 +52,8 @@ pub trait XmlStr {
     fn end_of_name(&self) -> Option<usize>;
     /// Find the end of the [NCName](http://www.w3.org/TR/REC-xml-names/#NT-NCName)
     fn end_of_ncname(&self) -> Option<usize>;
+    /// Find the end of the [Nmtoken](http://www.w3.org/TR/xml/#NT-Nmtoken)
+    fn end_of_nmtoken(&self) -> Option<usize>;
     /// Find the end of a run of space characters
     fn end_of_space(&self) -> Option<usize>;
     /// Find the end of the starting tag
@@ -53,6 +61,11 @@ pub trait XmlStr {
     fn end_of_encoding(&self) -> Option<usize>;
     /// Find the end of the internal doc type declaration, not including the ]
     fn end_of_int_subset(&self) -> Option<usize>;
+    /// Find the end of a markup declaration in the internal subset, not including the >
+    fn end_of_declaration(&self) -> Option<usize>;
+    /// Find the end of the tag, comment, processing instruction, CDATA
+    /// section or doc type declaration, including the closing delimiter
+    fn end_of_markup(&self) -> Option<usize>;
 }
 
 impl<'a> XmlStr for &'a str {
@@ -71,6 +84,28 @@ impl<'a> XmlStr for &'a str {
             .or_else(|| Some(self.len()))
     }
 
+    fn end_of_system_literal(&self, quote: &str) -> Option<usize> {
+        self.find(quote).or(Some(self.len()))
+    }
+
+    fn end_of_pubid_literal(&self, quote: &str) -> Option<usize> {
+        let quote_char = quote.chars().next().expect("Cant have null quote");
+
+        self.find(|c: char| c == quote_char || !c.is_pubid_char())
+            .or(Some(self.len()))
+    }
+
+    fn end_of_entity_value(&self, quote: &str) -> Option<usize> {
+        let quote_char = quote.chars().next().expect("Cant have null quote");
+
+        match self.find(&['&', '%', quote_char][..]) {
+            Some(0) => None,
+            Some(v) => Some(v),
+            None if self.is_empty() => None,
+            None => Some(self.len()),
+        }
+    }
+
     fn end_of_char_data(&self) -> Option<usize> {
         fn find_end_of_char_data(bytes: &[u8]) -> Option<usize> {
             for (i, &b) in bytes.iter().enumerate() {
@@ -123,6 +158,10 @@ impl<'a> XmlStr for &'a str {
         self.end_of_start_rest(|c| c.is_ncname_start_char(), |c| c.is_ncname_char())
     }
 
+    fn end_of_nmtoken(&self) -> Option<usize> {
+        self.end_of_start_rest(|c| c.is_name_char(), |c| c.is_name_char())
+    }
+
     fn end_of_space(&self) -> Option<usize> {
         self.end_of_start_rest(|c| c.is_space_char(), |c| c.is_space_char())
     }
@@ -152,11 +191,116 @@ impl<'a> XmlStr for &'a str {
     }
 
     fn end_of_int_subset(&self) -> Option<usize> {
-        self.find(']')
+        // A ] may appear in a quoted literal, comment or processing
+        // instruction without ending the subset
+        let bytes = self.as_bytes();
+        let mut quote = None;
+        let mut i = 0;
+
+        while i < bytes.len() {
+            let b = bytes[i];
+            match quote {
+                Some(q) if b == q => quote = None,
+                Some(_) => {}
+                None => match b {
+                    b'"' | b'\'' => quote = Some(b),
+                    b'<' if bytes[i..].starts_with(b"<!--") => {
+                        i += 4 + self[i + 4..].find("-->")? + 3;
+                        continue;
+                    }
+                    b'<' if bytes[i..].starts_with(b"<?") => {
+                        i += 2 + self[i + 2..].find("?>")? + 2;
+                        continue;
+                    }
+                    b']' => return Some(i),
+                    _ => {}
+                },
+            }
+            i += 1;
+        }
+
+        None
+    }
+
+    fn end_of_declaration(&self) -> Option<usize> {
+        let bytes = self.as_bytes();
+        let mut quote = None;
+
+        for (i, &b) in bytes.iter().enumerate() {
+            match quote {
+                Some(q) if b == q => quote = None,
+                Some(_) => {}
+                None => match b {
+                    b'"' | b'\'' => quote = Some(b),
+                    b'>' => return Some(i),
+                    _ => {}
+                },
+            }
+        }
+
+        None
+    }
+
+    fn end_of_markup(&self) -> Option<usize> {
+        fn after(s: &str, start: usize, delimiter: &str) -> Option<usize> {
+            s.get(start..)
+                .and_then(|rest| rest.find(delimiter))
+                .map(|i| start + i + delimiter.len())
+        }
+
+        // Finds the closing > while skipping over quoted strings and,
+        // for a doc type declaration, the internal subset.
+        fn end_of_tag(s: &str, start: usize) -> Option<usize> {
+            let bytes = s.as_bytes();
+            let mut quote = None;
+            let mut in_subset = false;
+            let mut i = start;
+
+            while i < bytes.len() {
+                let b = bytes[i];
+                match quote {
+                    Some(q) if b == q => quote = None,
+                    Some(_) => {}
+                    None => match b {
+                        b'"' | b'\'' => quote = Some(b),
+                        b'[' => in_subset = true,
+                        b']' => in_subset = false,
+                        b'<' if in_subset && bytes[i..].starts_with(b"<!--") => {
+                            i = after(s, i + 4, "-->")?;
+                            continue;
+                        }
+                        b'<' if in_subset && bytes[i..].starts_with(b"<?") => {
+                            i = after(s, i + 2, "?>")?;
+                            continue;
+                        }
+                        b'>' if !in_subset => return Some(i + 1),
+                        _ => {}
+                    },
+                }
+                i += 1;
+            }
+
+            None
+        }
+
+        if !self.starts_with('<') {
+            None
+        } else if self.starts_with("<!--") {
+            after(self, 4, "-->")
+        } else if self.starts_with("<![CDATA[") {
+            after(self, 9, "]]>")
+        } else if self.starts_with("<?") {
+            after(self, 2, "?>")
+        } else {
+            end_of_tag(self, 1)
+        }
     }
 }
 
 /// Predicates used when parsing an characters in an XML document.
+///
+/// The name characters are those of the fifth edition of XML 1.0,
+/// which are the same as those of XML 1.1.
 pub trait XmlChar {
     /// Is this a [NameStartChar](http://www.w3.org/TR/xml/#NT-NameStartChar)?
     fn is_name_start_char(self) -> bool;
@@ -172,6 +316,13 @@ pub trait XmlChar {
     fn is_hex_char(self) -> bool;
     fn is_encoding_start_char(self) -> bool;
     fn is_encoding_rest_char(self) -> bool;
+    /// Is this a [PubidChar](http://www.w3.org/TR/xml/#NT-PubidChar)?
+    fn is_pubid_char(self) -> bool;
+    /// Is this a [Char](http://www.w3.org/TR/xml/#NT-Char)?
+    fn is_char(self) -> bool;
+    /// Is this a [RestrictedChar](http://www.w3.org/TR/xml11/#NT-RestrictedChar),
+    /// which XML 1.1 only allows as a character reference?
+    fn is_restricted_char(self) -> bool;
 }
 
 impl XmlChar for char {
@@ -253,11 +404,41 @@ impl XmlChar for char {
             _ => false,
         }
     }
+
+    fn is_pubid_char(self) -> bool {
+        match self {
+            '\x20' | '\x0D' | '\x0A' | 'A'..='Z' | 'a'..='z' | '0'..='9' => true,
+            _ => "-'()+,./:=?;!*#@$_%".contains(self),
+        }
+    }
+
+    fn is_char(self) -> bool {
+        match self {
+            '\x09'
+            | '\x0A'
+            | '\x0D'
+            | '\x20'..='\u{D7FF}'
+            | '\u{E000}'..='\u{FFFD}'
+            | '\u{10000}'..='\u{10FFFF}' => true,
+            _ => false,
+        }
+    }
+
+    fn is_restricted_char(self) -> bool {
+        match self {
+            '\x01'..='\x08'
+            | '\x0B'..='\x0C'
+            | '\x0E'..='\x1F'
+            | '\x7F'..='\u{84}'
+            | '\u{86}'..='\u{9F}' => true,
+            _ => false,
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::XmlStr;
+    use super::{XmlChar, XmlStr};
 
     #[test]
     fn end_of_char_data_leading_ampersand() {
@@ -306,4 +487,98 @@ mod test {
     fn end_of_int_subset_excludes_right_square() {
         assert_eq!("hello]>world".end_of_int_subset(), Some("hello".len()))
     }
+
+    #[test]
+    fn end_of_int_subset_skips_literals_comments_and_pis() {
+        let subset = "<!ENTITY a ']'><!-- ] --><?pi ]?>]>";
+        assert_eq!(subset.end_of_int_subset(), Some(subset.len() - 2));
+        assert_eq!("<!-- ]".end_of_int_subset(), None);
+    }
+
+    #[test]
+    fn end_of_declaration_skips_quoted_greater_than() {
+        assert_eq!("a (b) '>' \">\">c".end_of_declaration(), Some(13));
+        assert_eq!("a '>".end_of_declaration(), None);
+    }
+
+    #[test]
+    fn end_of_entity_value_stops_at_references() {
+        assert_eq!("a&b;'".end_of_entity_value("'"), Some(1));
+        assert_eq!("a%b;'".end_of_entity_value("'"), Some(1));
+        assert_eq!("a\"b'".end_of_entity_value("'"), Some(3));
+        assert_eq!("'".end_of_entity_value("'"), None);
+    }
+
+    #[test]
+    fn end_of_markup_includes_closing_delimiter() {
+        assert_eq!("<!-- a -->b".end_of_markup(), Some("<!-- a -->".len()));
+        assert_eq!(
+            "<![CDATA[>]]>b".end_of_markup(),
+            Some("<![CDATA[>]]>".len())
+        );
+        assert_eq!("<?pi > ?>b".end_of_markup(), Some("<?pi > ?>".len()));
+        assert_eq!("</a >b".end_of_markup(), Some("</a >".len()));
+    }
+
+    #[test]
+    fn end_of_markup_skips_quoted_greater_than() {
+        assert_eq!("<a b='>' c=\">\">d".end_of_markup(), Some(15));
+    }
+
+    #[test]
+    fn end_of_markup_skips_internal_subset() {
+        let decl = "<!DOCTYPE a [<!ENTITY b '>]'><!-- ]> --><?pi ]>?>]>";
+        assert_eq!(decl.end_of_markup(), Some(decl.len()));
+    }
+
+    #[test]
+    fn end_of_markup_requires_closing_delimiter() {
+        assert_eq!("<a b='>".end_of_markup(), None);
+        assert_eq!("<!-- a --".end_of_markup(), None);
+        assert_eq!("<!DOCTYPE a [ ]".end_of_markup(), None);
+        assert_eq!("text".end_of_markup(), None);
+    }
+
+    #[test]
+    fn end_of_system_literal_allows_markup_characters() {
+        assert_eq!("a?b=1&c=<d>'rest".end_of_system_literal("'"), Some(11));
+        assert_eq!("".end_of_system_literal("'"), Some(0));
+    }
+
+    #[test]
+    fn end_of_pubid_literal_stops_at_the_quote() {
+        assert_eq!(
+            "-//W3C//DTD XHTML 1.0//EN\"".end_of_pubid_literal("\""),
+            Some(25)
+        );
+        assert_eq!("it's'".end_of_pubid_literal("'"), Some(2));
+        assert_eq!("it's\"".end_of_pubid_literal("\""), Some(4));
+    }
+
+    #[test]
+    fn end_of_pubid_literal_stops_at_invalid_characters() {
+        assert_eq!("a<b'".end_of_pubid_literal("'"), Some(1));
+    }
+
+    #[test]
+    fn chars_exclude_nul_controls_surrogates_and_non_characters() {
+        assert!('\x09'.is_char());
+        assert!('\x7F'.is_char());
+        assert!('\u{10000}'.is_char());
+        assert!(!'\x00'.is_char());
+        assert!(!'\x1F'.is_char());
+        assert!(!'\u{FFFE}'.is_char());
+        assert!(!'\u{FFFF}'.is_char());
+    }
+
+    #[test]
+    fn restricted_chars_are_the_controls_other_than_line_ends() {
+        assert!('\x01'.is_restricted_char());
+        assert!('\x7F'.is_restricted_char());
+        assert!('\u{9F}'.is_restricted_char());
+        assert!(!'\x09'.is_restricted_char());
+        assert!(!'\x0A'.is_restricted_char());
+        assert!(!'\u{85}'.is_restricted_char());
+        assert!(!'a'.is_restricted_char());
+    }
 }
@@ -102,7 +102,11 @@ where
         match tail.find(&self.chars) {
             Some(start) => {
                 let start = self.start + start;
-                let end = start + 1; // Super dangerous! Assume we are only one byte long
+                let len = self.haystack[start..]
+                    .chars()
+                    .next()
+                    .map_or(1, char::len_utf8);
+                let end = start + len;
                 if self.start == start {
                     let s = &self.haystack[start..end];
                     self.start = end;
@@ -168,4 +172,13 @@ mod test {
         let items: Vec<_> = ",;".split_keeping_delimiter(delims).collect();
         assert_eq!(&items, &[Delimiter(","), Delimiter(";")]);
     }
+
+    #[test]
+    fn split_with_delimiter_allows_multibyte_delimiters() {
+        use super::SplitType::*;
+        let items: Vec<_> = "a\u{20AC}b"
+            .split_keeping_delimiter(|c| c == '\u{20AC}')
+            .collect();
+        assert_eq!(&items, &[Match("a"), Delimiter("\u{20AC}"), Match("b")]);
+    }
 }
@@ -11,6 +11,18 @@ impl<'d> Storage<'d> {
         Storage { storage }
     }
 
+    pub fn create_document_type(
+        &'d self,
+        name: &str,
+        public_id: Option<&str>,
+        system_id: Option<&str>,
+    ) -> DocumentType<'d> {
+        DocumentType::wrap(
+            self.storage
+                .create_document_type(name, public_id, system_id),
+        )
+    }
+
     pub fn create_element<'n, N>(&'d self, name: N) -> Element<'d>
     where
         N: Into<QName<'n>>,
@@ -352,6 +364,29 @@ impl<'d> fmt::Debug for Root<'d> {
     }
 }
 
+node!(DocumentType, raw::DocumentType);
+
+impl<'d> DocumentType<'d> {
+    pub fn name(self) -> &'d str {
+        self.node().name()
+    }
+    pub fn public_id(self) -> Option<&'d str> {
+        self.node().public_id()
+    }
+    pub fn system_id(self) -> Option<&'d str> {
+        self.node().system_id()
+    }
+    pub fn internal_subset(self) -> Option<&'d str> {
+        self.node().internal_subset()
+    }
+}
+
+impl<'d> fmt::Debug for DocumentType<'d> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DocumentType {{ name: {:?} }}", self.name())
+    }
+}
+
 node!(Element, raw::Element);
 
 impl<'d> Element<'d> {
@@ -452,12 +487,14 @@ macro_rules! unpack(
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ChildOfRoot<'d> {
     Element(Element<'d>),
+    DocumentType(DocumentType<'d>),
     Comment(Comment<'d>),
     ProcessingInstruction(ProcessingInstruction<'d>),
 }
 
 impl<'d> ChildOfRoot<'d> {
     unpack!(ChildOfRoot, element, Element, Element);
+    unpack!(ChildOfRoot, document_type, DocumentType, DocumentType);
     unpack!(ChildOfRoot, comment, Comment, Comment);
     unpack!(
         ChildOfRoot,
@@ -466,9 +503,21 @@ impl<'d> ChildOfRoot<'d> {
         ProcessingInstruction
     );
 
+    /// The same node as a child of an element, unless it is a
+    /// document type, which can only be a child of the root.
+    pub fn into_child_of_element(self) -> Option<ChildOfElement<'d>> {
+        match self {
+            ChildOfRoot::Element(n) => Some(ChildOfElement::Element(n)),
+            ChildOfRoot::DocumentType(_) => None,
+            ChildOfRoot::Comment(n) => Some(ChildOfElement::Comment(n)),
+            ChildOfRoot::ProcessingInstruction(n) => Some(ChildOfElement::ProcessingInstruction(n)),
+        }
+    }
+
     pub fn wrap(node: raw::ChildOfRoot) -> ChildOfRoot<'d> {
         match node {
             raw::ChildOfRoot::Element(n) => ChildOfRoot::Element(Element::wrap(n)),
+            raw::ChildOfRoot::DocumentType(n) => ChildOfRoot::DocumentType(DocumentType::wrap(n)),
             raw::ChildOfRoot::Comment(n) => ChildOfRoot::Comment(Comment::wrap(n)),
             raw::ChildOfRoot::ProcessingInstruction(n) => {
                 ChildOfRoot::ProcessingInstruction(ProcessingInstruction::wrap(n))
@@ -479,6 +528,7 @@ impl<'d> ChildOfRoot<'d> {
     pub fn as_raw(&self) -> raw::ChildOfRoot {
         match *self {
             ChildOfRoot::Element(n) => raw::ChildOfRoot::Element(n.node),
+            ChildOfRoot::DocumentType(n) => raw::ChildOfRoot::DocumentType(n.node),
             ChildOfRoot::Comment(n) => raw::ChildOfRoot::Comment(n.node),
             ChildOfRoot::ProcessingInstruction(n) => {
                 raw::ChildOfRoot::ProcessingInstruction(n.node)
@@ -562,6 +612,7 @@ macro_rules! conversion_trait(
 conversion_trait!(
     ChildOfRoot, {
         Element               => ChildOfRoot::Element,
+        DocumentType          => ChildOfRoot::DocumentType,
         Comment               => ChildOfRoot::Comment,
         ProcessingInstruction => ChildOfRoot::ProcessingInstruction
     }
@@ -576,16 +627,6 @@ conversion_trait!(
     }
 );
 
-impl<'d> Into<ChildOfElement<'d>> for ChildOfRoot<'d> {
-    fn into(self) -> ChildOfElement<'d> {
-        match self {
-            ChildOfRoot::Element(n) => ChildOfElement::Element(n),
-            ChildOfRoot::Comment(n) => ChildOfElement::Comment(n),
-            ChildOfRoot::ProcessingInstruction(n) => ChildOfElement::ProcessingInstruction(n),
-        }
-    }
-}
-
 #[cfg(test)]
 mod test {
     use super::{
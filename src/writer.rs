@@ -25,12 +25,14 @@
 use std::{
     borrow::ToOwned,
     io::{self, Write},
-    slice,
+    slice, str,
 };
 
 use self::Content::*;
 
 use super::{
+    encoding::Encoding,
+    str::XmlChar,
     str_ext::{SplitKeepingDelimiterExt, SplitType},
     QName,
 };
@@ -49,6 +51,39 @@ trait WriteStr: Write {
 
 impl<W: ?Sized> WriteStr for W where W: Write {}
 
+/// Transcodes the UTF-8 produced by the writer into the encoding
+/// declared by the document.
+struct Encoder<'a, W: ?Sized> {
+    writer: &'a mut W,
+    encoding: Encoding,
+    bytes: Vec<u8>,
+}
+
+impl<'a, W: ?Sized> Write for Encoder<'a, W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text =
+            str::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        self.bytes.clear();
+        self.encoding.encode(text, &mut self.bytes).map_err(|c| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} cannot be written in {:?}", c, self.encoding),
+            )
+        })?;
+        self.writer.write_all(&self.bytes)?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
 // TODO: Duplicating the String seems inefficient...
 struct PrefixScope<'d> {
     ns_to_prefix: LazyHashMap<&'d str, String>,
@@ -273,6 +308,29 @@ impl<'d> PrefixMapping<'d> {
     }
 }
 
+/// The characters that can be written as they are. The rest are
+/// written as character references.
+#[derive(Debug, Copy, Clone)]
+struct Charset {
+    encoding: Encoding,
+    /// XML 1.1 only allows restricted characters as references, and
+    /// would read NEL and LS as line ends
+    xml_1_1: bool,
+}
+
+impl Charset {
+    fn can_write(self, c: char) -> bool {
+        // A carriage return would be read back as a line end
+        if c == '\r' {
+            return false;
+        }
+        if self.xml_1_1 && (c.is_restricted_char() || c == '\u{85}' || c == '\u{2028}') {
+            return false;
+        }
+        self.encoding.can_encode(c)
+    }
+}
+
 enum Content<'d> {
     Element(dom::Element<'d>),
     ElementEnd(dom::Element<'d>),
@@ -307,6 +365,7 @@ enum Content<'d> {
 pub struct Writer {
     single_quotes: bool,
     write_encoding: bool,
+    use_declared_encoding: bool,
 }
 
 impl Default for Writer {
@@ -314,6 +373,7 @@ impl Default for Writer {
         Self {
             single_quotes: true,
             write_encoding: false,
+            use_declared_encoding: false,
         }
     }
 }
@@ -336,6 +396,16 @@ impl Writer {
         self
     }
 
+    /// Set whether the output uses the encoding named by the
+    /// document's XML declaration. Characters in text and attribute
+    /// values that the encoding cannot represent are written as
+    /// character references, and an encoding that cannot be written
+    /// is an error. Defaults to `false`, which always writes UTF-8.
+    pub fn set_use_declared_encoding(mut self, use_declared_encoding: bool) -> Self {
+        self.use_declared_encoding = use_declared_encoding;
+        self
+    }
+
     fn quote_char(&self) -> &'static str {
         if self.single_quotes {
             "'"
@@ -376,13 +446,26 @@ impl Writer {
         writer.write_str(q.local_part)
     }
 
-    fn format_attribute_value<W: ?Sized>(&self, value: &str, writer: &mut W) -> io::Result<()>
+    fn format_attribute_value<W: ?Sized>(
+        &self,
+        value: &str,
+        charset: Charset,
+        writer: &mut W,
+    ) -> io::Result<()>
     where
         W: Write,
     {
-        for item in value
-            .split_keeping_delimiter(|c| c == '<' || c == '>' || c == '&' || c == '\'' || c == '"')
-        {
+        // Whitespace other than a space would be read back as a space
+        for item in value.split_keeping_delimiter(|c| {
+            c == '<'
+                || c == '>'
+                || c == '&'
+                || c == '\''
+                || c == '"'
+                || c == '\n'
+                || c == '\t'
+                || !charset.can_write(c)
+        }) {
             match item {
                 SplitType::Match(t) => writer.write_str(t)?,
                 SplitType::Delimiter("<") => writer.write_str("&lt;")?,
@@ -390,7 +473,7 @@ impl Writer {
                 SplitType::Delimiter("&") => writer.write_str("&amp;")?,
                 SplitType::Delimiter("'") => writer.write_str("&apos;")?,
                 SplitType::Delimiter("\"") => writer.write_str("&quot;")?,
-                SplitType::Delimiter(..) => unreachable!(),
+                SplitType::Delimiter(c) => format_character_references(c, writer)?,
             }
         }
         Ok(())
@@ -401,6 +484,7 @@ impl Writer {
         element: dom::Element<'d>,
         todo: &mut Vec<Content<'d>>,
         mapping: &mut PrefixMapping<'d>,
+        charset: Charset,
         writer: &mut W,
     ) -> io::Result<()>
     where
@@ -424,7 +508,7 @@ impl Writer {
             self.format_qname(attr.name(), mapping, attr.preferred_prefix(), true, writer)?;
             write!(writer, "=")?;
             write!(writer, "{}", self.quote_char())?;
-            self.format_attribute_value(attr.value(), writer)?;
+            self.format_attribute_value(attr.value(), charset, writer)?;
             write!(writer, "{}", self.quote_char())?;
         }
 
@@ -482,25 +566,62 @@ impl Writer {
         writer.write_str(">")
     }
 
-    fn format_text<W: ?Sized>(&self, text: dom::Text<'_>, writer: &mut W) -> io::Result<()>
+    fn format_text<W: ?Sized>(
+        &self,
+        text: dom::Text<'_>,
+        charset: Charset,
+        writer: &mut W,
+    ) -> io::Result<()>
     where
         W: Write,
     {
+        if text.is_cdata() {
+            return self.format_cdata(text, charset, writer);
+        }
+
         for item in text
             .text()
-            .split_keeping_delimiter(|c| c == '<' || c == '>' || c == '&')
+            .split_keeping_delimiter(|c| c == '<' || c == '>' || c == '&' || !charset.can_write(c))
         {
             match item {
                 SplitType::Match(t) => writer.write_str(t)?,
                 SplitType::Delimiter("<") => writer.write_str("&lt;")?,
                 SplitType::Delimiter(">") => writer.write_str("&gt;")?,
                 SplitType::Delimiter("&") => writer.write_str("&amp;")?,
-                SplitType::Delimiter(..) => unreachable!(),
+                SplitType::Delimiter(c) => format_character_references(c, writer)?,
             }
         }
         Ok(())
     }
 
+    /// Writes the text as a CDATA section. `]]>` would end the section
+    /// early, so it is split across two sections, and characters that
+    /// cannot be encoded are written as references between sections.
+    fn format_cdata<W: ?Sized + Write>(
+        &self,
+        text: dom::Text<'_>,
+        charset: Charset,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        writer.write_str("<![CDATA[")?;
+        for item in text
+            .text()
+            .split_keeping_delimiter(|c| !charset.can_write(c))
+        {
+            match item {
+                SplitType::Match(t) => {
+                    writer.write_str(&t.replace("]]>", "]]]]><![CDATA[>"))?;
+                }
+                SplitType::Delimiter(c) => {
+                    writer.write_str("]]>")?;
+                    format_character_references(c, writer)?;
+                    writer.write_str("<![CDATA[")?;
+                }
+            }
+        }
+        writer.write_str("]]>")
+    }
+
     fn format_comment<W: ?Sized>(&self, comment: dom::Comment<'_>, writer: &mut W) -> io::Result<()>
     where
         W: Write,
@@ -522,11 +643,63 @@ impl Writer {
         }
     }
 
+    fn format_literal<W: ?Sized + Write>(&self, literal: &str, writer: &mut W) -> io::Result<()> {
+        // Literals cannot be escaped, so pick a quote they do not contain
+        let quote = if !literal.contains(self.quote_char()) {
+            self.quote_char()
+        } else if !literal.contains('"') {
+            "\""
+        } else if !literal.contains('\'') {
+            "'"
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} contains both kinds of quotes", literal),
+            ));
+        };
+
+        write!(writer, " {}{}{}", quote, literal, quote)
+    }
+
+    fn format_document_type<W: ?Sized + Write>(
+        &self,
+        document_type: dom::DocumentType<'_>,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        write!(writer, "<!DOCTYPE {}", document_type.name())?;
+
+        match (document_type.public_id(), document_type.system_id()) {
+            (Some(public_id), Some(system_id)) => {
+                writer.write_str(" PUBLIC")?;
+                self.format_literal(public_id, writer)?;
+                self.format_literal(system_id, writer)?;
+            }
+            (None, Some(system_id)) => {
+                writer.write_str(" SYSTEM")?;
+                self.format_literal(system_id, writer)?;
+            }
+            (Some(_), None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "a public identifier requires a system identifier",
+                ));
+            }
+            (None, None) => {}
+        }
+
+        if let Some(internal_subset) = document_type.internal_subset() {
+            write!(writer, " [{}]", internal_subset)?;
+        }
+
+        writer.write_str(">")
+    }
+
     fn format_one<'d, W: ?Sized>(
         &self,
         content: Content<'d>,
         todo: &mut Vec<Content<'d>>,
         mapping: &mut PrefixMapping<'d>,
+        charset: Charset,
         writer: &mut W,
     ) -> io::Result<()>
     where
@@ -535,20 +708,25 @@ impl Writer {
         match content {
             Element(e) => {
                 mapping.push_scope();
-                self.format_element(e, todo, mapping, writer)
+                self.format_element(e, todo, mapping, charset, writer)
             }
             ElementEnd(e) => {
                 let r = self.format_element_end(e, mapping, writer);
                 mapping.pop_scope();
                 r
             }
-            Text(t) => self.format_text(t, writer),
+            Text(t) => self.format_text(t, charset, writer),
             Comment(c) => self.format_comment(c, writer),
             ProcessingInstruction(p) => self.format_processing_instruction(p, writer),
         }
     }
 
-    fn format_body<W: ?Sized>(&self, element: dom::Element<'_>, writer: &mut W) -> io::Result<()>
+    fn format_body<W: ?Sized>(
+        &self,
+        element: dom::Element<'_>,
+        charset: Charset,
+        writer: &mut W,
+    ) -> io::Result<()>
     where
         W: Write,
     {
@@ -556,28 +734,60 @@ impl Writer {
         let mut mapping = PrefixMapping::new();
 
         while !todo.is_empty() {
-            self.format_one(todo.pop().unwrap(), &mut todo, &mut mapping, writer)?;
+            self.format_one(
+                todo.pop().unwrap(),
+                &mut todo,
+                &mut mapping,
+                charset,
+                writer,
+            )?;
         }
 
         Ok(())
     }
 
-    fn format_declaration<W: ?Sized>(&self, writer: &mut W) -> io::Result<()>
+    fn format_declaration<W: ?Sized>(
+        &self,
+        doc: &dom::Document<'_>,
+        output: Encoding,
+        writer: &mut W,
+    ) -> io::Result<()>
     where
         W: Write,
     {
         write!(
             writer,
-            "<?xml version={}1.0{}",
+            "<?xml version={}{}{}",
             self.quote_char(),
+            doc.xml_version(),
             self.quote_char()
         )?;
 
-        if self.write_encoding {
+        // A declared encoding that the output is not in is replaced,
+        // so that the output can be read back
+        let encoding = match doc.xml_encoding() {
+            Some(name) if Encoding::from_name(name, output) == Some(output) => Some(name),
+            Some(_) => Some("UTF-8"),
+            None if self.write_encoding => Some("UTF-8"),
+            None => None,
+        };
+
+        if let Some(encoding) = encoding {
+            write!(
+                writer,
+                " encoding={}{}{}",
+                self.quote_char(),
+                encoding,
+                self.quote_char()
+            )?;
+        }
+
+        if let Some(standalone) = doc.xml_standalone() {
             write!(
                 writer,
-                " encoding={}UTF-8{}",
+                " standalone={}{}{}",
                 self.quote_char(),
+                if standalone { "yes" } else { "no" },
                 self.quote_char()
             )?;
         }
@@ -588,6 +798,10 @@ impl Writer {
     }
 
     /// Formats a document into a Write
+    ///
+    /// The output is UTF-8 unless `set_use_declared_encoding` is set.
+    /// The encoding in the XML declaration is replaced with UTF-8 if
+    /// it names another encoding.
     pub fn format_document<'d, W: ?Sized>(
         &self,
         doc: &'d dom::Document<'d>,
@@ -596,11 +810,48 @@ impl Writer {
     where
         W: Write,
     {
-        self.format_declaration(writer)?;
+        let encoding = match doc.xml_encoding() {
+            Some(name) if self.use_declared_encoding => Encoding::from_name(name, Encoding::Utf8)
+                .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unsupported encoding {}", name),
+                )
+            })?,
+            _ => Encoding::Utf8,
+        };
+
+        if encoding == Encoding::Utf8 {
+            self.format_document_in(doc, encoding, writer)
+        } else {
+            writer.write_all(encoding.byte_order_mark())?;
+
+            let mut writer = Encoder {
+                writer,
+                encoding,
+                bytes: Vec::new(),
+            };
+            self.format_document_in(doc, encoding, &mut writer)
+        }
+    }
+
+    fn format_document_in<'d, W: ?Sized + Write>(
+        &self,
+        doc: &'d dom::Document<'d>,
+        encoding: Encoding,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        self.format_declaration(doc, encoding, writer)?;
+
+        let charset = Charset {
+            encoding,
+            xml_1_1: doc.xml_version() == "1.1",
+        };
 
         for child in doc.root().children().into_iter() {
             match child {
-                ChildOfRoot::Element(e) => self.format_body(e, writer),
+                ChildOfRoot::Element(e) => self.format_body(e, charset, writer),
+                ChildOfRoot::DocumentType(d) => self.format_document_type(d, writer),
                 ChildOfRoot::Comment(c) => self.format_comment(c, writer),
                 ChildOfRoot::ProcessingInstruction(p) => {
                     self.format_processing_instruction(p, writer)
@@ -612,6 +863,13 @@ impl Writer {
     }
 }
 
+fn format_character_references<W: ?Sized + Write>(chars: &str, writer: &mut W) -> io::Result<()> {
+    for c in chars.chars() {
+        write!(writer, "&#x{:X};", c as u32)?;
+    }
+    Ok(())
+}
+
 /// Formats a document into a `Write` using the default `Writer`
 pub fn format_document<'d, W: ?Sized>(doc: &'d dom::Document<'d>, writer: &mut W) -> io::Result<()>
 where
@@ -622,6 +880,8 @@ where
 
 #[cfg(test)]
 mod test {
+    use std::io;
+
     use super::{
         super::{dom, Package},
         Writer,
@@ -922,6 +1182,59 @@ mod test {
         );
     }
 
+    #[test]
+    fn cdata_text_is_not_escaped() {
+        let p = Package::new();
+        let d = p.as_document();
+        let script = d.create_element("script");
+        let text = d.create_text("if (a < b && c) {}");
+        text.set_cdata(true);
+        script.append_child(text);
+        d.root().append_child(script);
+
+        let xml = format_xml(&d);
+        assert_eq!(
+            xml,
+            "<?xml version='1.0'?><script><![CDATA[if (a < b && c) {}]]></script>"
+        );
+    }
+
+    #[test]
+    fn cdata_text_splits_the_end_marker() {
+        let p = Package::new();
+        let d = p.as_document();
+        let hello = d.create_element("hello");
+        let text = d.create_text("a]]>b");
+        text.set_cdata(true);
+        hello.append_child(text);
+        d.root().append_child(hello);
+
+        let xml = format_xml(&d);
+        assert_eq!(
+            xml,
+            "<?xml version='1.0'?><hello><![CDATA[a]]]]><![CDATA[>b]]></hello>"
+        );
+    }
+
+    #[test]
+    fn cdata_text_references_characters_outside_the_encoding() {
+        let p = Package::new();
+        let d = p.as_document();
+        let hello = d.create_element("hello");
+        let text = d.create_text("a\u{20AC}b");
+        text.set_cdata(true);
+        hello.append_child(text);
+        d.root().append_child(hello);
+        d.set_xml_encoding(Some("US-ASCII"));
+
+        let xml = format_xml_writer(Writer::new().set_use_declared_encoding(true), &d);
+        assert_eq!(
+            xml,
+            "<?xml version='1.0' encoding='US-ASCII'?>\
+             <hello><![CDATA[a]]>&#x20AC;<![CDATA[b]]></hello>"
+        );
+    }
+
     #[test]
     fn nested_comment() {
         let p = Package::new();
@@ -1015,4 +1328,249 @@ mod test {
         );
         assert_eq!(xml, r#"<?xml version="1.0" encoding="UTF-8"?><hello/>"#);
     }
+
+    #[test]
+    fn document_type_with_a_system_id() {
+        let p = Package::new();
+        let d = p.as_document();
+        let doctype = d.create_document_type("hello", None, Some("hello.dtd"));
+        doctype.set_internal_subset(Some("<!ELEMENT hello EMPTY>"));
+        d.root().append_child(doctype);
+        d.root().append_child(d.create_element("hello"));
+
+        let xml = format_xml(&d);
+        assert_eq!(
+            xml,
+            "<?xml version='1.0'?><!DOCTYPE hello SYSTEM 'hello.dtd' [<!ELEMENT hello EMPTY>]><hello/>"
+        );
+    }
+
+    #[test]
+    fn document_type_with_a_public_id() {
+        let p = Package::new();
+        let d = p.as_document();
+        let doctype = d.create_document_type(
+            "html",
+            Some("-//W3C//DTD XHTML 1.0 Strict//EN"),
+            Some("xhtml1-strict.dtd"),
+        );
+        d.root().append_child(doctype);
+        d.root().append_child(d.create_element("html"));
+
+        let xml = format_xml_writer(Writer::new().set_single_quotes(false), &d);
+        assert_eq!(
+            xml,
+            r#"<?xml version="1.0"?><!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Strict//EN" "xhtml1-strict.dtd"><html/>"#
+        );
+    }
+
+    #[test]
+    fn document_type_literals_use_the_other_quote_when_needed() {
+        let p = Package::new();
+        let d = p.as_document();
+        let doctype = d.create_document_type("a", Some("it's"), Some("a'b.dtd"));
+        d.root().append_child(doctype);
+
+        let xml = format_xml(&d);
+        assert_eq!(
+            xml,
+            r#"<?xml version='1.0'?><!DOCTYPE a PUBLIC "it's" "a'b.dtd">"#
+        );
+    }
+
+    #[test]
+    fn document_type_with_a_public_id_requires_a_system_id() {
+        let p = Package::new();
+        let d = p.as_document();
+        let doctype = d.create_document_type("a", Some("pub"), None);
+        d.root().append_child(doctype);
+
+        let mut w = Vec::new();
+        let err = Writer::new()
+            .set_use_declared_encoding(true)
+            .format_document(&d, &mut w)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn declaration_from_the_document() {
+        let p = Package::new();
+        let d = p.as_document();
+        let e = d.create_element("hello");
+        d.root().append_child(e);
+        d.set_xml_version("1.1");
+        d.set_xml_encoding(Some("UTF-8"));
+        d.set_xml_standalone(Some(true));
+
+        let xml = format_xml(&d);
+        assert_eq!(
+            xml,
+            "<?xml version='1.1' encoding='UTF-8' standalone='yes'?><hello/>"
+        );
+    }
+
+    #[test]
+    fn attribute_whitespace_other_than_spaces_is_escaped() {
+        let p = Package::new();
+        let d = p.as_document();
+        let e = d.create_element("hello");
+        e.set_attribute_value("a", "1 2\t3\n4\r5");
+        d.root().append_child(e);
+
+        let xml = format_xml(&d);
+        assert_eq!(
+            xml,
+            "<?xml version='1.0'?><hello a='1 2&#x9;3&#xA;4&#xD;5'/>"
+        );
+    }
+
+    #[test]
+    fn text_carriage_returns_are_escaped() {
+        let p = Package::new();
+        let d = p.as_document();
+        let e = d.create_element("hello");
+        e.append_child(d.create_text("a\r\nb\tc"));
+        d.root().append_child(e);
+
+        let xml = format_xml(&d);
+        assert_eq!(xml, "<?xml version='1.0'?><hello>a&#xD;\nb\tc</hello>");
+    }
+
+    #[test]
+    fn xml_1_1_restricted_characters_and_line_ends_are_references() {
+        let p = Package::new();
+        let d = p.as_document();
+        let e = d.create_element("hello");
+        e.set_attribute_value("a", "\u{1}\u{85}");
+        e.append_child(d.create_text("\u{7F}\u{2028}\n"));
+        d.root().append_child(e);
+        d.set_xml_version("1.1");
+
+        let xml = format_xml(&d);
+        assert_eq!(
+            xml,
+            "<?xml version='1.1'?><hello a='&#x1;&#x85;'>&#x7F;&#x2028;\n</hello>"
+        );
+    }
+
+    #[test]
+    fn xml_1_0_writes_nel_and_ls_as_they_are() {
+        let p = Package::new();
+        let d = p.as_document();
+        let e = d.create_element("hello");
+        e.append_child(d.create_text("\u{85}\u{2028}"));
+        d.root().append_child(e);
+
+        let xml = format_xml(&d);
+        assert_eq!(xml, "<?xml version='1.0'?><hello>\u{85}\u{2028}</hello>");
+    }
+
+    #[test]
+    fn declared_encoding_takes_precedence_over_utf_8() {
+        let p = Package::new();
+        let d = p.as_document();
+        let e = d.create_element("hello");
+        d.root().append_child(e);
+        d.set_xml_encoding(Some("US-ASCII"));
+
+        let writer = Writer::new()
+            .set_write_encoding(true)
+            .set_use_declared_encoding(true);
+        let xml = format_xml_writer(writer, &d);
+        assert_eq!(xml, "<?xml version='1.0' encoding='US-ASCII'?><hello/>");
+    }
+
+    #[test]
+    fn output_is_utf_8_by_default() {
+        let package = crate::parser::parse(
+            "<?xml version='1.0' encoding='windows-1252'?><hello>\u{e9}</hello>",
+        )
+        .unwrap();
+
+        let xml = format_xml(&package.as_document());
+        assert_eq!(
+            xml,
+            "<?xml version='1.0' encoding='UTF-8'?><hello>\u{e9}</hello>"
+        );
+    }
+
+    #[test]
+    fn declared_utf_8_is_kept_as_written() {
+        let p = Package::new();
+        let d = p.as_document();
+        d.root().append_child(d.create_element("hello"));
+        d.set_xml_encoding(Some("utf-8"));
+
+        let xml = format_xml(&d);
+        assert_eq!(xml, "<?xml version='1.0' encoding='utf-8'?><hello/>");
+    }
+
+    #[test]
+    fn output_uses_the_declared_encoding() {
+        let p = Package::new();
+        let d = p.as_document();
+        let e = d.create_element("hello");
+        e.set_attribute_value("a", "\u{e9}\u{20AC}");
+        e.append_child(d.create_text("\u{e9}\u{20AC}"));
+        d.root().append_child(e);
+        d.set_xml_encoding(Some("ISO-8859-1"));
+
+        let mut w = Vec::new();
+        Writer::new()
+            .set_use_declared_encoding(true)
+            .format_document(&d, &mut w)
+            .unwrap();
+        assert_eq!(
+            &w[..],
+            &b"<?xml version='1.0' encoding='ISO-8859-1'?><hello a='\xE9&#x20AC;'>\xE9&#x20AC;</hello>"[..]
+        );
+    }
+
+    #[test]
+    fn output_in_utf_16_starts_with_a_byte_order_mark() {
+        let p = Package::new();
+        let d = p.as_document();
+        d.root().append_child(d.create_element("a"));
+        d.set_xml_encoding(Some("UTF-16LE"));
+
+        let mut w = Vec::new();
+        Writer::new()
+            .set_use_declared_encoding(true)
+            .format_document(&d, &mut w)
+            .unwrap();
+        assert_eq!(&w[..2], b"\xFF\xFE");
+        assert_eq!(&w[w.len() - 4..], b"/\x00>\x00");
+    }
+
+    #[test]
+    fn characters_outside_the_encoding_cannot_be_written_in_names() {
+        let p = Package::new();
+        let d = p.as_document();
+        d.root().append_child(d.create_element("caf\u{e9}"));
+        d.set_xml_encoding(Some("US-ASCII"));
+
+        let mut w = Vec::new();
+        let err = Writer::new()
+            .set_use_declared_encoding(true)
+            .format_document(&d, &mut w)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn unsupported_encoding() {
+        let p = Package::new();
+        let d = p.as_document();
+        d.root().append_child(d.create_element("a"));
+        d.set_xml_encoding(Some("Shift_JIS"));
+
+        let mut w = Vec::new();
+        let err = Writer::new()
+            .set_use_declared_encoding(true)
+            .format_document(&d, &mut w)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(w.is_empty());
+    }
 }